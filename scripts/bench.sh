@@ -0,0 +1,34 @@
+#!/bin/sh
+# Time whole invocations of the release binary, startup included, as the bar runs it.
+#
+#   scripts/bench.sh [runs] [widget args...]
+#
+# Defaults to 500 runs of `hostname` in every output format. Prints the mean wall time per
+# run of each format next to the same loop running /bin/true, which is the process spawn cost.
+set -eu
+
+runs=${1:-500}
+[ $# -gt 0 ] && shift
+[ $# -gt 0 ] || set -- hostname
+
+cd "$(dirname "$0")/.."
+cargo build --release --quiet
+bin=target/release/i3widgets
+# keep the user's config out of it
+export XDG_CONFIG_HOME="${TMPDIR:-/tmp}/i3widgets-bench"
+
+per_run() {
+    start=$(date +%s%N)
+    i=0
+    while [ "$i" -lt "$runs" ]; do
+        "$@" >/dev/null
+        i=$((i + 1))
+    done
+    end=$(date +%s%N)
+    echo "$(((end - start) / runs / 1000))"
+}
+
+printf '%-10s %s\n' baseline "$(per_run /bin/true)us"
+for format in json pango i3bar ansi; do
+    printf '%-10s %s\n' "$format" "$(per_run "$bin" --output "$format" "$@")us"
+done
@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+
+use crate::{json, process::CommandSource, Span, Style, Widget, TABULAR_NUMS};
+
+pub struct Card {
+    pub index: u32,
+    /// The long name in brackets, e.g. `HDA Intel PCH`
+    pub name: String,
+    /// Names of the playback devices on the card
+    pub devices: Vec<String>,
+}
+
+pub struct SoundCards {
+    pub cards: Vec<Card>,
+    pub show_names: bool,
+}
+
+impl Widget for SoundCards {
+    fn render(&self, style: &Style) -> String {
+        let mut text = format!(
+            "{icon} {count}",
+            icon = style.icon("sound_cards", "sound_card"),
+            count = pango!(
+                self.cards.len(),
+                color = style.color("sound_cards.count", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+        );
+        if self.show_names && !self.cards.is_empty() {
+            let names = self
+                .cards
+                .iter()
+                .map(|card| card.name.as_str())
+                .collect::<Vec<_>>();
+            text += &pango!(
+                format!(" {}", names.join(", ")),
+                color = style.color("sound_cards.names", style.palette().secondary)
+            );
+        }
+        text
+    }
+
+    fn to_json(&self) -> json::Object {
+        let cards = self
+            .cards
+            .iter()
+            .map(|card| {
+                json::Object::new()
+                    .field("index", card.index)
+                    .field("name", &card.name)
+                    .field("devices", &card.devices)
+            })
+            .collect::<Vec<_>>();
+        json::Object::new().field("cards", cards)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.cards.len().to_string())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cards.is_empty()
+    }
+}
+
+pub fn sound_cards(show_names: bool, source: &dyn CommandSource) -> Result<SoundCards> {
+    let cards = parse(&source.output("aplay", &["-l"])?)?;
+    Ok(SoundCards { cards, show_names })
+}
+
+/// `HDA Intel PCH` out of `PCH [HDA Intel PCH]`, falling back to the whole text
+fn bracketed(text: &str) -> &str {
+    text.split_once('[')
+        .and_then(|(_, rest)| rest.rsplit_once(']'))
+        .map_or(text.trim(), |(name, _)| name)
+}
+
+/// Parse the output of `aplay -l`, which has a line per device like
+/// `card 0: PCH [HDA Intel PCH], device 3: HDMI 0 [HDMI 0]`
+pub fn parse(output: &str) -> Result<Vec<Card>> {
+    let mut cards: Vec<Card> = Vec::new();
+    for line in output.lines() {
+        let Some(rest) = line.strip_prefix("card ") else {
+            continue;
+        };
+        let invalid = || anyhow!("invalid `aplay` line `{}`", line);
+        let (index, rest) = rest.split_once(": ").ok_or_else(invalid)?;
+        let index = index.parse::<u32>().map_err(|_| invalid())?;
+        let (card, device) = rest.split_once(", device ").ok_or_else(invalid)?;
+        let device = device.split_once(": ").map_or(device, |(_, name)| name);
+        let device = bracketed(device).to_string();
+        match cards.iter_mut().find(|card| card.index == index) {
+            Some(card) => card.devices.push(device),
+            None => cards.push(Card {
+                index,
+                name: bracketed(card).to_string(),
+                devices: vec![device],
+            }),
+        }
+    }
+    Ok(cards)
+}
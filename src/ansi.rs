@@ -0,0 +1,64 @@
+use std::str::FromStr;
+
+use crate::{color::Color, polybar};
+
+const RESET: &str = "\x1b[0m";
+
+/// The SGR parameters selecting `color`, e.g. `38;2;255;0;0` for a red foreground
+fn sgr(layer: u8, color: &str) -> Option<String> {
+    let Color(r, g, b) = Color::from_str(color).ok()?;
+    Some(format!("{};2;{};{};{}", layer, r, g, b))
+}
+
+/// The markup with its colors and bold text as escape sequences. Every styled run ends in a
+/// reset, so nothing leaks into whatever is printed after
+pub fn from_pango(markup: &str) -> String {
+    let mut out = String::new();
+    for span in polybar::spans(markup) {
+        let params = [
+            span.bold.then(|| "1".to_string()),
+            span.color.as_deref().and_then(|color| sgr(38, color)),
+            span.background.as_deref().and_then(|color| sgr(48, color)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if params.is_empty() {
+            out.push_str(&span.text);
+        } else {
+            out.push_str(&format!("\x1b[{}m{}{}", params.join(";"), span.text, RESET));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Span;
+
+    #[test]
+    fn unescapes_the_text() {
+        let name = r#"<Tom & Jerry's "VM">"#;
+        let markup = Span::new(name).color("#ff0000").to_string();
+        assert_eq!(
+            from_pango(&markup),
+            format!("\x1b[38;2;255;0;0m{}{}", name, RESET)
+        );
+        // an escaped entity in the source text survives a single unescape
+        assert_eq!(from_pango(&Span::new("&lt;").to_string()), "&lt;");
+    }
+
+    #[test]
+    fn resets_after_every_styled_run() {
+        let markup = format!(
+            "{} {}",
+            Span::new("74").color("#c0caf5").weight(crate::Weight::Bold),
+            Span::new("%").color("#a9b1d6")
+        );
+        assert_eq!(
+            from_pango(&markup),
+            "\x1b[1;38;2;192;202;245m74\x1b[0m \x1b[38;2;169;177;214m%\x1b[0m"
+        );
+    }
+}
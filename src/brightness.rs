@@ -0,0 +1,207 @@
+use std::{fs::File, io::Read, path::Path, thread::JoinHandle};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, process::CommandSource, Span, Style, Widget, TABULAR_NUMS};
+
+pub struct BrightnessInfo {
+    pub current: u64,
+    pub max: u64,
+}
+
+impl BrightnessInfo {
+    /// Clamped to 100, `current` can briefly read above a stale `max` on some hardware
+    pub fn pct(&self) -> u64 {
+        (self.current * 100)
+            .checked_div(self.max)
+            .unwrap_or(0)
+            .min(100)
+    }
+
+    /// Name of the icon in [`Icons`](crate::Icons)
+    pub fn icon_name(&self) -> &'static str {
+        match self.pct() {
+            0 => "brightness_0",
+            1..=33 => "brightness_1",
+            34..=66 => "brightness_2",
+            67..=99 => "brightness_3",
+            _ => "brightness_4",
+        }
+    }
+}
+
+impl Widget for BrightnessInfo {
+    fn render(&self, style: &Style) -> String {
+        format!(
+            "{icon} {value}{pct}",
+            icon = style.icon("brightness", self.icon_name()),
+            value = pango!(
+                self.pct(),
+                color = style.color("brightness.pct", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            pct = style.unit("brightness", "%"),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("current", self.current)
+            .field("max", self.max)
+            .field("brightness_pct", self.pct())
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(format!("{}{}", self.pct(), style.unit("brightness", "%")))
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        Some(("brightness", self.icon_name()))
+    }
+}
+
+pub fn info(source: &dyn CommandSource) -> Result<BrightnessInfo> {
+    parse(&source.output("brightnessctl", &["info"])?)
+}
+
+/// The names of the devices under `/sys/class/backlight`, sorted
+pub fn devices() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/backlight") else {
+        return Vec::new();
+    };
+    let mut devices = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    devices.sort();
+    devices
+}
+
+/// The brightness of `device` straight from `/sys/class/backlight`, without
+/// `brightnessctl`
+pub fn read_sysfs(device: &str) -> Result<BrightnessInfo> {
+    let dir = Path::new("/sys/class/backlight").join(device);
+    let read = |name: &str| -> Result<u64> {
+        let path = dir.join(name);
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?
+            .trim()
+            .parse()
+            .with_context(|| format!("parsing {}", path.display()))
+    };
+    Ok(BrightnessInfo {
+        current: read("brightness")?,
+        max: read("max_brightness")?.max(1),
+    })
+}
+
+/// Call `cb` with the new brightness every time the `brightness` file of `device` changes,
+/// on a background thread that runs until the watch breaks. Setting up the watch happens
+/// before returning, so a missing device or a lack of inotify is an error here.
+pub fn watch(device: &str, cb: impl Fn(BrightnessInfo) + Send + 'static) -> Result<JoinHandle<()>> {
+    let path = Path::new("/sys/class/backlight")
+        .join(device)
+        .join("brightness");
+    // SAFETY: plain syscall, the fd is closed through the File below
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error()).context("creating an inotify instance");
+    }
+    // SAFETY: fd is a fresh descriptor owned by nothing else
+    let mut inotify = unsafe { <File as std::os::fd::FromRawFd>::from_raw_fd(fd) };
+    let c_path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes())
+        .map_err(|_| anyhow!("invalid backlight device `{}`", device))?;
+    // SAFETY: c_path is a valid C string for the duration of the call
+    if unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), libc::IN_MODIFY) } < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("watching {}", path.display()));
+    }
+    let device = device.to_string();
+    Ok(std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while matches!(inotify.read(&mut buf), Ok(n) if n > 0) {
+            if let Ok(info) = read_sysfs(&device) {
+                cb(info);
+            }
+        }
+    }))
+}
+
+/// Parse the output of `brightnessctl info`
+pub fn parse(output: &str) -> Result<BrightnessInfo> {
+    // Current brightness: 19200 (40%)
+    let value = |label: &str| -> Result<u64> {
+        let line = output
+            .lines()
+            .find(|line| line.trim_start().starts_with(label))
+            .ok_or_else(|| anyhow!("`brightnessctl` output has no `{}` line", label))?;
+        line.split_whitespace()
+            .nth(2)
+            .ok_or_else(|| anyhow!("`brightnessctl` line is invalid: {}", line.trim()))?
+            .parse::<u64>()
+            .with_context(|| format!("parsing `brightnessctl` line: {}", line.trim()))
+    };
+    Ok(BrightnessInfo {
+        current: value("Current brightness:")?,
+        // at least 1 to avoid dividing by 0
+        max: value("Max brightness:")?.max(1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_brightnessctl_info() {
+        let info = parse(include_str!("../tests/fixtures/brightnessctl-info.txt")).unwrap();
+        assert_eq!((info.current, info.max, info.pct()), (19200, 48000, 40));
+    }
+
+    #[test]
+    fn clamps_the_percentage() {
+        // (current, max, pct, icon)
+        let cases = [
+            (0, 255, 0, "brightness_0"),
+            (84, 255, 32, "brightness_1"),
+            (254, 255, 99, "brightness_3"),
+            (255, 255, 100, "brightness_4"),
+            (263, 255, 100, "brightness_4"),
+            (u32::MAX as u64, 1, 100, "brightness_4"),
+            // a zero max reads as off rather than dividing by zero
+            (120, 0, 0, "brightness_0"),
+            (0, 0, 0, "brightness_0"),
+        ];
+        for (current, max, pct, icon) in cases {
+            let info = BrightnessInfo { current, max };
+            assert_eq!(
+                (info.pct(), info.icon_name()),
+                (pct, icon),
+                "{}/{}",
+                current,
+                max
+            );
+        }
+        let over = BrightnessInfo {
+            current: 263,
+            max: 255,
+        };
+        assert!(over
+            .to_json()
+            .to_string()
+            .contains("\"brightness_pct\":100"));
+    }
+
+    #[test]
+    fn rejects_broken_brightnessctl_output() {
+        for output in [
+            "",
+            "\tCurrent brightness: 19200 (40%)\n",
+            "\tCurrent brightness:\n\tMax brightness: 48000\n",
+            "\tCurrent brightness: lots\n\tMax brightness: 48000\n",
+        ] {
+            assert!(parse(output).is_err(), "{:?}", output);
+        }
+    }
+}
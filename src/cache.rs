@@ -0,0 +1,250 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use crate::{process::CommandSource, state};
+
+pub fn dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("i3widgets/cache"),
+        // SAFETY: getuid can't fail
+        _ => std::env::temp_dir().join(format!("i3widgets-{}/cache", unsafe { libc::getuid() })),
+    }
+}
+
+/// File name for the output of `program` run with `args`. The hash is 64-bit FNV-1a
+/// rather than std's, which may change between Rust releases and split the cache shared
+/// by binaries built with different toolchains
+pub fn key(program: &str, args: &[&str]) -> String {
+    // arguments can't contain a nul, so it marks where each one starts
+    let bytes = program.as_bytes().iter().chain(
+        args.iter()
+            .flat_map(|arg| std::iter::once(&0).chain(arg.as_bytes())),
+    );
+    let hash = bytes.fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{}-{:016x}", program, hash)
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// The output stored in `entry` if it was fetched less than `ttl` before `now`. Entries
+/// are the fetch time in milliseconds on the first line followed by the output
+pub fn parse_entry(entry: &str, now: u128, ttl: Duration) -> Option<&str> {
+    let (fetched, output) = entry.split_once('\n')?;
+    let age = now.checked_sub(fetched.parse().ok()?)?;
+    (age < ttl.as_millis()).then_some(output)
+}
+
+/// Answers from the cache while it is fresh and asks `inner` otherwise. Concurrent runs
+/// wait on a per-entry lock so only the first one runs the tool
+pub struct CachedSource<'a> {
+    pub inner: &'a dyn CommandSource,
+    pub ttl: Duration,
+}
+
+impl CachedSource<'_> {
+    /// The cached output of `program` run with `args`, from `fetch` on a miss
+    fn cached(
+        &self,
+        program: &str,
+        args: &[&str],
+        fetch: impl FnOnce() -> Result<String>,
+    ) -> Result<String> {
+        let dir = dir();
+        let key = key(program, args);
+        // a cache that can't be used shouldn't break the widget, it just isn't shared
+        let Ok(_lock) = std::fs::create_dir_all(&dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| state::lock(&dir.join(format!("{}.lock", key))))
+        else {
+            return fetch();
+        };
+        let path = dir.join(&key);
+        // anything unreadable or stale is just a miss
+        if let Ok(entry) = std::fs::read_to_string(&path) {
+            if let Some(output) = parse_entry(&entry, now_millis(), self.ttl) {
+                log!(Debug, "cache", "hit for `{} {}`", program, args.join(" "));
+                return Ok(output.to_string());
+            }
+        }
+        let output = fetch()?;
+        let tmp = dir.join(format!(".{}.{}", key, std::process::id()));
+        let stored = std::fs::write(&tmp, format!("{}\n{}", now_millis(), output))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        if stored.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        Ok(output)
+    }
+}
+
+impl CommandSource for CachedSource<'_> {
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.cached(program, args, || self.inner.output(program, args))
+    }
+
+    fn output_within(&self, program: &str, args: &[&str], timeout: Duration) -> Result<String> {
+        self.cached(program, args, || {
+            self.inner.output_within(program, args, timeout)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Counts the runs, each taking long enough for a concurrent one to overlap it
+    struct Slow {
+        runs: AtomicUsize,
+    }
+
+    impl CommandSource for Slow {
+        fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+            let run = self.runs.fetch_add(1, Ordering::SeqCst) + 1;
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(format!("{} {} #{}\n", program, args.join(" "), run))
+        }
+
+        fn output_within(&self, program: &str, args: &[&str], timeout: Duration) -> Result<String> {
+            let output = self.output(program, args)?;
+            Ok(format!("{}within {:?}\n", output, timeout))
+        }
+    }
+
+    #[test]
+    fn keys_by_program_and_args() {
+        let key = key(
+            "upower",
+            &["-i", "/org/freedesktop/UPower/devices/battery_BAT0"],
+        );
+        // pinned, so a change that would orphan every cached entry shows up here
+        assert_eq!(key, "upower-78016447cb53ce9e");
+        assert_eq!(
+            key,
+            super::key(
+                "upower",
+                &["-i", "/org/freedesktop/UPower/devices/battery_BAT0"]
+            )
+        );
+        for (program, args) in [
+            (
+                "upower",
+                &["-i", "/org/freedesktop/UPower/devices/battery_BAT1"][..],
+            ),
+            ("upower", &["-i"]),
+            ("upower", &[]),
+            // the split between the words matters, not just the words
+            (
+                "upower -i",
+                &["/org/freedesktop/UPower/devices/battery_BAT0"],
+            ),
+        ] {
+            assert_ne!(super::key(program, args), key, "{} {:?}", program, args);
+        }
+    }
+
+    #[test]
+    fn entries_are_fresh_for_the_ttl() {
+        let ttl = Duration::from_millis(500);
+        let entry = "1000\nline one\nline two\n";
+        assert_eq!(parse_entry(entry, 1000, ttl), Some("line one\nline two\n"));
+        assert_eq!(parse_entry(entry, 1499, ttl), Some("line one\nline two\n"));
+        assert_eq!(parse_entry(entry, 1500, ttl), None);
+        // written by a clock that is ahead, so its age is unknown
+        assert_eq!(parse_entry(entry, 999, ttl), None);
+        for corrupt in ["", "1000", "soon\noutput", "-5\noutput", "\noutput"] {
+            assert_eq!(parse_entry(corrupt, 1000, ttl), None, "{:?}", corrupt);
+        }
+    }
+
+    #[test]
+    fn concurrent_runs_share_one_fetch() {
+        let _env = crate::snapshots::test_env();
+        let slow = Slow {
+            runs: AtomicUsize::new(0),
+        };
+        let outputs = std::thread::scope(|scope| {
+            let threads = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let cached = CachedSource {
+                            inner: &slow,
+                            ttl: Duration::from_secs(60),
+                        };
+                        cached.output("upower", &["--concurrent"]).unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(slow.runs.load(Ordering::SeqCst), 1);
+        assert_eq!(outputs, ["upower --concurrent #1\n"; 2]);
+    }
+
+    #[test]
+    fn a_corrupt_entry_is_a_miss() {
+        let _env = crate::snapshots::test_env();
+        let slow = Slow {
+            runs: AtomicUsize::new(0),
+        };
+        let cached = CachedSource {
+            inner: &slow,
+            ttl: Duration::from_secs(60),
+        };
+        let path = dir().join(key("pactl", &["--corrupt"]));
+        std::fs::create_dir_all(dir()).unwrap();
+        std::fs::write(&path, [0xff, 0xfe, b'\n']).unwrap();
+        assert_eq!(
+            cached.output("pactl", &["--corrupt"]).unwrap(),
+            "pactl --corrupt #1\n"
+        );
+        // and is replaced by the fresh output
+        assert_eq!(
+            cached.output("pactl", &["--corrupt"]).unwrap(),
+            "pactl --corrupt #1\n"
+        );
+        assert_eq!(slow.runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn a_miss_keeps_the_timeout() {
+        let _env = crate::snapshots::test_env();
+        let slow = Slow {
+            runs: AtomicUsize::new(0),
+        };
+        let cached = CachedSource {
+            inner: &slow,
+            ttl: Duration::from_secs(60),
+        };
+        let timeout = Duration::from_millis(300);
+        let args = ["list", "--within"];
+        let _ = std::fs::remove_file(dir().join(key("virsh", &args)));
+        let output = "virsh list --within #1\nwithin 300ms\n";
+        assert_eq!(
+            cached.output_within("virsh", &args, timeout).unwrap(),
+            output
+        );
+        // and the hit is the same output
+        assert_eq!(
+            cached.output_within("virsh", &args, timeout).unwrap(),
+            output
+        );
+        assert_eq!(slow.runs.load(Ordering::SeqCst), 1);
+    }
+}
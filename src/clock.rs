@@ -0,0 +1,351 @@
+use std::ffi::CStr;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, Local, Timelike,
+};
+
+use crate::{json, pango_escape, Era, Span, Style, Widget, TABULAR_NUMS};
+
+/// 2026 => 115, years before 1912 come out as 0 or less
+pub fn gregorian_to_minguo(year: i32) -> i32 {
+    year - 1911
+}
+
+/// 2026 => 2569
+pub fn gregorian_to_buddhist(year: i32) -> i32 {
+    year + 543
+}
+
+fn to_json(now: &DateTime<Local>) -> json::Object {
+    json::Object::new()
+        .field("local_time", now.to_rfc3339())
+        .field("unix_secs", now.timestamp())
+}
+
+/// The `strftime` formats of the `LC_TIME` locale, from `nl_langinfo`
+pub struct LocaleFormats {
+    pub date: String,
+    pub time: String,
+}
+
+impl LocaleFormats {
+    /// `None` if the locale named by the environment isn't installed
+    pub fn get() -> Option<Self> {
+        // SAFETY: the widgets are fetched on the main thread and nothing else reads the
+        // locale, and `nl_langinfo` returns a valid string until the next `setlocale`
+        unsafe {
+            if libc::setlocale(libc::LC_TIME, c"".as_ptr()).is_null() {
+                return None;
+            }
+            let read = |item| {
+                CStr::from_ptr(libc::nl_langinfo(item))
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            Some(Self {
+                date: read(libc::D_FMT),
+                time: read(libc::T_FMT),
+            })
+        }
+    }
+}
+
+/// Turn a `strftime` format into one chrono renders the same, `None` if chrono can't.
+/// Drops the `E` and `O` modifiers for alternative eras and digits, which chrono lacks, and
+/// the seconds along with their separator unless `seconds`.
+pub fn chrono_format(strftime: &str, seconds: bool) -> Option<String> {
+    let mut format = String::new();
+    let mut chars = strftime.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            format.push(c);
+            continue;
+        }
+        let mut spec = chars.next()?;
+        if spec == 'E' || spec == 'O' {
+            spec = chars.next()?;
+        }
+        match (spec, seconds) {
+            ('T', true) => format.push_str("%H:%M:%S"),
+            ('T', false) => format.push_str("%H:%M"),
+            ('r', true) => format.push_str("%I:%M:%S %p"),
+            ('r', false) => format.push_str("%I:%M %p"),
+            ('S', false) => {
+                if format.ends_with([':', '.']) {
+                    format.pop();
+                }
+            }
+            (spec, _) => {
+                format.push('%');
+                format.push(spec);
+            }
+        }
+    }
+    (!StrftimeItems::new(&format).any(|item| item == Item::Error)).then_some(format)
+}
+
+/// `HH:MM:SS.mmm`, with a leap second shown as `.999` rather than overflowing into `.1xxx`
+pub fn format_millis<T: Timelike>(time: &T) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        time.hour(),
+        time.minute(),
+        time.second(),
+        (time.nanosecond() / 1_000_000).min(999)
+    )
+}
+
+pub struct Time {
+    pub now: DateTime<Local>,
+    pub seconds: bool,
+    /// Seconds with milliseconds, from `--millis`
+    pub millis: bool,
+    pub date: bool,
+    /// Render with these instead of the fixed formats, from `--locale-time`
+    pub locale: Option<LocaleFormats>,
+}
+
+impl Time {
+    fn render_locale(&self, locale: &LocaleFormats) -> Option<String> {
+        let mut format = chrono_format(&locale.time, self.seconds || self.millis)?;
+        if self.millis {
+            format = format.replace("%S", "%S%.3f");
+        }
+        let time = self.now.format(&format).to_string();
+        let text = if self.date {
+            let date = self
+                .now
+                .format(&chrono_format(&locale.date, true)?)
+                .to_string();
+            format!("{} {}", date, time)
+        } else {
+            time
+        };
+        Some(pango_escape(&text))
+    }
+}
+
+impl Widget for Time {
+    fn render(&self, _style: &Style) -> String {
+        if let Some(text) = self
+            .locale
+            .as_ref()
+            .and_then(|locale| self.render_locale(locale))
+        {
+            return text;
+        }
+        let time = self.now;
+        let time_str = if self.millis {
+            format_millis(&time)
+        } else if self.seconds {
+            time.format("%H:%M:%S").to_string()
+        } else {
+            time.format("%H:%M").to_string()
+        };
+        let time_of_day = match time.hour() {
+            0..=11 => "AM",
+            12..=23 => "PM",
+            _ => unreachable!(),
+        };
+        if self.date {
+            format!(
+                "{date} {time} {}",
+                time_of_day,
+                date = time.format("%Y-%m-%d"),
+                time = time_str,
+            )
+        } else {
+            format!("{} {}", time_str, time_of_day)
+        }
+    }
+
+    fn to_json(&self) -> json::Object {
+        to_json(&self.now)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.now.format("%H:%M").to_string())
+    }
+}
+
+pub struct TimeZh {
+    pub now: DateTime<Local>,
+    pub seconds: bool,
+    pub date: bool,
+    pub am_pm: bool,
+    pub era: Option<Era>,
+}
+
+impl TimeZh {
+    /// `民国115年 ` with `--era`, nothing without it
+    fn era_str(&self, style: &Style) -> String {
+        let Some(era) = self.era else {
+            return String::new();
+        };
+        let (name, year) = match era {
+            Era::Minguo => ("民国", gregorian_to_minguo(self.now.year())),
+            Era::Buddhist => ("佛历", gregorian_to_buddhist(self.now.year())),
+        };
+        let unit = |text| {
+            pango!(
+                text,
+                color = style.color("time_zh.unit", style.palette().secondary)
+            )
+        };
+        format!(
+            "{}{}{} ",
+            unit(name),
+            pango!(
+                year,
+                color = style.color("time_zh.date", style.palette().primary),
+                font_size = "110%",
+                weight = "ultrabold"
+            ),
+            unit("年"),
+        )
+    }
+
+    fn time_str(&self) -> String {
+        let time = self.now;
+        let mut h = time.hour() % if self.am_pm { 12 } else { 24 };
+        // 12-hour clock 0:00 => 12:00, but in 24 hour clock 0:00 => 0:00
+        if h == 0 && self.am_pm {
+            h = 12;
+        }
+        let m = time.minute();
+        let s = time.second();
+        if self.seconds {
+            format!("{:02}:{:02}:{:02}", h, m, s)
+        } else {
+            format!("{:02}:{:02}", h, m)
+        }
+    }
+}
+
+impl Widget for TimeZh {
+    fn render(&self, style: &Style) -> String {
+        let time = self.now;
+        let palette = style.palette();
+        let time_str = self.time_str();
+        let time_of_day = match time.hour() {
+            0..=5 => "凌晨",
+            6..=11 => "上午",
+            12..=13 => "中午",
+            14..=17 => "下午",
+            18..=23 => "晚上",
+            _ => unreachable!(),
+        };
+        let era = self.era_str(style);
+        if self.date {
+            format!(
+                "{era}{date} {time} {tod}",
+                time = pango!(
+                    time_str,
+                    color = style.color("time_zh.time", palette.primary),
+                    weight = "ultrabold",
+                    font_features = TABULAR_NUMS,
+                    font_size = "120%",
+                ),
+                tod = pango!(
+                    time_of_day,
+                    color = style.color("time_zh.time_of_day", palette.secondary),
+                ),
+                date = {
+                    let y = time.year();
+                    let m = time.month();
+                    let d = time.day();
+                    format!(
+                        "{y}{nian}{m}{yue}{d}{ri}",
+                        y = pango!(
+                            y,
+                            color = style.color("time_zh.date", palette.primary),
+                            font_size = "110%",
+                            weight = "ultrabold"
+                        ),
+                        m = pango!(
+                            m,
+                            color = style.color("time_zh.date", palette.primary),
+                            font_size = "110%",
+                            weight = "ultrabold"
+                        ),
+                        d = pango!(
+                            d,
+                            color = style.color("time_zh.date", palette.primary),
+                            font_size = "110%",
+                            weight = "ultrabold"
+                        ),
+                        nian = pango!("年", color = style.color("time_zh.unit", palette.secondary)),
+                        yue = pango!("月", color = style.color("time_zh.unit", palette.secondary)),
+                        ri = pango!("日", color = style.color("time_zh.unit", palette.secondary)),
+                    )
+                },
+            )
+        } else {
+            format!("{}{} {}", era, time_str, time_of_day)
+        }
+    }
+
+    fn to_json(&self) -> json::Object {
+        to_json(&self.now)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.time_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+
+    #[test]
+    fn translates_locale_formats_for_chrono() {
+        for (strftime, seconds, expected) in [
+            ("%H:%M:%S", true, Some("%H:%M:%S")),
+            ("%H:%M:%S", false, Some("%H:%M")),
+            ("%H.%M.%S", false, Some("%H.%M")),
+            ("%T", false, Some("%H:%M")),
+            ("%r", true, Some("%I:%M:%S %p")),
+            ("%r", false, Some("%I:%M %p")),
+            ("%EY/%Om/%d", true, Some("%Y/%m/%d")),
+            ("%Y年%m月%d日", true, Some("%Y年%m月%d日")),
+            ("%H:%M %", true, None),
+            ("%Q", true, None),
+        ] {
+            assert_eq!(
+                chrono_format(strftime, seconds).as_deref(),
+                expected,
+                "{}",
+                strftime
+            );
+        }
+    }
+
+    #[test]
+    fn millis_are_padded_and_leap_seconds_stay_in_range() {
+        for (time, expected) in [
+            (NaiveTime::from_hms_milli_opt(9, 5, 3, 7), "09:05:03.007"),
+            (
+                NaiveTime::from_hms_milli_opt(23, 59, 59, 999),
+                "23:59:59.999",
+            ),
+            (
+                NaiveTime::from_hms_milli_opt(23, 59, 59, 1_500),
+                "23:59:59.999",
+            ),
+        ] {
+            assert_eq!(format_millis(&time.unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn converts_to_the_era_years() {
+        for (year, minguo, buddhist) in [(2026, 115, 2569), (1912, 1, 2455), (1911, 0, 2454)] {
+            assert_eq!(gregorian_to_minguo(year), minguo);
+            assert_eq!(gregorian_to_buddhist(year), buddhist);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Error, Result};
+
+use crate::Theme;
+
+/// Resolve a user supplied color, either a `#rgb`/`#rrggbb` literal or a color name of
+/// `theme`
+pub fn resolve(input: &str, theme: &Theme) -> Result<Color> {
+    if input.starts_with('#') {
+        return input.parse();
+    }
+    theme.by_name(input).ok_or_else(|| unknown(input))
+}
+
+/// Check that `input` is a color [`resolve`] accepts, without building a theme for it
+pub fn validate(input: &str) -> Result<()> {
+    if input.starts_with('#') {
+        input.parse::<Color>()?;
+    } else if !Theme::FIELDS.contains(&input) {
+        return Err(unknown(input));
+    }
+    Ok(())
+}
+
+fn unknown(input: &str) -> Error {
+    anyhow!(
+        "unknown color `{}`, expected #rrggbb, #rgb or one of {}",
+        input,
+        Theme::FIELDS.join(", ")
+    )
+}
+
+/// An sRGB color, parsed from and displayed as `#rrggbb`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    /// Linear interpolation towards `other`, `t` is clamped to `0.0..=1.0`
+    pub fn lerp(self, other: Color, t: f64) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Color(
+            mix(self.0, other.0),
+            mix(self.1, other.1),
+            mix(self.2, other.2),
+        )
+    }
+
+    /// Darken towards black, `factor` 0.0 keeps the color and 1.0 is black
+    pub fn dim(self, factor: f64) -> Color {
+        self.lerp(Color(0, 0, 0), factor)
+    }
+}
+
+/// The color `t` of the way along evenly spaced `stops`, e.g. green, yellow and red for an
+/// urgency gradient. `t` is clamped to `0.0..=1.0`, and `stops` must not be empty
+pub fn gradient(stops: &[Color], t: f64) -> Color {
+    assert!(!stops.is_empty(), "a gradient needs a color");
+    if stops.len() == 1 {
+        return stops[0];
+    }
+    let segments = stops.len() - 1;
+    let position = t.clamp(0.0, 1.0) * segments as f64;
+    let i = (position.floor() as usize).min(segments - 1);
+    stops[i].lerp(stops[i + 1], position - i as f64)
+}
+
+impl FromStr for Color {
+    type Err = Error;
+
+    /// Accepts `#rrggbb` and the short `#rgb` form
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid hex color `{}`, expected #rrggbb", s);
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| invalid());
+        match hex.len() {
+            6 => Ok(Color(
+                channel(&hex[0..2])?,
+                channel(&hex[2..4])?,
+                channel(&hex[4..6])?,
+            )),
+            3 => {
+                let short = |i: usize| channel(&hex[i..=i]).map(|c| c * 0x11);
+                Ok(Color(short(0)?, short(1)?, short(2)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_literals() {
+        for (input, expected) in [
+            ("#ff0000", Color(0xff, 0, 0)),
+            ("#f00", Color(0xff, 0, 0)),
+            ("#AbC", Color(0xaa, 0xbb, 0xcc)),
+            ("#1a1b26", Color(0x1a, 0x1b, 0x26)),
+            ("#000", Color(0, 0, 0)),
+        ] {
+            assert_eq!(input.parse::<Color>().unwrap(), expected, "{}", input);
+        }
+        // the short form comes out expanded
+        assert_eq!("#f00".parse::<Color>().unwrap().to_string(), "#ff0000");
+        assert_eq!("#AbC".parse::<Color>().unwrap().to_string(), "#aabbcc");
+        for invalid in [
+            "ff0000", "#", "#ff00", "#ff00001", "#gg0000", "#+1f", "#ffé",
+        ] {
+            assert!(invalid.parse::<Color>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn resolves_names_against_the_theme() {
+        let theme = Theme::tokyonight_normal();
+        for (input, expected) in [
+            ("red", theme.red()),
+            ("index_16", theme.index_16()),
+            ("foreground", theme.foreground()),
+            ("#0f0", Color(0, 0xff, 0)),
+        ] {
+            assert_eq!(resolve(input, &theme).unwrap(), expected, "{}", input);
+            assert!(validate(input).is_ok(), "{}", input);
+        }
+        for name in Theme::FIELDS {
+            assert_eq!(resolve(name, &theme).ok(), theme.by_name(name));
+        }
+    }
+
+    #[test]
+    fn unknown_colors_list_what_is_accepted() {
+        let theme = Theme::tokyonight_normal();
+        for invalid in ["crimson", "Red", "", "index_18"] {
+            let message = resolve(invalid, &theme).unwrap_err().to_string();
+            assert!(message.contains("#rrggbb, #rgb"), "{}", message);
+            for name in Theme::FIELDS {
+                assert!(message.contains(name), "{} lacks {}", message, name);
+            }
+            assert_eq!(validate(invalid).unwrap_err().to_string(), message);
+        }
+        assert!(resolve("#12", &theme).is_err());
+        assert!(validate("#12").is_err());
+    }
+
+    #[test]
+    fn lerps_and_grades_between_stops() {
+        let black = Color(0, 0, 0);
+        let white = Color(0xff, 0xff, 0xff);
+        assert_eq!(black.lerp(white, 0.5), Color(0x80, 0x80, 0x80));
+        assert_eq!(black.lerp(white, -1.0), black);
+        assert_eq!(black.lerp(white, 2.0), white);
+
+        let (green, yellow, red) = (Color(0, 0xff, 0), Color(0xff, 0xff, 0), Color(0xff, 0, 0));
+        let stops = [green, yellow, red];
+        for (t, expected) in [
+            (0.0, green),
+            (0.25, Color(0x80, 0xff, 0)),
+            (0.5, yellow),
+            (0.75, Color(0xff, 0x80, 0)),
+            (1.0, red),
+            (-0.5, green),
+            (1.5, red),
+        ] {
+            assert_eq!(gradient(&stops, t), expected, "{}", t);
+        }
+        assert_eq!(gradient(&[red], 0.7), red);
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt::Write;
+
+use clap::{builder::PossibleValue, Arg, ValueHint};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What completion needs to know about a `--long` option
+struct Opt {
+    long: String,
+    help: String,
+    takes_value: bool,
+    values: Vec<String>,
+    files: bool,
+}
+
+impl Opt {
+    fn from_arg(arg: &Arg) -> Option<Self> {
+        let long = arg.get_long()?.to_string();
+        let takes_value = arg.get_num_args().is_some_and(|num| num.takes_values());
+        let values = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| PossibleValue::get_name(value).to_string())
+            .collect();
+        let files = matches!(
+            arg.get_value_hint(),
+            ValueHint::FilePath | ValueHint::AnyPath | ValueHint::DirPath
+        );
+        Some(Self {
+            long,
+            help: first_line(arg.get_help().map(|help| help.to_string())),
+            takes_value,
+            values,
+            files,
+        })
+    }
+}
+
+/// A visible subcommand and its options, globals included
+struct Sub {
+    name: String,
+    about: String,
+    opts: Vec<Opt>,
+}
+
+fn first_line(text: Option<String>) -> String {
+    text.unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn opts(command: &clap::Command) -> Vec<Opt> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .filter_map(Opt::from_arg)
+        .collect()
+}
+
+/// Subcommands that print something other than a bar block
+pub const UTILITIES: &[&str] = &["print-theme", "doctor", "color-test", "list"];
+
+/// `(name, description)` of every visible widget subcommand
+pub fn list(command: &clap::Command) -> Vec<(String, String)> {
+    command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && !UTILITIES.contains(&sub.get_name()))
+        .map(|sub| {
+            let about = sub.get_about().map(|about| about.to_string());
+            (sub.get_name().to_string(), first_line(about))
+        })
+        .collect()
+}
+
+pub fn generate(shell: Shell, command: &mut clap::Command) -> String {
+    // propagates the global options into the subcommands
+    command.build();
+    let name = command.get_name().to_string();
+    let global = opts(command);
+    let subs = command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+        .map(|sub| Sub {
+            name: sub.get_name().to_string(),
+            about: first_line(sub.get_about().map(|about| about.to_string())),
+            opts: opts(sub),
+        })
+        .collect::<Vec<_>>();
+    match shell {
+        Shell::Bash => bash(&name, &global, &subs),
+        Shell::Zsh => zsh(&name, &global, &subs),
+        Shell::Fish => fish(&name, &global, &subs),
+    }
+}
+
+/// Quote for any of the shells inside single quotes
+fn quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+fn bash(name: &str, global: &[Opt], subs: &[Sub]) -> String {
+    let function = format!("_{}", name.replace('-', "_"));
+    let mut out = String::new();
+    let names = subs.iter().map(|sub| sub.name.as_str()).collect::<Vec<_>>();
+    let _ = writeln!(out, "{}() {{", function);
+    let _ = writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(out, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(out, "    local sub word");
+    let _ = writeln!(
+        out,
+        "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do"
+    );
+    let _ = writeln!(out, "        case \"$word\" in");
+    let _ = writeln!(
+        out,
+        "            {}) sub=\"$word\"; break ;;",
+        names.join("|")
+    );
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "    done");
+    let _ = writeln!(out, "    case \"$prev\" in");
+    let mut seen = Vec::new();
+    for opt in global.iter().chain(subs.iter().flat_map(|sub| &sub.opts)) {
+        if !opt.takes_value || seen.contains(&&opt.long) {
+            continue;
+        }
+        seen.push(&opt.long);
+        let reply = if opt.files {
+            "$(compgen -f -- \"$cur\")".to_string()
+        } else if !opt.values.is_empty() {
+            format!("$(compgen -W '{}' -- \"$cur\")", opt.values.join(" "))
+        } else {
+            String::new()
+        };
+        let _ = writeln!(
+            out,
+            "        --{}) COMPREPLY=({}); return ;;",
+            opt.long, reply
+        );
+    }
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "    local words");
+    let _ = writeln!(out, "    case \"$sub\" in");
+    for sub in subs {
+        let longs = sub
+            .opts
+            .iter()
+            .map(|opt| format!("--{}", opt.long))
+            .collect::<Vec<_>>();
+        let _ = writeln!(out, "        {}) words='{}' ;;", sub.name, longs.join(" "));
+    }
+    let longs = global
+        .iter()
+        .map(|opt| format!("--{}", opt.long))
+        .chain(names.iter().map(|name| name.to_string()))
+        .collect::<Vec<_>>();
+    let _ = writeln!(out, "        *) words='{}' ;;", longs.join(" "));
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out, "complete -F {} {}", function, name);
+    out
+}
+
+fn zsh_spec(opt: &Opt) -> String {
+    // `[` and `]` delimit the description and `:` the value spec
+    let help = quote(&opt.help)
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:");
+    let value = if !opt.takes_value {
+        String::new()
+    } else if opt.files {
+        format!(":{}:_files", opt.long)
+    } else if !opt.values.is_empty() {
+        format!(":{}:({})", opt.long, opt.values.join(" "))
+    } else {
+        format!(":{}:", opt.long)
+    };
+    format!("'--{}[{}]{}'", opt.long, help, value)
+}
+
+fn zsh(name: &str, global: &[Opt], subs: &[Sub]) -> String {
+    let function = format!("_{}", name.replace('-', "_"));
+    let mut out = String::new();
+    let _ = writeln!(out, "#compdef {}", name);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "{}() {{", function);
+    let _ = writeln!(out, "    local -a subcommands");
+    let _ = writeln!(out, "    subcommands=(");
+    for sub in subs {
+        let _ = writeln!(
+            out,
+            "        '{}:{}'",
+            sub.name,
+            quote(&sub.about).replace(':', "\\:")
+        );
+    }
+    let _ = writeln!(out, "    )");
+    let _ = writeln!(out, "    local curcontext=\"$curcontext\" state line");
+    let _ = writeln!(out, "    _arguments -C \\");
+    for opt in global {
+        let _ = writeln!(out, "        {} \\", zsh_spec(opt));
+    }
+    let _ = writeln!(out, "        '1:command:->command' \\");
+    let _ = writeln!(out, "        '*::arg:->args'");
+    let _ = writeln!(out, "    case $state in");
+    let _ = writeln!(out, "        command) _describe command subcommands ;;");
+    let _ = writeln!(out, "        args)");
+    let _ = writeln!(out, "            case $line[1] in");
+    for sub in subs {
+        let specs = sub.opts.iter().map(zsh_spec).collect::<Vec<_>>();
+        let _ = writeln!(
+            out,
+            "                {}) _arguments {} ;;",
+            sub.name,
+            specs.join(" ")
+        );
+    }
+    let _ = writeln!(out, "            esac");
+    let _ = writeln!(out, "            ;;");
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "{} \"$@\"", function);
+    out
+}
+
+fn fish_opt(out: &mut String, name: &str, condition: &str, opt: &Opt) {
+    let mut line = format!("complete -c {} -n '{}' -l {}", name, condition, opt.long);
+    if opt.takes_value {
+        line += if opt.files { " -rF" } else { " -r" };
+        if !opt.values.is_empty() {
+            let _ = write!(line, " -fa '{}'", opt.values.join(" "));
+        }
+    }
+    if !opt.help.is_empty() {
+        let _ = write!(line, " -d '{}'", quote(&opt.help));
+    }
+    let _ = writeln!(out, "{}", line);
+}
+
+fn fish(name: &str, global: &[Opt], subs: &[Sub]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "complete -c {} -f", name);
+    for sub in subs {
+        let _ = writeln!(
+            out,
+            "complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'",
+            name,
+            sub.name,
+            quote(&sub.about)
+        );
+    }
+    for opt in global {
+        fish_opt(&mut out, name, "__fish_use_subcommand", opt);
+    }
+    for sub in subs {
+        let condition = format!("__fish_seen_subcommand_from {}", sub.name);
+        for opt in &sub.opts {
+            fish_opt(&mut out, name, &condition, opt);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn the_cli_is_consistent() {
+        crate::Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn completes_every_visible_subcommand() {
+        let command = crate::Cli::command();
+        let names = command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .map(|sub| sub.get_name().to_string())
+            .collect::<Vec<_>>();
+        assert!(names.len() > UTILITIES.len());
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = generate(shell, &mut crate::Cli::command());
+            for name in &names {
+                assert!(script.contains(name.as_str()), "{:?} lacks {}", shell, name);
+            }
+        }
+    }
+
+    #[test]
+    fn lists_every_widget_and_no_utility() {
+        let listed = list(&crate::Cli::command());
+        let names = listed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let widgets = [
+            "battery",
+            "time",
+            "time-zh",
+            "memory",
+            "disk",
+            "disk-tree",
+            "network",
+            "tcp-connections",
+            "sink-volume",
+            "app-volume",
+            "brightness",
+            "virsh-active",
+            "cpu",
+            "gpu-fan",
+            "sound-cards",
+            "combo",
+            "sessions",
+            "wireguard",
+            "sway-mode",
+            "countdown",
+            "hostname",
+            "lid-state",
+        ];
+        for widget in widgets {
+            assert!(names.contains(&widget), "{} is not listed", widget);
+        }
+        for other in UTILITIES.iter().chain(&["help", "completions", "man"]) {
+            assert!(!names.contains(other), "{} is listed", other);
+        }
+        assert_eq!(names.len(), widgets.len());
+        for (name, about) in &listed {
+            assert!(!about.is_empty(), "{} has no description", name);
+            assert!(!about.contains('\n'), "{}: {:?}", name, about);
+        }
+    }
+}
@@ -0,0 +1,627 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, ValueEnum};
+
+use crate::{Cli, Command};
+
+/// A value in the supported subset of TOML: no arrays, inline tables or dates
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+/// `[global]`, the options every widget takes
+#[derive(Debug, Default)]
+pub struct Global {
+    /// `--fg`, a hex literal or theme color name
+    pub fg: Option<String>,
+    /// `--accent`, a hex literal or theme color name
+    pub accent: Option<String>,
+    /// `--output`, e.g. `"i3bar"`
+    pub output: Option<String>,
+    /// `--icon-set`, e.g. `"nerd"`
+    pub icon_set: Option<String>,
+    /// `--appearance`, `"light"` or `"dark"`
+    pub appearance: Option<String>,
+    /// `--cmd-timeout` in seconds
+    pub cmd_timeout: Option<f64>,
+    /// `--interval` in seconds
+    pub interval: Option<f64>,
+}
+
+/// `[battery]`
+#[derive(Debug, Default)]
+pub struct Battery {
+    /// `--device-path`, e.g. `"/org/freedesktop/UPower/devices/battery_BAT0"`
+    pub device_path: Option<String>,
+    /// `--smooth`, the number of readings to take the median of
+    pub smooth: Option<usize>,
+    /// `--show-profile`
+    pub show_profile: Option<bool>,
+    /// `--show-cycles`
+    pub show_cycles: Option<bool>,
+    /// `--wear-warn`, a health percentage
+    pub wear_warn: Option<u32>,
+    /// `--battery-icon-charging`
+    pub icon_charging: Option<String>,
+    /// `--battery-icon-low`
+    pub icon_low: Option<String>,
+    /// `--battery-icon-normal`
+    pub icon_normal: Option<String>,
+}
+
+/// `[memory]`
+#[derive(Debug, Default)]
+pub struct Memory {
+    /// `--si`
+    pub si: Option<bool>,
+    /// `--include-buffers-cache`
+    pub include_buffers_cache: Option<bool>,
+    /// `--show-hugepages`
+    pub show_hugepages: Option<bool>,
+}
+
+/// `[sink_volume]`
+#[derive(Debug, Default)]
+pub struct SinkVolume {
+    /// `--max-volume`
+    pub max_volume: Option<u32>,
+}
+
+/// `[time]`, used by both `time` and `time-zh`
+#[derive(Debug, Default)]
+pub struct Time {
+    /// `--seconds`
+    pub seconds: Option<bool>,
+    /// `--date`
+    pub date: Option<bool>,
+    /// `--am-pm`, only for `time-zh`
+    pub am_pm: Option<bool>,
+    /// `--locale-time`, only for `time`
+    pub locale_time: Option<bool>,
+    /// `--millis`, only for `time`
+    pub millis: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub global: Global,
+    /// `[theme]`, theme color names mapped to hex literals or other theme color names, see
+    /// [`crate::Theme::with_field`]
+    pub theme: Vec<(String, String)>,
+    /// `[tools]`, program names mapped to the path to run them from, like `--tool-path`
+    pub tools: Vec<(String, String)>,
+    /// `[format]`, widget names mapped to their parsed templates
+    pub format: Vec<(String, crate::template::Template)>,
+    pub battery: Battery,
+    pub memory: Memory,
+    pub sink_volume: SinkVolume,
+    pub time: Time,
+    /// Every setting by its `section.key` name, for [`Config::changes`]
+    pub settings: Vec<(String, Value)>,
+}
+
+/// Whether a change to the setting `name` only takes effect after a restart, as the tools and
+/// their timeout are set up once when `--interval` starts
+pub fn needs_restart(name: &str) -> bool {
+    name == "global.cmd_timeout" || name.starts_with("tools.")
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("i3widgets/config.toml"))
+}
+
+impl Config {
+    /// Load `path`, or the default path if `None`, where a missing file is just an empty
+    /// config
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("in {}", path.display())),
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (line, section, key, value) in parse_toml(text)? {
+            let name = match section.as_str() {
+                "" => key.clone(),
+                section => format!("{}.{}", section, key),
+            };
+            config
+                .set(&section, &key, value.clone())
+                .with_context(|| format!("line {}: `{}`", line, name))?;
+            // a repeated key wins, as it does in `set`
+            config.settings.retain(|(setting, _)| *setting != name);
+            config.settings.push((name, value));
+        }
+        Ok(config)
+    }
+
+    /// The names of the settings `new` adds, removes or gives another value, for a reload
+    pub fn changes(&self, new: &Config) -> Vec<String> {
+        let value = |config: &Config, name: &str| {
+            config
+                .settings
+                .iter()
+                .find(|(setting, _)| setting == name)
+                .map(|(_, value)| value.clone())
+        };
+        let mut names = Vec::new();
+        for (name, _) in self.settings.iter().chain(&new.settings) {
+            if !names.contains(name) && value(self, name) != value(new, name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) -> Result<()> {
+        match (section, key) {
+            ("global", "fg") => self.global.fg = Some(string(value)?),
+            ("global", "accent") => self.global.accent = Some(string(value)?),
+            ("global", "output") => self.global.output = Some(string(value)?),
+            ("global", "icon_set") => self.global.icon_set = Some(string(value)?),
+            ("global", "appearance") => self.global.appearance = Some(string(value)?),
+            ("global", "cmd_timeout") => {
+                self.global.cmd_timeout = Some(crate::seconds(float(value)?, 0.0)?)
+            }
+            ("global", "interval") => {
+                self.global.interval = Some(crate::seconds(float(value)?, crate::MIN_INTERVAL)?)
+            }
+            ("theme", field) => self.theme.push((field.to_string(), string(value)?)),
+            ("tools", program) => self.tools.push((program.to_string(), string(value)?)),
+            ("format", widget) => {
+                let fields = crate::template::fields(widget).ok_or_else(|| {
+                    let widgets = crate::template::FIELDS
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>();
+                    anyhow!("no template support, only for {}", widgets.join(", "))
+                })?;
+                let template = crate::template::Template::parse(&string(value)?, fields)?;
+                self.format.push((widget.to_string(), template));
+            }
+            ("battery", "device_path") => self.battery.device_path = Some(string(value)?),
+            ("battery", "smooth") => self.battery.smooth = Some(integer(value)?),
+            ("battery", "show_profile") => self.battery.show_profile = Some(boolean(value)?),
+            ("battery", "show_cycles") => self.battery.show_cycles = Some(boolean(value)?),
+            ("battery", "wear_warn") => self.battery.wear_warn = Some(integer(value)?),
+            ("battery", "icon_charging") => self.battery.icon_charging = Some(string(value)?),
+            ("battery", "icon_low") => self.battery.icon_low = Some(string(value)?),
+            ("battery", "icon_normal") => self.battery.icon_normal = Some(string(value)?),
+            ("memory", "si") => self.memory.si = Some(boolean(value)?),
+            ("memory", "include_buffers_cache") => {
+                self.memory.include_buffers_cache = Some(boolean(value)?)
+            }
+            ("memory", "show_hugepages") => self.memory.show_hugepages = Some(boolean(value)?),
+            ("sink_volume", "max_volume") => self.sink_volume.max_volume = Some(integer(value)?),
+            ("time", "seconds") => self.time.seconds = Some(boolean(value)?),
+            ("time", "date") => self.time.date = Some(boolean(value)?),
+            ("time", "am_pm") => self.time.am_pm = Some(boolean(value)?),
+            ("time", "locale_time") => self.time.locale_time = Some(boolean(value)?),
+            ("time", "millis") => self.time.millis = Some(boolean(value)?),
+            _ => return Err(anyhow!("unknown setting")),
+        }
+        Ok(())
+    }
+
+    /// The subcommand `name` with its defaults and the settings from this config
+    pub(crate) fn command(&self, name: &str) -> Result<Command> {
+        let matches = Cli::command()
+            .try_get_matches_from(["i3widgets", name])
+            .map_err(|err| {
+                // only the first paragraph, the rest is usage for the command line
+                let message = err.to_string();
+                let first = message
+                    .lines()
+                    .take_while(|line| !line.trim().is_empty())
+                    .map(str::trim)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                anyhow!("{}", first.trim_start_matches("error: "))
+            })?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        self.apply(&mut cli, &matches)?;
+        Ok(cli.command)
+    }
+
+    /// Fill in every option of `cli` that `matches` says wasn't given on the command line
+    pub(crate) fn apply(&self, cli: &mut Cli, matches: &ArgMatches) -> Result<()> {
+        let unset = |matches: &ArgMatches, id: &str| {
+            matches.value_source(id) != Some(ValueSource::CommandLine)
+        };
+        let global = &self.global;
+        if unset(matches, "fg") && global.fg.is_some() {
+            cli.fg = global.fg.clone();
+        }
+        if unset(matches, "accent") && global.accent.is_some() {
+            cli.accent = global.accent.clone();
+        }
+        if let (true, Some(output)) = (unset(matches, "output"), &global.output) {
+            cli.output = value_enum(output).context("global.output")?;
+        }
+        if let (true, Some(icon_set)) = (unset(matches, "icon_set"), &global.icon_set) {
+            cli.icon_set = value_enum(icon_set).context("global.icon_set")?;
+        }
+        if let (true, Some(appearance)) = (unset(matches, "appearance"), &global.appearance) {
+            cli.appearance = Some(value_enum(appearance).context("global.appearance")?);
+        }
+        if let (true, Some(cmd_timeout)) = (unset(matches, "cmd_timeout"), global.cmd_timeout) {
+            cli.cmd_timeout = cmd_timeout;
+        }
+        if unset(matches, "interval") && global.interval.is_some() {
+            cli.interval = global.interval;
+        }
+        // the command line goes last so its paths win
+        let mut tool_paths = self.tools.clone();
+        tool_paths.append(&mut cli.tool_paths);
+        cli.tool_paths = tool_paths;
+        let Some((_, matches)) = matches.subcommand() else {
+            return Ok(());
+        };
+        let set_bool = |target: &mut bool, id: &str, value: Option<bool>| {
+            if let (true, Some(value)) = (unset(matches, id), value) {
+                *target = value;
+            }
+        };
+        match &mut cli.command {
+            Command::Battery {
+                device_path,
+                smooth,
+                show_profile,
+                show_cycles,
+                wear_warn,
+                battery_icon_charging,
+                battery_icon_low,
+                battery_icon_normal,
+                ..
+            } => {
+                let battery = &self.battery;
+                if device_path.is_none() {
+                    device_path.clone_from(&battery.device_path);
+                }
+                if let (true, Some(value)) = (unset(matches, "smooth"), battery.smooth) {
+                    *smooth = value;
+                }
+                set_bool(show_profile, "show_profile", battery.show_profile);
+                set_bool(show_cycles, "show_cycles", battery.show_cycles);
+                if wear_warn.is_none() {
+                    *wear_warn = battery.wear_warn;
+                }
+                for (target, value) in [
+                    (battery_icon_charging, &battery.icon_charging),
+                    (battery_icon_low, &battery.icon_low),
+                    (battery_icon_normal, &battery.icon_normal),
+                ] {
+                    if let (None, Some(value)) = (target.as_ref(), value) {
+                        *target = Some(crate::parse_icon(value)?);
+                    }
+                }
+            }
+            Command::Memory {
+                si,
+                include_buffers_cache,
+                show_hugepages,
+                ..
+            } => {
+                set_bool(si, "si", self.memory.si);
+                set_bool(
+                    include_buffers_cache,
+                    "include_buffers_cache",
+                    self.memory.include_buffers_cache,
+                );
+                set_bool(show_hugepages, "show_hugepages", self.memory.show_hugepages);
+            }
+            Command::SinkVolume { max_volume, .. } => {
+                if let (true, Some(value)) =
+                    (unset(matches, "max_volume"), self.sink_volume.max_volume)
+                {
+                    *max_volume = value;
+                }
+            }
+            Command::Time {
+                seconds,
+                date,
+                locale_time,
+                millis,
+            } => {
+                set_bool(seconds, "seconds", self.time.seconds);
+                set_bool(date, "date", self.time.date);
+                set_bool(locale_time, "locale_time", self.time.locale_time);
+                set_bool(millis, "millis", self.time.millis);
+            }
+            Command::TimeZh {
+                seconds,
+                date,
+                am_pm,
+                ..
+            } => {
+                set_bool(seconds, "seconds", self.time.seconds);
+                set_bool(date, "date", self.time.date);
+                set_bool(am_pm, "am_pm", self.time.am_pm);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn value_enum<T: ValueEnum>(value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|_| anyhow!("invalid value `{}`", value))
+}
+
+fn string(value: Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value),
+        value => Err(anyhow!("expected a string, got {:?}", value)),
+    }
+}
+
+fn boolean(value: Value) -> Result<bool> {
+    match value {
+        Value::Boolean(value) => Ok(value),
+        value => Err(anyhow!("expected true or false, got {:?}", value)),
+    }
+}
+
+fn integer<T: TryFrom<i64>>(value: Value) -> Result<T> {
+    match value {
+        Value::Integer(value) => {
+            T::try_from(value).map_err(|_| anyhow!("{} is out of range", value))
+        }
+        value => Err(anyhow!("expected an integer, got {:?}", value)),
+    }
+}
+
+fn float(value: Value) -> Result<f64> {
+    match value {
+        Value::Float(value) => Ok(value),
+        Value::Integer(value) => Ok(value as f64),
+        value => Err(anyhow!("expected a number, got {:?}", value)),
+    }
+}
+
+/// Every `key = value` in `text` as `(line number, section, key, value)`
+pub fn parse_toml(text: &str) -> Result<Vec<(usize, String, String, Value)>> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            section = name
+                .strip_suffix(']')
+                .filter(|name| !name.trim().is_empty())
+                .ok_or_else(|| anyhow!("line {}: invalid section header", number))?
+                .trim()
+                .to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected `key = value`", number))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(anyhow!("line {}: missing key", number));
+        }
+        let value = parse_value(value.trim()).with_context(|| format!("line {}", number))?;
+        entries.push((number, section.clone(), key.to_string(), value));
+    }
+    Ok(entries)
+}
+
+/// `line` up to a `#` that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+pub fn parse_value(value: &str) -> Result<Value> {
+    if let Some(literal) = value.strip_prefix('\'') {
+        let literal = literal
+            .strip_suffix('\'')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        return Ok(Value::String(literal.to_string()));
+    }
+    if let Some(basic) = value.strip_prefix('"') {
+        let basic = basic
+            .strip_suffix('"')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        return unescape(basic).map(Value::String);
+    }
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+    let number = value.replace('_', "");
+    if let Ok(integer) = number.parse::<i64>() {
+        return Ok(Value::Integer(integer));
+    }
+    match number.parse::<f64>() {
+        Ok(float) if number.chars().any(|c| c.is_ascii_digit()) => Ok(Value::Float(float)),
+        _ => Err(anyhow!("unsupported value `{}`", value)),
+    }
+}
+
+fn unescape(basic: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = basic.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(kind @ ('u' | 'U')) => {
+                let len = if kind == 'u' { 4 } else { 8 };
+                let hex = chars.by_ref().take(len).collect::<String>();
+                let c = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == len)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| anyhow!("invalid escape `\\{}{}`", kind, hex))?;
+                out.push(c);
+            }
+            Some(c) => return Err(anyhow!("invalid escape `\\{}`", c)),
+            None => return Err(anyhow!("trailing backslash")),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_section() {
+        let config = Config::parse(
+            r##"
+# the bar on the left monitor
+[global]
+fg = "#c0caf5"  # comments after values too
+accent = 'cyan'
+output = "i3bar"
+interval = 2
+cmd_timeout = 0.5
+
+[theme]
+red = "#ff5555"
+
+[battery]
+device_path = "/org/freedesktop/UPower/devices/battery_BAT0"
+
+[memory]
+si = true
+
+[time]
+seconds = false
+"##,
+        )
+        .unwrap();
+        assert_eq!(config.global.fg.as_deref(), Some("#c0caf5"));
+        assert_eq!(config.global.accent.as_deref(), Some("cyan"));
+        assert_eq!(config.global.output.as_deref(), Some("i3bar"));
+        assert_eq!(config.global.interval, Some(2.0));
+        assert_eq!(config.global.cmd_timeout, Some(0.5));
+        assert_eq!(config.theme, [("red".to_string(), "#ff5555".to_string())]);
+        assert_eq!(
+            config.battery.device_path.as_deref(),
+            Some("/org/freedesktop/UPower/devices/battery_BAT0")
+        );
+        assert_eq!(config.memory.si, Some(true));
+        assert_eq!(config.time.seconds, Some(false));
+    }
+
+    #[test]
+    fn the_command_line_wins() {
+        let config =
+            Config::parse("[global]\nfg = \"red\"\naccent = \"cyan\"\n[memory]\nsi = true\n")
+                .unwrap();
+        let matches = Cli::command()
+            .try_get_matches_from(["i3widgets", "--fg", "green", "memory"])
+            .unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        config.apply(&mut cli, &matches).unwrap();
+        assert_eq!(cli.fg.as_deref(), Some("green"));
+        assert_eq!(cli.accent.as_deref(), Some("cyan"));
+        assert!(matches!(cli.command, Command::Memory { si: true, .. }));
+    }
+
+    #[test]
+    fn lists_the_changed_settings() {
+        let old = Config::parse(
+            "[global]\ninterval = 2\nfg = \"red\"\n[memory]\nsi = true\n[tools]\nwg = \"/a\"\n",
+        )
+        .unwrap();
+        // (new config, changed settings)
+        let cases: [(&str, &[&str]); 5] = [
+            (
+                "[memory]\nsi = true\n[tools]\nwg = \"/a\"\n[global]\nfg = 'red'\ninterval = 2\n",
+                &[],
+            ),
+            (
+                "[global]\ninterval = 5\nfg = \"red\"\n[memory]\nsi = true\n[tools]\nwg = \"/a\"\n",
+                &["global.interval"],
+            ),
+            (
+                "[global]\ninterval = 2\nfg = \"red\"\n[tools]\nwg = \"/b\"\n[time]\ndate = true\n",
+                &["memory.si", "tools.wg", "time.date"],
+            ),
+            // the last of a repeated key is the one in effect
+            (
+                "[global]\ninterval = 9\ninterval = 2\nfg = \"red\"\n[memory]\nsi = true\n[tools]\nwg = \"/a\"\n",
+                &[],
+            ),
+            (
+                "",
+                &["global.interval", "global.fg", "memory.si", "tools.wg"],
+            ),
+        ];
+        for (text, changed) in cases {
+            let new = Config::parse(text).unwrap();
+            assert_eq!(old.changes(&new), changed, "{:?}", text);
+        }
+        assert!(old.changes(&old).is_empty());
+        let restart = ["global.cmd_timeout", "tools.wg"];
+        assert!(restart.iter().all(|name| needs_restart(name)));
+        assert!(!needs_restart("global.interval"));
+    }
+
+    #[test]
+    fn rejects_bad_settings_with_their_line() {
+        for (text, message) in [
+            ("[global]\nfg = 3\n", "line 2"),
+            ("[global]\ncolour = \"red\"\n", "unknown setting"),
+            ("[memory]\nsi = \"yes\"\n", "expected true or false"),
+            ("[global]\ninterval = 0\n", "expected seconds from 0.1"),
+            ("[global]\ncmd_timeout = -1\n", "expected seconds from 0"),
+            ("[global]\nfg = \"red\n", "unterminated string"),
+        ] {
+            let err = Config::parse(text).unwrap_err();
+            let found = format!("{:#}", err);
+            assert!(found.contains(message), "{:?}: {}", text, found);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+
+use crate::{json, Color, Span, Style, Widget, TABULAR_NUMS};
+
+pub struct Countdown {
+    pub target: DateTime<Local>,
+    pub remaining: Duration,
+    /// Turn red once less than this remains
+    pub warn: Duration,
+    pub done_text: Option<String>,
+}
+
+impl Widget for Countdown {
+    fn render(&self, style: &Style) -> String {
+        let text = match &self.done_text {
+            Some(done_text) if self.remaining.is_zero() => done_text.clone(),
+            _ => format_remaining(self.remaining),
+        };
+        let color = if self.remaining < self.warn {
+            style.theme().red()
+        } else {
+            style.palette().primary
+        };
+        format!(
+            "{icon} {text}",
+            icon = style.icon("countdown", "countdown"),
+            text = pango!(
+                text,
+                color = style.color("countdown.time", color),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("target", self.target.to_rfc3339())
+            .field("remaining_secs", self.remaining.as_secs())
+            .field("done", self.remaining.is_zero())
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(format_remaining(self.remaining))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        (self.remaining < self.warn).then(|| style.theme().red())
+    }
+}
+
+/// Parse the `--until` target, either RFC3339 or `HH:MM` meaning the next time the clock
+/// reads that, today or tomorrow
+pub fn parse_target(until: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    if let Ok(target) = DateTime::parse_from_rfc3339(until) {
+        return Ok(target.with_timezone(&Local));
+    }
+    let time = NaiveTime::parse_from_str(until, "%H:%M")
+        .map_err(|_| anyhow!("`{}` is neither RFC3339 nor HH:MM", until))?;
+    let mut date = now.date_naive();
+    loop {
+        // skip over times that don't exist locally, e.g. inside a DST gap
+        if let Some(target) = Local.from_local_datetime(&date.and_time(time)).earliest() {
+            if target > now {
+                return Ok(target);
+            }
+        }
+        date = date
+            .succ_opt()
+            .ok_or_else(|| anyhow!("no next occurrence of `{}`", until))?;
+    }
+}
+
+/// Time left until `target`, zero once it has passed
+pub fn remaining(now: DateTime<Local>, target: DateTime<Local>) -> Duration {
+    (target - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+/// `MM:SS`, or `HH:MM:SS` when at least an hour remains
+pub fn format_remaining(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+
+    /// In the local time zone, so callers hold [`test_env`](crate::snapshots::test_env)
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 10, 14, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn a_past_target_shows_zero() {
+        let _env = crate::snapshots::test_env();
+        let now = at(18, 13);
+        assert_eq!(remaining(now, at(18, 0)), Duration::ZERO);
+        assert_eq!(remaining(now, now), Duration::ZERO);
+        assert_eq!(format_remaining(remaining(now, at(9, 0))), "00:00");
+        assert_eq!(remaining(now, at(18, 30)), Duration::from_secs(17 * 60));
+    }
+
+    #[test]
+    fn hh_mm_is_the_next_time_the_clock_reads_it() {
+        let _env = crate::snapshots::test_env();
+        let now = at(18, 13);
+        let later = parse_target("18:30", now).unwrap();
+        assert_eq!(later, at(18, 30));
+        // already past today, so tomorrow
+        let tomorrow = parse_target("09:00", now).unwrap();
+        assert_eq!(tomorrow.date_naive(), now.date_naive().succ_opt().unwrap());
+        assert_eq!((tomorrow.hour(), tomorrow.minute()), (9, 0));
+        // right now has passed too
+        assert_eq!(
+            parse_target("18:13", now).unwrap() - now,
+            chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn parses_rfc3339_or_fails() {
+        let _env = crate::snapshots::test_env();
+        let target = parse_target("2026-10-14T18:30:00+00:00", at(0, 0)).unwrap();
+        let utc = chrono::Utc
+            .with_ymd_and_hms(2026, 10, 14, 18, 30, 0)
+            .unwrap();
+        assert_eq!(target, utc);
+        for invalid in ["garbage", "25:00", "18:61", "18", ""] {
+            let err = parse_target(invalid, at(0, 0)).unwrap_err().to_string();
+            assert!(err.contains("neither RFC3339 nor HH:MM"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn formats_minutes_until_an_hour_is_left() {
+        for (secs, expected) in [
+            (0.0, "00:00"),
+            (59.9, "00:59"),
+            (60.0, "01:00"),
+            (3599.0, "59:59"),
+            (3600.0, "01:00:00"),
+            (86399.0, "23:59:59"),
+            (100.0 * 3600.0, "100:00:00"),
+        ] {
+            assert_eq!(
+                format_remaining(Duration::from_secs_f64(secs)),
+                expected,
+                "{}",
+                secs
+            );
+        }
+    }
+}
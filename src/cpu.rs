@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{color, json, Color, Span, Style, Widget, TABULAR_NUMS};
+
+/// Cores at or above this load are drawn red with `--per-core`, and yellow at half of it
+pub const CORE_CRITICAL_PCT: u64 = 90;
+
+/// Cumulative jiffies from one `cpu` line of `/proc/stat`
+#[derive(Debug, Clone, Copy)]
+pub struct Jiffies {
+    pub idle: u64,
+    pub total: u64,
+}
+
+impl Jiffies {
+    fn parse(line: &str) -> Result<Self> {
+        // cpu  user nice system idle iowait irq softirq steal guest guest_nice
+        let fields = line
+            .split_whitespace()
+            .skip(1)
+            .take(8)
+            .map(|field| field.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("parsing /proc/stat")?;
+        if fields.len() < 5 {
+            return Err(anyhow!("/proc/stat cpu line is invalid"));
+        }
+        Ok(Self {
+            idle: fields[3] + fields[4],
+            total: fields.iter().sum(),
+        })
+    }
+
+    /// Busy percentage between two readings of the same line
+    fn busy_pct(prev: Self, next: Self) -> u64 {
+        let idle = next.idle.saturating_sub(prev.idle);
+        let total = next.total.saturating_sub(prev.total);
+        (total.saturating_sub(idle) * 100)
+            .checked_div(total)
+            .unwrap_or(0)
+    }
+}
+
+/// The aggregate `cpu` line of `/proc/stat` and the `cpuN` line of every core
+#[derive(Debug, Clone)]
+pub struct CpuSnapshot {
+    pub all: Jiffies,
+    pub cores: Vec<Jiffies>,
+}
+
+impl CpuSnapshot {
+    pub fn read() -> Result<Self> {
+        let stat = std::fs::read_to_string("/proc/stat").context("reading /proc/stat")?;
+        let line = stat
+            .lines()
+            .find(|line| line.starts_with("cpu "))
+            .ok_or_else(|| anyhow!("cpu line not found in /proc/stat"))?;
+        let cores = stat
+            .lines()
+            .filter(|line| {
+                line.strip_prefix("cpu")
+                    .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+            })
+            .map(Jiffies::parse)
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            all: Jiffies::parse(line)?,
+            cores,
+        })
+    }
+}
+
+pub struct CpuUsage {
+    pub usage_pct: u64,
+    /// Busy percentage of each core, in `/proc/stat` order
+    pub cores: Vec<u64>,
+    /// Show every core with `--per-core` instead of the average
+    pub per_core: bool,
+}
+
+impl Widget for CpuUsage {
+    fn render(&self, style: &Style) -> String {
+        if self.per_core && !self.cores.is_empty() {
+            let stops = [
+                style.palette().primary,
+                style.theme().yellow(),
+                style.theme().red(),
+            ];
+            let cores = self
+                .cores
+                .iter()
+                .zip(core_colors(&stops, &self.cores))
+                .map(|(pct, color)| {
+                    pango!(
+                        pct,
+                        color = style.color("cpu.core", color),
+                        weight = "ultrabold",
+                        font_features = TABULAR_NUMS,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            return format!(
+                "{icon} {cores}{pct}",
+                icon = style.icon("cpu", "cpu"),
+                pct = style.unit("cpu", "%"),
+            );
+        }
+        format!(
+            "{icon} {value}{pct}",
+            icon = style.icon("cpu", "cpu"),
+            value = pango!(
+                self.usage_pct,
+                color = style.color("cpu.pct", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            pct = style.unit("cpu", "%"),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("usage_pct", self.usage_pct)
+            .field("cores_pct", self.cores.clone())
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(format!("{}{}", self.usage_pct, style.unit("cpu", "%")))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        (self.usage_pct >= 90).then(|| style.theme().red())
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        Some(("cpu", "cpu"))
+    }
+}
+
+/// The heat-map color of each core, by its own load on the urgency gradient `stops`, from
+/// idle up to [`CORE_CRITICAL_PCT`]
+pub fn core_colors(stops: &[Color], cores: &[u64]) -> Vec<Color> {
+    cores
+        .iter()
+        .map(|&pct| color::gradient(stops, pct as f64 / CORE_CRITICAL_PCT as f64))
+        .collect()
+}
+
+/// Busy percentage over `sample_count` consecutive samples spaced `interval` apart, along
+/// with the last snapshot taken
+pub fn usage(sample_count: u32, interval: Duration) -> Result<(CpuUsage, CpuSnapshot)> {
+    let first = CpuSnapshot::read()?;
+    let mut prev = first.clone();
+    for _ in 0..sample_count.max(1) {
+        std::thread::sleep(interval);
+        prev = CpuSnapshot::read()?;
+    }
+    Ok((between(&first, &prev), prev))
+}
+
+/// Busy percentage between two snapshots, overall and per core
+pub fn between(prev: &CpuSnapshot, next: &CpuSnapshot) -> CpuUsage {
+    CpuUsage {
+        usage_pct: Jiffies::busy_pct(prev.all, next.all),
+        cores: prev
+            .cores
+            .iter()
+            .zip(&next.cores)
+            .map(|(prev, next)| Jiffies::busy_pct(*prev, *next))
+            .collect(),
+        per_core: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_each_core_by_its_own_load() {
+        let stops = [Color(0, 0xff, 0), Color(0xff, 0xff, 0), Color(0xff, 0, 0)];
+        assert_eq!(
+            core_colors(&stops, &[0, 45, 90, 100, 9, 72]),
+            [
+                Color(0, 0xff, 0),
+                Color(0xff, 0xff, 0),
+                Color(0xff, 0, 0),
+                Color(0xff, 0, 0),
+                Color(0x33, 0xff, 0),
+                Color(0xff, 0x66, 0),
+            ]
+        );
+        assert!(core_colors(&stops, &[]).is_empty());
+    }
+}
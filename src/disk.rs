@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    json, process::CommandSource, ByteUnits, Color, Span, Style, Trend, Widget, TABULAR_NUMS,
+};
+
+/// Free space has to move by this much between two ticks to count as a trend
+pub const TREND_MIN_CHANGE: u64 = 1 << 20;
+
+pub struct DiskInfo {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+}
+
+pub struct DiskWidget {
+    pub mount: String,
+    pub info: DiskInfo,
+    pub units: ByteUnits,
+    /// How the free space moved since the last tick with `--interval`
+    pub trend: Option<Trend>,
+}
+
+impl Widget for DiskWidget {
+    fn render(&self, style: &Style) -> String {
+        let (available, suffix) = self.units.human(self.info.available);
+        let text = format!(
+            "{icon} {available}{suffix}",
+            icon = style.icon("disk", "disk"),
+            available = pango!(
+                format!("{:.1}", available),
+                color = style.color("disk.free", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            suffix = pango!(
+                style.localize(suffix),
+                color = style.color("disk.unit", style.palette().secondary)
+            ),
+        );
+        match self.trend.and_then(|trend| trend.icon_name()) {
+            Some(arrow) => format!("{} {}", text, style.icon("disk", arrow)),
+            None => text,
+        }
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("mount", &self.mount)
+            .field("total_bytes", self.info.total)
+            .field("used_bytes", self.info.used)
+            .field("available_bytes", self.info.available)
+            .optional("trend", self.trend.map(|trend| trend.name()))
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        let (available, suffix) = self.units.human(self.info.available);
+        Some(format!("{:.1}{}", available, suffix))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        let used_pct = (self.info.used * 100)
+            .checked_div(self.info.used + self.info.available)
+            .unwrap_or(0);
+        (used_pct >= 90).then(|| style.theme().red())
+    }
+}
+
+/// A filesystem from `df`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub target: String,
+    pub size: u64,
+    pub used_pct: u64,
+}
+
+/// Every mount, sorted by path so nested mounts follow the one they are under
+pub struct DiskTree {
+    pub mounts: Vec<Mount>,
+    pub bar_width: usize,
+}
+
+impl DiskTree {
+    /// The nearest other mount `mount` is under
+    fn parent(&self, mount: &Mount) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .filter(|other| other.target != mount.target && is_under(&mount.target, &other.target))
+            .max_by_key(|other| other.target.len())
+    }
+
+    fn depth(&self, mount: &Mount) -> usize {
+        self.mounts
+            .iter()
+            .filter(|other| other.target != mount.target && is_under(&mount.target, &other.target))
+            .count()
+    }
+
+    fn row(&self, style: &Style, mount: &Mount, label: &str) -> String {
+        let color = style.color(
+            "disk-tree.bar",
+            style.threshold_color(mount.used_pct, 75, 90),
+        );
+        format!(
+            "{label} {bar} {pct}{unit}",
+            label =
+                Span::new(label).color(style.color("disk-tree.mount", style.palette().secondary)),
+            bar = Span::new(crate::progress_bar(
+                mount.used_pct as f64 / 100.0,
+                self.bar_width
+            ))
+            .color(color),
+            pct = pango!(
+                mount.used_pct,
+                color = style.color("disk-tree.pct", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+            ),
+            unit = style.unit("disk-tree", "%"),
+        )
+    }
+}
+
+/// Whether `path` is below the mount point `parent`
+fn is_under(path: &str, parent: &str) -> bool {
+    parent == "/"
+        || path
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl Widget for DiskTree {
+    fn render(&self, style: &Style) -> String {
+        self.render_lines(style, 1).join(" ")
+    }
+
+    /// On several rows the mounts are indented under the one they are nested in and
+    /// labeled relative to it
+    fn render_lines(&self, style: &Style, lines: u8) -> Vec<String> {
+        let icon = style.icon("disk-tree", "disk");
+        if lines < 2 {
+            let rows = self
+                .mounts
+                .iter()
+                .map(|mount| self.row(style, mount, &mount.target))
+                .collect::<Vec<_>>();
+            return vec![format!("{} {}", icon, rows.join(" "))];
+        }
+        self.mounts
+            .iter()
+            .map(|mount| {
+                let label = match self.parent(mount) {
+                    Some(parent) if parent.target == "/" => mount.target.clone(),
+                    Some(parent) => mount.target[parent.target.len()..].to_string(),
+                    None => mount.target.clone(),
+                };
+                let indent = "  ".repeat(self.depth(mount));
+                format!("{}{} {}", indent, icon, self.row(style, mount, &label))
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> json::Object {
+        let mounts = self
+            .mounts
+            .iter()
+            .map(|mount| {
+                json::Object::new()
+                    .field("mount", &mount.target)
+                    .field("size_bytes", mount.size)
+                    .field("used_pct", mount.used_pct)
+            })
+            .collect::<Vec<_>>();
+        json::Object::new().field("mounts", mounts)
+    }
+
+    /// The fullest filesystem
+    fn short(&self, _style: &Style) -> Option<String> {
+        let fullest = self.mounts.iter().max_by_key(|mount| mount.used_pct)?;
+        Some(format!("{} {}%", fullest.target, fullest.used_pct))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        self.mounts
+            .iter()
+            .any(|mount| mount.used_pct >= 90)
+            .then(|| style.theme().red())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+}
+
+/// The mounted filesystems of at least `min_size` bytes
+pub fn mounts(min_size: u64, source: &dyn CommandSource) -> Result<Vec<Mount>> {
+    let output = source.output("df", &["-B1", "--output=target,size,pcent"])?;
+    let mut mounts = parse_mounts(&output)
+        .into_iter()
+        .filter(|mount| mount.size >= min_size)
+        .collect::<Vec<_>>();
+    mounts.sort_by(|a, b| a.target.cmp(&b.target));
+    // bind mounts and the like show up once per target
+    mounts.dedup_by(|a, b| a.target == b.target);
+    Ok(mounts)
+}
+
+/// Parse the output of `df -B1 --output=target,size,pcent`, skipping the filesystems `df`
+/// has no usage for. The size and percentage are the last fields because targets can
+/// contain spaces
+pub fn parse_mounts(output: &str) -> Vec<Mount> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let (rest, used_pct) = line.trim_end().rsplit_once(char::is_whitespace)?;
+            let (target, size) = rest.trim_end().rsplit_once(char::is_whitespace)?;
+            Some(Mount {
+                target: target.trim_end().to_string(),
+                size: size.parse().ok()?,
+                used_pct: used_pct.strip_suffix('%')?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Usage in bytes of the filesystem mounted at `mount`
+pub fn info(mount: &str, source: &dyn CommandSource) -> Result<DiskInfo> {
+    parse(&source.output("df", &["-B1", "--output=size,used,avail", mount])?)
+}
+
+/// Parse the output of `df -B1 --output=size,used,avail`
+pub fn parse(output: &str) -> Result<DiskInfo> {
+    let line = output
+        .lines()
+        .nth(1)
+        .ok_or_else(|| anyhow!("`df` output is invalid"))?;
+    let mut fields = line.split_whitespace().map(|field| field.parse::<u64>());
+    let mut next = || {
+        fields
+            .next()
+            .ok_or_else(|| anyhow!("`df` output is invalid"))?
+            .context("parsing `df` output")
+    };
+    Ok(DiskInfo {
+        total: next()?,
+        used: next()?,
+        available: next()?,
+    })
+}
@@ -0,0 +1,391 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{brightness, process::System, pulseaudio, signal, sway, Command};
+
+/// Set by the event source whenever the widget's data may have changed
+pub static CHANGED: AtomicBool = AtomicBool::new(false);
+/// Set along with [`CHANGED`] whenever the config file was saved, until the loop reloads it
+pub static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+/// Whether an event source is running. Timed polling at the normal interval takes over as
+/// soon as it stops
+pub static LIVE: AtomicBool = AtomicBool::new(false);
+
+/// Refresh once a burst has been quiet for this long
+pub const QUIET: Duration = Duration::from_millis(100);
+/// but never delay a refresh by more than this, even if events keep coming
+pub const MAX_DELAY: Duration = Duration::from_secs(1);
+/// Poll this often at most while an event source is live, in case it missed something
+pub const SAFETY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Coalesces a burst of events into a single refresh
+#[derive(Debug, Default)]
+pub struct Debounce {
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Debounce {
+    pub fn event(&mut self, at: Instant) {
+        self.first.get_or_insert(at);
+        self.last = Some(at);
+    }
+
+    /// Whether the pending burst should be refreshed for at `now`, clearing it if so
+    pub fn fire(&mut self, now: Instant) -> bool {
+        let (Some(first), Some(last)) = (self.first, self.last) else {
+            return false;
+        };
+        let due = now.saturating_duration_since(last) >= QUIET
+            || now.saturating_duration_since(first) >= MAX_DELAY;
+        if due {
+            *self = Self::default();
+        }
+        due
+    }
+}
+
+pub enum Source {
+    /// A long running command printing a line per change, only lines passing the filter
+    /// count
+    Lines {
+        program: String,
+        args: Vec<String>,
+        filter: Box<dyn Fn(&str) -> bool + Send>,
+    },
+    /// Volume changes of the default sink
+    Sink,
+    /// Brightness changes of these `/sys/class/backlight` devices
+    Backlight(Vec<String>),
+    /// Link changes reported over rtnetlink
+    Netlink,
+    /// Binding mode changes from the sway or i3 IPC socket
+    SwayModes,
+    /// Saves of this file, including editors that write a new file and rename it over the old
+    /// one, which is why the directory is watched rather than the file
+    Inotify(PathBuf),
+}
+
+/// The event source for the widget, `None` if it can only be polled
+pub(crate) fn source(command: &Command) -> Option<Source> {
+    match command {
+        Command::SinkVolume { .. } => Some(Source::Sink),
+        Command::AppVolume => Some(Source::Lines {
+            program: "pactl".to_string(),
+            args: vec!["subscribe".to_string()],
+            // `Event 'new' on sink-input #42`, also sent for volume changes and removal
+            filter: Box::new(|line| line.contains(" sink-input ")),
+        }),
+        Command::Battery { device_path, .. } => {
+            let device_path = device_path.clone()?;
+            Some(Source::Lines {
+                program: "upower".to_string(),
+                args: vec!["--monitor".to_string()],
+                filter: Box::new(move |line| line.contains(&device_path)),
+            })
+        }
+        Command::Brightness => {
+            let devices = brightness::devices();
+            (!devices.is_empty()).then_some(Source::Backlight(devices))
+        }
+        Command::Network { .. } => Some(Source::Netlink),
+        Command::SwayMode { .. } => Some(Source::SwayModes),
+        _ => None,
+    }
+}
+
+/// Run `source` on a background thread, setting [`CHANGED`] on every event
+pub fn spawn(mut source: Source, system: &System) {
+    if let Source::Lines { program, .. } = &mut source {
+        *program = system.tools.resolve(program).to_string();
+    }
+    let system = system.clone();
+    LIVE.store(true, Ordering::SeqCst);
+    std::thread::spawn(move || {
+        listen(source, system, || CHANGED.store(true, Ordering::SeqCst));
+        LIVE.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Watch the config file at `path` on a background thread, setting [`CONFIG_CHANGED`] on
+/// every save. Unlike [`spawn`] this leaves [`LIVE`] alone, as the widget still needs polling
+pub fn watch_config(path: PathBuf, system: &System) {
+    let system = system.clone();
+    std::thread::spawn(move || {
+        listen(Source::Inotify(path), system, || {
+            CONFIG_CHANGED.store(true, Ordering::SeqCst);
+            CHANGED.store(true, Ordering::SeqCst);
+        })
+    });
+}
+
+/// Call `notify` on every event of `source` until it stops
+fn listen(source: Source, system: System, notify: impl Fn() + Copy + Send + 'static) {
+    match source {
+        Source::Lines {
+            program,
+            args,
+            filter,
+        } => watch_lines(&program, &args, filter, notify),
+        Source::Sink => {
+            if let Ok(watcher) = pulseaudio::watch(system, move |_| notify()) {
+                let _ = watcher.join();
+            }
+        }
+        Source::Backlight(devices) => {
+            let watchers = devices
+                .iter()
+                .filter_map(|device| brightness::watch(device, move |_| notify()).ok())
+                .collect::<Vec<_>>();
+            for watcher in watchers {
+                let _ = watcher.join();
+            }
+        }
+        Source::Netlink => watch_links(notify),
+        Source::SwayModes => {
+            let _ = sway::watch_modes(notify);
+        }
+        Source::Inotify(path) => watch_file(&path, notify),
+    }
+}
+
+fn watch_lines(
+    program: &str,
+    args: &[String],
+    filter: Box<dyn Fn(&str) -> bool>,
+    notify: impl Fn(),
+) {
+    let Ok(mut child) = std::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return;
+    };
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(line) if filter(&line) => notify(),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn watch_links(notify: impl Fn()) {
+    // SAFETY: plain syscall, the fd is closed through the File below
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return;
+    }
+    // SAFETY: fd is a fresh descriptor owned by nothing else
+    let mut socket = unsafe { <std::fs::File as std::os::fd::FromRawFd>::from_raw_fd(fd) };
+    // SAFETY: sockaddr_nl is plain data, all zeroes is a valid value
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = libc::RTMGRP_LINK as u32;
+    // SAFETY: addr outlives the call and the length matches its type
+    let bound = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if bound < 0 {
+        return;
+    }
+    let mut buf = [0; 8192];
+    while matches!(socket.read(&mut buf), Ok(n) if n > 0) {
+        notify();
+    }
+}
+
+fn watch_file(path: &Path, notify: impl Fn()) {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return;
+    };
+    // a bare file name is in the current directory
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    // SAFETY: plain syscall, the fd is closed through the File below
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return;
+    }
+    // SAFETY: fd is a fresh descriptor owned by nothing else
+    let mut inotify = unsafe { <std::fs::File as std::os::fd::FromRawFd>::from_raw_fd(fd) };
+    let Ok(c_dir) = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes()) else {
+        return;
+    };
+    let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_DELETE;
+    // SAFETY: c_dir is a valid C string for the duration of the call
+    if unsafe { libc::inotify_add_watch(fd, c_dir.as_ptr(), mask) } < 0 {
+        return;
+    }
+    let mut buf = [0; 4096];
+    while let Ok(n @ 1..) = inotify.read(&mut buf) {
+        if inotify_names(&buf[..n]).contains(&name.as_encoded_bytes()) {
+            notify();
+        }
+    }
+}
+
+/// The file names of the `struct inotify_event`s in `buf`, each padded with nul bytes
+fn inotify_names(buf: &[u8]) -> Vec<&[u8]> {
+    let header = std::mem::size_of::<libc::inotify_event>();
+    let mut names = Vec::new();
+    let mut rest = buf;
+    while rest.len() >= header {
+        // `len` is the last field of the header
+        let len = u32::from_ne_bytes(rest[header - 4..header].try_into().unwrap()) as usize;
+        let Some(name) = rest.get(header..header + len) else {
+            break;
+        };
+        let end = name.iter().position(|&byte| byte == 0).unwrap_or(len);
+        names.push(&name[..end]);
+        rest = &rest[header + len..];
+    }
+    names
+}
+
+/// Sleep until the next update is due: `interval` has passed (stretched to
+/// [`SAFETY_INTERVAL`] while an event source is live), a debounced burst of events is over,
+/// SIGUSR1 asked for a refresh, SIGUSR2 toggled hiding or a quit signal arrived
+pub fn wait(interval: Duration, debounce: &mut Debounce) {
+    let start = Instant::now();
+    loop {
+        if signal::REFRESH.swap(false, Ordering::SeqCst)
+            || signal::TOGGLE_HIDDEN.load(Ordering::SeqCst)
+            || signal::QUIT.load(Ordering::SeqCst)
+        {
+            return;
+        }
+        let now = Instant::now();
+        if CHANGED.swap(false, Ordering::SeqCst) {
+            debounce.event(now);
+        }
+        if debounce.fire(now) {
+            return;
+        }
+        let limit = if LIVE.load(Ordering::SeqCst) {
+            interval.max(SAFETY_INTERVAL)
+        } else {
+            interval
+        };
+        let elapsed = now - start;
+        if elapsed >= limit {
+            return;
+        }
+        std::thread::sleep((limit - elapsed).min(Duration::from_millis(50)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn fires_once_a_burst_goes_quiet() {
+        let start = Instant::now();
+        let mut debounce = Debounce::default();
+        for at in [0, 30, 60] {
+            debounce.event(start + at * MS);
+        }
+        assert!(!debounce.fire(start + 60 * MS + QUIET - MS));
+        assert!(debounce.fire(start + 60 * MS + QUIET));
+        // the burst was cleared with that refresh
+        assert!(!debounce.fire(start + 60 * MS + QUIET * 2));
+    }
+
+    #[test]
+    fn a_continuous_burst_fires_at_the_max_delay() {
+        let start = Instant::now();
+        let mut debounce = Debounce::default();
+        let step = QUIET / 2;
+        let mut at = start;
+        while at - start < MAX_DELAY {
+            debounce.event(at);
+            assert!(!debounce.fire(at), "fired after {:?}", at - start);
+            at += step;
+        }
+        debounce.event(at);
+        assert!(debounce.fire(at));
+        // the events after that start a new burst
+        debounce.event(at + step);
+        assert!(!debounce.fire(at + step * 2 - MS));
+    }
+
+    #[test]
+    fn nothing_pending_never_fires() {
+        let start = Instant::now();
+        let mut debounce = Debounce::default();
+        for later in [Duration::ZERO, QUIET, MAX_DELAY * 10] {
+            assert!(!debounce.fire(start + later));
+        }
+    }
+
+    #[test]
+    fn a_save_of_the_watched_file_is_an_event() {
+        let dir = std::env::temp_dir().join(format!("i3widgets-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let saves = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = saves.clone();
+        let watched = path.clone();
+        std::thread::spawn(move || {
+            watch_file(&watched, move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+        });
+        let saved = |count: usize| {
+            let start = Instant::now();
+            while saves.load(Ordering::SeqCst) < count && start.elapsed() < Duration::from_secs(2) {
+                std::thread::sleep(10 * MS);
+            }
+            saves.load(Ordering::SeqCst) >= count
+        };
+        // give the watch time to be set up before the first write
+        std::thread::sleep(100 * MS);
+        std::fs::write(&path, "a").unwrap();
+        assert!(saved(1));
+        // the way editors save, and a write to another file in the directory
+        std::fs::write(dir.join("other"), "a").unwrap();
+        std::fs::write(dir.join("config.toml.tmp"), "b").unwrap();
+        std::fs::rename(dir.join("config.toml.tmp"), &path).unwrap();
+        assert!(saved(2));
+        std::thread::sleep(100 * MS);
+        assert_eq!(saves.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_refresh_flag_ends_the_wait() {
+        signal::REFRESH.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        wait(Duration::from_secs(30), &mut Debounce::default());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(!signal::REFRESH.load(Ordering::SeqCst));
+    }
+}
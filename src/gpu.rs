@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, process::CommandSource, Color, Span, Style, Widget, TABULAR_NUMS};
+
+pub struct NvidiaFanInfo {
+    pub speed_pct: u8,
+}
+
+impl Widget for NvidiaFanInfo {
+    fn render(&self, style: &Style) -> String {
+        format!(
+            "{icon} {value}{pct}",
+            icon = style.icon("gpu_fan", "fan"),
+            value = pango!(
+                self.speed_pct,
+                color = style.color(
+                    "gpu_fan.pct",
+                    style.threshold_color(self.speed_pct.into(), 60, 85),
+                ),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            pct = style.unit("gpu_fan", "%"),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new().field("speed_pct", self.speed_pct)
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(format!("{}{}", self.speed_pct, style.unit("gpu_fan", "%")))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        let color = style.threshold_color(self.speed_pct.into(), 60, 85);
+        (color != style.palette().primary).then_some(color)
+    }
+}
+
+/// Fan speed of the first GPU reported by `nvidia-smi`
+pub fn nvidia_fan(source: &dyn CommandSource) -> Result<NvidiaFanInfo> {
+    parse_fan(&source.output(
+        "nvidia-smi",
+        &["--query-gpu=fan.speed", "--format=csv,noheader,nounits"],
+    )?)
+}
+
+/// Parse the output of `nvidia-smi --query-gpu=fan.speed --format=csv,noheader,nounits`
+pub fn parse_fan(output: &str) -> Result<NvidiaFanInfo> {
+    let speed_pct = output
+        .lines()
+        .next()
+        .ok_or_else(|| anyhow!("`nvidia-smi` output is invalid"))?
+        .trim()
+        .parse::<u8>()
+        .context("parsing `nvidia-smi` fan speed")?;
+    Ok(NvidiaFanInfo { speed_pct })
+}
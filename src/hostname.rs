@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+
+use crate::{json, Span, Style, Widget};
+
+pub struct Hostname {
+    pub name: String,
+}
+
+impl Widget for Hostname {
+    fn render(&self, style: &Style) -> String {
+        format!(
+            "{} {}",
+            style.icon("hostname", "host"),
+            pango!(
+                self.name,
+                color = style.color("hostname.name", style.palette().primary),
+                weight = "bold",
+            ),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new().field("hostname", &self.name)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(short_hostname(&self.name).to_string())
+    }
+}
+
+/// `laptop` out of `laptop.example.com`
+pub fn short_hostname(fqdn: &str) -> &str {
+    fqdn.split('.').next().unwrap_or(fqdn)
+}
+
+pub fn hostname(short: bool) -> Result<Hostname> {
+    let name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .context("reading /proc/sys/kernel/hostname")?;
+    let name = name.trim();
+    let name = if short { short_hostname(name) } else { name };
+    Ok(Hostname {
+        name: name.to_string(),
+    })
+}
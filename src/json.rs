@@ -0,0 +1,340 @@
+use std::fmt::Display;
+
+/// Quote and escape a string as a JSON string literal
+pub fn string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A value that can be written as JSON
+pub trait ToJson {
+    fn to_json(&self) -> String;
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> String {
+        (**self).to_json()
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> String {
+        string(self)
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> String {
+        string(self)
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> String {
+        self.to_string()
+    }
+}
+
+macro_rules! impl_to_json_number {
+    ($($ty: ty),*) => {
+        $(impl ToJson for $ty {
+            fn to_json(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+impl_to_json_number!(u8, u32, u64, i32, i64, usize);
+
+impl ToJson for f64 {
+    fn to_json(&self) -> String {
+        if self.is_finite() {
+            self.to_string()
+        } else {
+            "null".to_string()
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> String {
+        match self {
+            Some(value) => value.to_json(),
+            None => "null".to_string(),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> String {
+        let items = self.iter().map(ToJson::to_json).collect::<Vec<_>>();
+        format!("[{}]", items.join(","))
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> String {
+        self.as_slice().to_json()
+    }
+}
+
+/// The string value of the first `"key": "..."` in `text`, for picking a field out of a small
+/// reply without a full parser. `None` if it's missing or not a string.
+pub fn string_field(text: &str, key: &str) -> Option<String> {
+    let needle = string(key);
+    let mut rest = text;
+    loop {
+        let start = rest.find(&needle)? + needle.len();
+        rest = &rest[start..];
+        if let Some(value) = rest.trim_start().strip_prefix(':') {
+            return parse_string(value.trim_start());
+        }
+    }
+}
+
+/// Decode the JSON string literal at the start of `text`
+pub fn parse_string(text: &str) -> Option<String> {
+    let mut chars = text.strip_prefix('"')?.chars();
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'u' => {
+                    let hex = chars.by_ref().take(4).collect::<String>();
+                    out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+/// A JSON object whose fields keep their insertion order, so the output is stable
+#[derive(Default)]
+pub struct Object {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, key: &'static str, value: impl ToJson) -> Self {
+        self.fields.push((key, value.to_json()));
+        self
+    }
+
+    /// The fields in insertion order, with their values as JSON
+    pub fn fields(&self) -> &[(&'static str, String)] {
+        &self.fields
+    }
+
+    /// Add the field only when `value` is present
+    pub fn optional(self, key: &'static str, value: Option<impl ToJson>) -> Self {
+        match value {
+            Some(value) => self.field(key, value),
+            None => self,
+        }
+    }
+}
+
+impl ToJson for Object {
+    fn to_json(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", string(key), value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Widget;
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    /// The JSON value at the start of `text` and what follows it, just enough of a parser to
+    /// read the output back
+    fn parse(text: &str) -> Option<(Value, &str)> {
+        let text = text.trim_start();
+        let literal = |word: &str, value| text.strip_prefix(word).map(|rest| (value, rest));
+        match text.chars().next()? {
+            'n' => literal("null", Value::Null),
+            't' => literal("true", Value::Bool(true)),
+            'f' => literal("false", Value::Bool(false)),
+            '"' => {
+                let mut escaped = false;
+                let end = text.char_indices().skip(1).find_map(|(i, c)| {
+                    match (escaped, c) {
+                        (false, '"') => return Some(i + 1),
+                        (false, '\\') => escaped = true,
+                        _ => escaped = false,
+                    }
+                    None
+                })?;
+                Some((Value::String(parse_string(text)?), &text[end..]))
+            }
+            open @ ('[' | '{') => {
+                let close = if open == '[' { ']' } else { '}' };
+                let mut items = Vec::new();
+                let mut rest = text[1..].trim_start();
+                if let Some(after) = rest.strip_prefix(close) {
+                    let value = match open {
+                        '[' => Value::Array(Vec::new()),
+                        _ => Value::Object(Vec::new()),
+                    };
+                    return Some((value, after));
+                }
+                loop {
+                    let (key, after) = match open {
+                        '[' => (None, rest),
+                        _ => match parse(rest)? {
+                            (Value::String(key), after) => {
+                                (Some(key), after.trim_start().strip_prefix(':')?)
+                            }
+                            _ => return None,
+                        },
+                    };
+                    let (value, after) = parse(after)?;
+                    items.push((key, value));
+                    let after = after.trim_start();
+                    let next = after.chars().next()?;
+                    rest = &after[1..];
+                    if next == close {
+                        break;
+                    } else if next != ',' {
+                        return None;
+                    }
+                }
+                let value = match open {
+                    '[' => Value::Array(items.into_iter().map(|(_, value)| value).collect()),
+                    _ => Value::Object(
+                        items
+                            .into_iter()
+                            .map(|(key, value)| (key.unwrap_or_default(), value))
+                            .collect(),
+                    ),
+                };
+                Some((value, rest))
+            }
+            _ => {
+                let end = text
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(text.len());
+                Some((Value::Number(text[..end].parse().ok()?), &text[end..]))
+            }
+        }
+    }
+
+    fn object(fields: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn widgets_read_back_from_their_json() {
+        use Value::*;
+        let battery =
+            crate::parse_battery_info(include_str!("../tests/fixtures/upower-discharging.txt"))
+                .unwrap();
+        let apps = crate::pulseaudio::AppVolumes {
+            inputs: crate::pulseaudio::parse_sink_inputs(include_str!(
+                "../tests/fixtures/pactl-list-sink-inputs.txt"
+            ))
+            .unwrap(),
+        };
+        let cases: [(&dyn Widget, Value); 2] = [
+            (
+                &battery,
+                object(vec![
+                    ("state", String("discharging".to_string())),
+                    ("charge_pct", Number(74.0)),
+                    ("energy_wh", Number(38.52)),
+                    ("energy_full_wh", Number(51.95)),
+                    ("energy_full_design_wh", Number(57.0)),
+                    ("health_pct", Number(51.95 / 57.0 * 100.0)),
+                    ("wear_warning", Bool(false)),
+                    ("time_to_empty_secs", Number(18720.0)),
+                    ("time_to_full_secs", Null),
+                    ("cycle_count", Number(214.0)),
+                ]),
+            ),
+            (
+                &apps,
+                object(vec![(
+                    "inputs",
+                    Array(vec![
+                        object(vec![
+                            ("app", String("Firefox <beta>".to_string())),
+                            ("pct", Number(80.0)),
+                            ("muted", Bool(false)),
+                        ]),
+                        object(vec![
+                            ("app", String("mpv".to_string())),
+                            ("pct", Number(100.0)),
+                            ("muted", Bool(true)),
+                        ]),
+                    ]),
+                )]),
+            ),
+        ];
+        for (widget, expected) in cases {
+            let text = widget.to_json().to_string();
+            assert_eq!(parse(&text), Some((expected, "")), "{}", text);
+        }
+        // escapes come back as the characters they stand for
+        let tricky = "say \"hi\"\\\n\t\u{1}é";
+        let text = super::Object::new().field("text", tricky).to_string();
+        assert_eq!(
+            parse(&text),
+            Some((object(vec![("text", String(tricky.to_string()))]), "")),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt::Display;
+
+use crate::polybar::{self, PolybarSpan};
+
+/// A run of text with the colors it ends up with in the markup
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LemonbarSpan {
+    pub color: Option<String>,
+    pub background: Option<String>,
+    pub text: String,
+}
+
+impl From<PolybarSpan> for LemonbarSpan {
+    fn from(span: PolybarSpan) -> Self {
+        Self {
+            color: span.color,
+            background: span.background,
+            text: span.text,
+        }
+    }
+}
+
+impl LemonbarSpan {
+    /// Append the span. Lemonbar has no `%%` escape, so a literal `%{` in the text is split
+    /// with a zero width space instead.
+    pub fn write_to(&self, out: &mut String) {
+        if let Some(color) = &self.color {
+            out.push_str(&format!("%{{F{}}}", color));
+        }
+        if let Some(background) = &self.background {
+            out.push_str(&format!("%{{B{}}}", background));
+        }
+        out.push_str(&self.text.replace("%{", "%\u{200b}{"));
+        if self.background.is_some() {
+            out.push_str("%{B-}");
+        }
+        if self.color.is_some() {
+            out.push_str("%{F-}");
+        }
+    }
+}
+
+impl Display for LemonbarSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write_to(&mut out);
+        f.write_str(&out)
+    }
+}
+
+/// Split markup into runs of text with their colors, like [`polybar::spans`]
+pub fn spans(markup: &str) -> Vec<LemonbarSpan> {
+    polybar::spans(markup).into_iter().map(Into::into).collect()
+}
+
+/// The markup with its colors as lemonbar blocks
+pub fn from_pango(markup: &str) -> String {
+    let mut out = String::new();
+    for span in spans(markup) {
+        span.write_to(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_a_literal_block_start() {
+        // (markup, lemonbar)
+        let cases = [
+            ("plain", "plain"),
+            // no `%%` escape, so a trailing % can go right before a block
+            ("<span color=\"#c0caf5\">74%</span>", "%{F#c0caf5}74%%{F-}"),
+            (
+                "50%<span color=\"#f7768e\">hot</span>",
+                "50%%{F#f7768e}hot%{F-}",
+            ),
+            ("a %{F#fff} b", "a %\u{200b}{F#fff} b"),
+            (
+                "<span color=\"#c0caf5\" background=\"#1a1b26\">%{B-}</span>",
+                "%{F#c0caf5}%{B#1a1b26}%\u{200b}{B-}%{B-}%{F-}",
+            ),
+            ("&lt;%{&gt;", "<%\u{200b}{>"),
+        ];
+        for (markup, lemonbar) in cases {
+            assert_eq!(from_pango(markup), lemonbar, "{:?}", markup);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, Span, Style, Widget};
+
+/// Lid switches live under a firmware-specific name, `LID` or `LID0` usually
+const ACPI_DIR: &str = "/proc/acpi/button/lid";
+
+pub struct Lid {
+    pub open: bool,
+}
+
+impl Lid {
+    fn state_name(&self) -> &'static str {
+        if self.open {
+            "open"
+        } else {
+            "closed"
+        }
+    }
+}
+
+impl Widget for Lid {
+    fn render(&self, style: &Style) -> String {
+        let color = if self.open {
+            style.color("lid.state", style.theme().green())
+        } else {
+            style.color("lid.state", style.theme().red())
+        };
+        format!(
+            "{} {}",
+            style.icon("lid-state", "lid"),
+            pango!(self.state_name(), color = color, weight = "bold"),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("state", self.state_name())
+            .field("open", self.open)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.state_name().to_string())
+    }
+}
+
+/// `open` out of `state:      open`
+pub fn parse_state(text: &str) -> Result<bool> {
+    let state = text
+        .trim()
+        .strip_prefix("state:")
+        .map(str::trim)
+        .ok_or_else(|| anyhow!("lid state is invalid: {}", text.trim()))?;
+    match state {
+        "open" => Ok(true),
+        "closed" => Ok(false),
+        other => Err(anyhow!("unknown lid state `{other}`")),
+    }
+}
+
+pub fn state() -> Result<Lid> {
+    let switch = std::fs::read_dir(ACPI_DIR)
+        .with_context(|| format!("reading {ACPI_DIR}, is this a laptop?"))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join("state"))
+        .find(|path| path.exists())
+        .ok_or_else(|| anyhow!("no lid switch under {ACPI_DIR}"))?;
+    let text = std::fs::read_to_string(&switch)
+        .with_context(|| format!("reading {}", switch.display()))?;
+    Ok(Lid {
+        open: parse_state(&text)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_acpi_lid_state() {
+        // (contents of the state file, open)
+        let cases = [
+            ("state:      open\n", Some(true)),
+            ("state:      closed\n", Some(false)),
+            ("state:open", Some(true)),
+            ("", None),
+            ("status:      open\n", None),
+            ("state:      unknown\n", None),
+            ("state:      Open\n", None),
+        ];
+        for (text, open) in cases {
+            assert_eq!(parse_state(text).ok(), open, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::{
+    fmt::Arguments,
+    io::Write,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    /// Fetch and tool timings, and cache hits
+    Debug,
+    /// Also the output of every tool
+    Trace,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// One line like `debug cpu: fetched in 502.1ms`, use [`log!`] instead
+pub fn write(level: Level, target: &str, message: Arguments) {
+    let _ = writeln!(
+        std::io::stderr().lock(),
+        "{} {}: {}",
+        level.name(),
+        target,
+        message
+    );
+}
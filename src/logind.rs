@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Result};
+
+use crate::{json, process::CommandSource, Color, Span, Style, Widget, TABULAR_NUMS};
+
+pub struct Sessions {
+    /// Owner of each active session, one entry per session
+    pub users: Vec<String>,
+    pub show_users: bool,
+}
+
+impl Sessions {
+    /// Distinct users with an active session, in first-seen order
+    pub fn distinct_users(&self) -> Vec<&str> {
+        let mut users = Vec::new();
+        for user in &self.users {
+            if !users.contains(&user.as_str()) {
+                users.push(user.as_str());
+            }
+        }
+        users
+    }
+
+    fn color(&self, style: &Style) -> Color {
+        if self.distinct_users().len() > 1 {
+            style.theme().red()
+        } else {
+            style.theme().green()
+        }
+    }
+}
+
+impl Widget for Sessions {
+    fn render(&self, style: &Style) -> String {
+        let mut text = format!(
+            "{icon} {count}",
+            icon = style.icon("sessions", "user"),
+            count = pango!(
+                self.users.len(),
+                color = style.color("sessions.count", self.color(style)),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+        );
+        if self.show_users && !self.users.is_empty() {
+            text += &pango!(
+                format!(" {}", self.distinct_users().join(",")),
+                color = style.color("sessions.users", style.palette().secondary)
+            );
+        }
+        text
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("sessions", self.users.len())
+            .field("users", self.distinct_users())
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.users.len().to_string())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        (self.distinct_users().len() > 1).then(|| style.theme().red())
+    }
+}
+
+/// Active sessions from `loginctl list-sessions`
+pub fn sessions(show_users: bool, source: &dyn CommandSource) -> Result<Sessions> {
+    let users = parse(&source.output("loginctl", &["list-sessions", "--no-legend"])?)?;
+    Ok(Sessions { users, show_users })
+}
+
+/// Parse the output of `loginctl list-sessions --no-legend` into the owner of each active
+/// session
+pub fn parse(output: &str) -> Result<Vec<String>> {
+    let mut users = Vec::new();
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        // SESSION UID USER [SEAT] [TTY] [STATE IDLE SINCE], where the state columns only
+        // exist on newer systemd and the seat and tty columns may be blank
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let user = fields
+            .get(2)
+            .ok_or_else(|| anyhow!("invalid `loginctl` line `{}`", line))?;
+        let state = fields
+            .iter()
+            .find(|field| matches!(**field, "active" | "online" | "closing" | "opening"));
+        if matches!(state, None | Some(&"active")) {
+            users.push(user.to_string());
+        }
+    }
+    Ok(users)
+}
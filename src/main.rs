@@ -1,32 +1,324 @@
-use std::{fmt::Display, time::Duration};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    process::ExitCode,
+    sync::atomic::Ordering,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context, Result};
-use chrono::{Datelike, Timelike};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
+use color::Color;
+use process::CommandSource;
 
+/// Easy to use status bar elements for i3blocks and other status bars
 #[derive(clap::Parser)]
 #[command()]
 struct Cli {
+    /// Read settings from this file instead of `$XDG_CONFIG_HOME/i3widgets/config.toml`
+    #[arg(long, global = true, value_hint = clap::ValueHint::FilePath)]
+    config: Option<std::path::PathBuf>,
+    /// Color for the dim segments, as a hex literal or a theme color name
+    #[arg(long, global = true)]
+    fg: Option<String>,
+    /// Color for the bold segments, as a hex literal or a theme color name
+    #[arg(long, global = true)]
+    accent: Option<String>,
+    #[arg(long, visible_alias = "output-format", global = true, value_enum, default_value_t = OutputFormat::Auto)]
+    output: OutputFormat,
+    #[arg(long, global = true, value_enum, default_value_t = IconSet::Emoji)]
+    icon_set: IconSet,
+    /// Pick the light or dark theme instead of guessing from `GTK_THEME`
+    #[arg(long, global = true, value_enum)]
+    appearance: Option<Appearance>,
+    /// Mix the light theme into the dark one, from 0.0 for the dark theme to 1.0 for the light
+    /// one, e.g. to fade between them around dusk
+    #[arg(long, global = true, value_parser = parse_fraction, conflicts_with = "appearance")]
+    theme_blend: Option<f64>,
+    /// Override the color of one widget part, e.g. `battery.pct=#ff0000` or `battery.pct=red`,
+    /// can be repeated
+    #[arg(long = "override-color", global = true, value_parser = parse_override)]
+    override_colors: Vec<(String, String)>,
+    /// Run a tool from somewhere other than `PATH`, e.g. `upower=/opt/upower/bin/upower`, can be
+    /// repeated
+    #[arg(long = "tool-path", global = true, value_parser = parse_tool_path)]
+    tool_paths: Vec<(String, String)>,
+    /// Seconds to wait for external tools before killing them
+    #[arg(
+        long,
+        global = true,
+        default_value_t = process::DEFAULT_TIMEOUT.as_secs_f64(),
+        value_parser = parse_timeout
+    )]
+    cmd_timeout: f64,
+    /// Keep running, printing a new line every this many seconds or on SIGUSR1, for i3blocks'
+    /// `interval=persist`
+    #[arg(long, global = true, value_parser = parse_interval)]
+    interval: Option<f64>,
+    /// Reuse tool output up to this many milliseconds old, shared between concurrent runs
+    #[arg(long, global = true)]
+    cache: Option<u64>,
+    /// Fill the block with this color in `--output i3bar` mode, as a hex literal or a theme
+    /// color name
+    #[arg(long, global = true)]
+    background_color: Option<String>,
+    /// Draw a border of this color around the block in `--output i3bar` mode, as a hex literal or
+    /// a theme color name
+    #[arg(long, global = true)]
+    border_color: Option<String>,
+    /// Width of every side of the border in pixels with `--output i3bar`, 1 unless set
+    #[arg(long, global = true)]
+    border_px: Option<u32>,
+    /// Prepend an empty spacer block in `--output i3bar` mode
+    #[arg(long, global = true)]
+    align_right: bool,
+    /// Pad the text with spaces to at least this many characters, so the bar doesn't jitter as
+    /// the values change width
+    #[arg(long, global = true, default_value_t = 0)]
+    min_width: usize,
+    /// Where the text goes when `--min-width` pads it
+    #[arg(long, global = true, value_enum, default_value_t = Align::Left)]
+    align: Align,
+    /// Leave out the `%` after percentages, so the block is just the icon and the number
+    #[arg(long, global = true)]
+    hide_label: bool,
+    /// Leave out the icons, e.g. for fonts without the emoji. Wins over `--icon-set`
+    #[arg(long, global = true)]
+    no_icons: bool,
+    /// Send the widget's details with `notify-send` when the block is right clicked, i.e. when
+    /// i3blocks sets `BLOCK_BUTTON=3`
+    #[arg(long, global = true)]
+    show_detail: bool,
+    /// Write `%` and the byte units the way this locale does, e.g. `zh_CN` for `％` and `兆字节`.
+    /// `auto` takes the locale from `LC_ALL`, `LC_MESSAGES` or `LANG`
+    #[arg(long, global = true)]
+    unit_locale: Option<String>,
+    /// Split supported widgets over two rows for stacked bars, value on top and detail below
+    #[arg(long, global = true, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    lines: u8,
+    /// Print an empty block for widgets with nothing to show, like `virsh-active` with no VMs
+    /// running or `app-volume` with nothing playing
+    #[arg(long, global = true)]
+    hide_zero: bool,
+    /// Print an empty block instead of a dim `n/a` when the widget's tool isn't installed
+    #[arg(long, global = true)]
+    quiet: bool,
+    /// Exit with an error when fetching fails instead of rendering a ⚠ block, for scripting
+    #[arg(long, global = true)]
+    strict: bool,
+    /// Serve the numbers of every update in the Prometheus text format at `/metrics` on this
+    /// address, e.g. `127.0.0.1:9632`, with `--interval`
+    #[arg(long, global = true)]
+    metrics_addr: Option<std::net::SocketAddr>,
+    /// How much to log to stderr, `warn` unless set here or in `I3WIDGETS_LOG`
+    #[arg(long, global = true, value_enum)]
+    log_level: Option<logging::Level>,
     #[command(subcommand)]
     command: Command,
 }
 
+/// Expand `\u{XXXX}` and `\uXXXX` escapes, for glyphs that are awkward to type in a config
+fn parse_icon(s: &str) -> Result<String> {
+    let mut icon = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("\\u") {
+        icon.push_str(&rest[..start]);
+        let escape = &rest[start + 2..];
+        let (hex, len) = match escape.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| anyhow!("unterminated escape in `{}`", s))?;
+                (&braced[..end], end + 2)
+            }
+            None => {
+                let end = escape
+                    .find(|c: char| !c.is_ascii_hexdigit())
+                    .unwrap_or(escape.len())
+                    .min(4);
+                (&escape[..end], end)
+            }
+        };
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| anyhow!("invalid escape `\\u{}` in `{}`", hex, s))?;
+        icon.push(c);
+        rest = &escape[len..];
+    }
+    icon.push_str(rest);
+    Ok(icon)
+}
+
+impl Command {
+    /// The subcommand name, for prefixing log lines
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Battery { .. } => "battery",
+            Command::Time { .. } => "time",
+            Command::TimeZh { .. } => "time-zh",
+            Command::Memory { .. } => "memory",
+            Command::Disk { .. } => "disk",
+            Command::DiskTree { .. } => "disk-tree",
+            Command::Network { .. } => "network",
+            Command::TcpConnections { .. } => "tcp-connections",
+            Command::SinkVolume { .. } => "sink-volume",
+            Command::AppVolume => "app-volume",
+            Command::Brightness => "brightness",
+            Command::VirshActive { .. } => "virsh-active",
+            Command::Cpu { .. } => "cpu",
+            Command::GpuFan => "gpu-fan",
+            Command::Countdown { .. } => "countdown",
+            Command::Hostname { .. } => "hostname",
+            Command::LidState => "lid-state",
+            Command::PrintTheme => "print-theme",
+            Command::Doctor => "doctor",
+            Command::ColorTest => "color-test",
+            Command::List => "list",
+            Command::Combo { .. } => "combo",
+            Command::Completions { .. } => "completions",
+            Command::Man { .. } => "man",
+            Command::SoundCards { .. } => "sound-cards",
+            Command::Sessions { .. } => "sessions",
+            Command::Wireguard { .. } => "wireguard",
+            Command::SwayMode { .. } => "sway-mode",
+        }
+    }
+
+    /// The icon shown in place of the widget when fetching it fails
+    fn fallback_icon(&self) -> &'static str {
+        match self {
+            Command::Battery { .. } => "charging",
+            Command::Memory { .. } => "memory",
+            Command::Disk { .. } | Command::DiskTree { .. } => "disk",
+            Command::Network { .. } => "rx",
+            Command::TcpConnections { .. } => "network",
+            Command::SinkVolume { .. } => "volume_muted",
+            Command::AppVolume => "volume_muted",
+            Command::Brightness => "brightness_0",
+            Command::VirshActive { .. } => "vm",
+            Command::Cpu { .. } => "cpu",
+            Command::GpuFan => "fan",
+            Command::Countdown { .. } => "countdown",
+            Command::Hostname { .. } => "host",
+            Command::LidState => "lid",
+            Command::Sessions { .. } => "user",
+            Command::Wireguard { .. } => "vpn",
+            Command::SwayMode { .. } => "error",
+            Command::SoundCards { .. } => "sound_card",
+            Command::Time { .. } | Command::TimeZh { .. } => "error",
+            Command::PrintTheme
+            | Command::Doctor
+            | Command::ColorTest
+            | Command::List
+            | Command::Combo { .. }
+            | Command::Completions { .. }
+            | Command::Man { .. } => "error",
+        }
+    }
+}
+
+/// The longest `--cmd-timeout` or `--interval`, far beyond any use yet short enough that a
+/// deadline this far out can't overflow an `Instant`
+const MAX_SECONDS: f64 = 86400.0;
+
+/// The shortest `--interval`, anything faster only keeps a core busy redrawing
+const MIN_INTERVAL: f64 = 0.1;
+
+fn parse_timeout(s: &str) -> Result<f64> {
+    seconds(s.parse()?, 0.0)
+}
+
+fn parse_interval(s: &str) -> Result<f64> {
+    seconds(s.parse()?, MIN_INTERVAL)
+}
+
+/// `secs` if it's from `min` to [`MAX_SECONDS`], which rules out NaN too, as
+/// `Duration::from_secs_f64` panics on what's left
+fn seconds(secs: f64, min: f64) -> Result<f64> {
+    if !(min..=MAX_SECONDS).contains(&secs) {
+        return Err(anyhow!("expected seconds from {} to {}", min, MAX_SECONDS));
+    }
+    Ok(secs)
+}
+
+fn parse_fraction(s: &str) -> Result<f64> {
+    let value = s.parse::<f64>()?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(anyhow!("expected a number from 0.0 to 1.0"));
+    }
+    Ok(value)
+}
+
+/// Split `<widget>.<part>=<color>`, the color is resolved once the theme is known
+fn parse_override(s: &str) -> Result<(String, String)> {
+    let (key, color) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected <widget>.<part>=<color>"))?;
+    Ok((key.to_string(), color.to_string()))
+}
+
+fn parse_tool_path(s: &str) -> Result<(String, String)> {
+    let (program, path) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected <program>=<path>"))?;
+    Ok((program.to_string(), path.to_string()))
+}
+
 #[derive(clap::Subcommand)]
 enum Command {
+    /// Charge and time estimate from UPower
     #[command()]
     Battery {
+        /// Falls back to `device_path` in the `[battery]` section of the config
         #[arg(long)]
-        device_path: String,
+        device_path: Option<String>,
+        /// Same as `--log-level debug`
         #[arg(long, default_value = "false")]
         debug: bool,
+        /// Same as `--interval`, kept for existing configs
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..=MAX_SECONDS as u64))]
+        watch: Option<u64>,
+        /// Show the median of this many recent readings so the percentage doesn't flicker
+        #[arg(long, default_value = "1")]
+        smooth: usize,
+        /// Show the power profile from `powerprofilesctl` after the time, if it is installed
+        #[arg(long)]
+        show_profile: bool,
+        /// Show the number of charge cycles after the time, for batteries that report it
+        #[arg(long)]
+        show_cycles: bool,
+        /// Put a warning marker before the charge once the battery holds less than this
+        /// percentage of its design capacity
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..=100))]
+        wear_warn: Option<u32>,
+        /// Icon while charging, `\u{f0e7}`-style escapes are expanded
+        #[arg(long, value_parser = parse_icon)]
+        battery_icon_charging: Option<String>,
+        /// Icon below 20%
+        #[arg(long, value_parser = parse_icon)]
+        battery_icon_low: Option<String>,
+        /// Icon otherwise
+        #[arg(long, value_parser = parse_icon)]
+        battery_icon_normal: Option<String>,
     },
+    /// The local date and time
     #[command()]
     Time {
         #[arg(long, default_value = "false")]
         seconds: bool,
         #[arg(long, default_value = "true")]
         date: bool,
+        /// Format the date and time as the `LC_TIME` locale does, e.g. `14/10/26 17:05` for en_GB
+        #[arg(long)]
+        locale_time: bool,
+        /// Show the time as `HH:MM:SS.mmm`, best paired with a sub-second `--interval`
+        #[arg(long)]
+        millis: bool,
     },
+    /// The local date and time in Chinese
     #[command()]
     TimeZh {
         #[arg(long, default_value = "false")]
@@ -35,594 +327,4701 @@ enum Command {
         date: bool,
         #[arg(long, default_value = "true")]
         am_pm: bool,
+        /// Put the year of this era before the date, e.g. `民国115年` for 2026
+        #[arg(long, value_enum)]
+        era: Option<Era>,
+    },
+    /// Used and total memory from `free`
+    #[command()]
+    Memory {
+        /// Use decimal (MB, 1000) instead of binary (MiB, 1024) units
+        #[arg(long)]
+        si: bool,
+        /// Count buffers and the page cache as used, i.e. everything but `MemFree`
+        #[arg(long)]
+        include_buffers_cache: bool,
+        /// Show a sparkline of this many recent readings after the value
+        #[arg(long, default_value_t = 0)]
+        history: usize,
+        /// Show RAM and swap as two bars this many characters wide instead of the numbers
+        #[arg(long, default_value_t = 0)]
+        bars: usize,
+        /// Show the used and total huge pages after the memory, on systems that reserve any
+        #[arg(long)]
+        show_hugepages: bool,
+        /// Show swap instead of RAM, which `--hide-zero` blanks while none of it is in use
+        #[arg(long, conflicts_with = "bars")]
+        swap: bool,
+    },
+    /// Free space on a filesystem
+    #[command()]
+    Disk {
+        #[arg(long, default_value = "/")]
+        mount: String,
+        /// Use decimal (GB, 1000) instead of binary (GiB, 1024) units
+        #[arg(long)]
+        si: bool,
+    },
+    /// Usage of every mounted filesystem as a small bar, one row per mount with `--lines`
+    #[command()]
+    DiskTree {
+        /// Leave out filesystems smaller than this many GB, which skips most pseudo-filesystems
+        #[arg(long, default_value_t = 1.0)]
+        min_size_gb: f64,
+        /// Width of each bar in characters
+        #[arg(long, default_value_t = 5)]
+        bar_width: usize,
+    },
+    /// Throughput of a network interface, or the traffic since a reference point
+    #[command()]
+    Network {
+        /// Defaults to the first interface that is up
+        #[arg(long, value_parser = network::parse_interface)]
+        interface: Option<String>,
+        /// Use decimal (GB, 1000) instead of binary (GiB, 1024) units
+        #[arg(long)]
+        si: bool,
+        /// Show the bytes transferred since the reference point instead of the rate
+        #[arg(long)]
+        total: bool,
+        /// Move the `--total` reference point to now
+        #[arg(long, requires = "total")]
+        reset: bool,
+        /// Show a sparkline of this many recent readings after the value
+        #[arg(long, default_value_t = 0)]
+        history: usize,
+    },
+    /// TCP connections from `/proc/net/tcp`, established ones and TIME_WAIT by default
+    #[command()]
+    TcpConnections {
+        /// Only count connections in this state
+        #[arg(long, value_enum)]
+        state: Option<tcp::State>,
     },
+    /// Volume of the default PulseAudio sink
     #[command()]
-    Memory,
+    SinkVolume {
+        /// Set the volume to this percentage before showing it, for keybindings
+        #[arg(long)]
+        set: Option<u32>,
+        /// `--set` never goes above this percentage
+        #[arg(long, default_value_t = 100)]
+        max_volume: u32,
+    },
+    /// Volume of each application playing through PulseAudio, e.g. `firefox:80 mpv:100`
     #[command()]
-    SinkVolume,
+    AppVolume,
+    /// Backlight brightness from `brightnessctl`
     #[command()]
     Brightness,
+    /// Running and stopped libvirt VMs
+    #[command()]
+    VirshActive {
+        /// Only list the running VMs, which is cheaper than listing them all
+        #[arg(long)]
+        running_only: bool,
+        /// Kill `virsh` after this many milliseconds and show the last VMs it listed, since it
+        /// hangs while the hypervisor is unresponsive
+        #[arg(long, default_value_t = 1000)]
+        virsh_timeout_ms: u64,
+    },
+    /// CPU usage from `/proc/stat`
+    #[command()]
+    Cpu {
+        #[arg(long, default_value = "500")]
+        sample_interval_ms: u64,
+        /// Average over this many consecutive samples
+        #[arg(long, default_value = "1")]
+        sample_count: u32,
+        /// Show every core's load, each colored by how busy it is
+        #[arg(long)]
+        per_core: bool,
+        /// Show a sparkline of this many recent readings after the value
+        #[arg(long, default_value_t = 0)]
+        history: usize,
+    },
+    /// NVIDIA GPU fan speed from `nvidia-smi`
+    #[command()]
+    GpuFan,
+    /// Sound cards with playback devices, from `aplay -l`
+    #[command()]
+    SoundCards {
+        /// List the card names after the count
+        #[arg(long)]
+        show_names: bool,
+    },
+    /// Show a swatch for every color of the active theme
+    #[command()]
+    PrintTheme,
+    /// Check which of the external tools the widgets use are installed, and their versions
+    #[command()]
+    Doctor,
+    /// Show every theme color as a labeled block on one line, to preview a theme in the bar
+    #[command()]
+    ColorTest,
+    /// List the widgets with a one line description, as a JSON array with `--output json`
     #[command()]
-    VirshActive,
+    List,
+    /// Print a completion script for `shell`
+    #[command(hide = true)]
+    Completions { shell: completions::Shell },
+    /// Write man pages for the program and every widget to `--out-dir`
+    #[command(hide = true)]
+    Man {
+        #[arg(long, value_hint = clap::ValueHint::DirPath)]
+        out_dir: std::path::PathBuf,
+    },
+    /// Several widgets joined into one block, each with its settings from the config
+    #[command()]
+    Combo {
+        /// Comma separated widget names in display order, e.g. `cpu,memory,time`
+        #[arg(long, value_delimiter = ',', required = true)]
+        widgets: Vec<String>,
+        #[arg(long, default_value = " · ")]
+        separator: String,
+        /// The widgets as parsed commands, filled in by main
+        #[arg(skip)]
+        commands: Vec<Command>,
+    },
+    /// Active login sessions from logind
+    #[command()]
+    Sessions {
+        /// List the users owning the sessions after the count
+        #[arg(long)]
+        show_users: bool,
+    },
+    /// WireGuard peers with a recent handshake, from `wg show`
+    #[command()]
+    Wireguard {
+        /// The WireGuard interface, e.g. `wg0`
+        #[arg(long)]
+        interface: String,
+    },
+    /// The current binding mode of sway or i3, over the IPC socket
+    #[command()]
+    SwayMode {
+        /// Shown in the `default` mode, which is otherwise left blank
+        #[arg(long)]
+        default_text: Option<String>,
+    },
+    /// Time left until a target time
+    #[command()]
+    Countdown {
+        /// Target as an RFC3339 timestamp or `HH:MM` (next occurrence)
+        #[arg(long)]
+        until: String,
+        /// Turn red when fewer than this many minutes remain
+        #[arg(long, default_value = "5")]
+        warn_minutes: u64,
+        /// Shown instead of `00:00` once the target has passed
+        #[arg(long)]
+        done_text: Option<String>,
+    },
+    /// The machine's hostname
+    #[command()]
+    Hostname {
+        /// Only the first label, e.g. `laptop` for `laptop.example.com`
+        #[arg(long)]
+        short_hostname: bool,
+    },
+    /// Whether the laptop lid is open or closed, from ACPI
+    #[command()]
+    LidState,
 }
 
-#[derive(Default)]
-pub struct PangoSpan {
-    pub color: Option<String>,
-    pub font_family: Option<String>,
-    pub font_size: Option<String>,
-    pub weight: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// `ansi` when stdout is a terminal and `pango` otherwise
+    Auto,
+    /// A single line of pango markup, as read by i3blocks
+    Pango,
+    /// A block of the i3bar JSON protocol
+    I3bar,
+    /// The widget's data as a JSON object, for scripting
+    Json,
+    /// i3blocks' three lines: full text, short text and the block color
+    I3blocks,
+    /// A line with polybar's `%{F#rrggbb}` color tags, for a `custom/script` module
+    Polybar,
+    /// A line with lemonbar's `%{F#rrggbb}` color blocks, for piping into `lemonbar`
+    Lemonbar,
+    /// A line with 24-bit ANSI colors, for a terminal or tmux's `status-right`
+    Ansi,
 }
 
-impl Display for PangoSpan {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<span ")?;
-        if let Some(color) = &self.color {
-            write!(f, "color=\"{}\" ", color)?;
-        }
-        if let Some(font_family) = &self.font_family {
-            write!(f, "font_family=\"{}\" ", font_family)?;
-        }
-        if let Some(font_size) = &self.font_size {
-            write!(f, "font_size=\"{}\" ", font_size)?;
-        }
-        if let Some(weight) = &self.weight {
-            write!(f, "weight=\"{}\" ", weight)?;
+impl OutputFormat {
+    /// Settles `auto` on what stdout is, given whether it's a terminal
+    fn resolve(self, terminal: bool) -> Self {
+        match self {
+            OutputFormat::Auto if terminal => OutputFormat::Ansi,
+            OutputFormat::Auto => OutputFormat::Pango,
+            format => format,
         }
-        write!(f, ">")?;
-        Ok(())
     }
 }
 
-macro_rules! pango {
-    ($text: expr, $($key:ident = $value:expr),* $(,)?) => {{
-        let span = PangoSpan {
-            $($key: Some($value.to_string()),)*
-            ..PangoSpan::default()
-        };
-        let mut s = span.to_string();
-        s.push_str(&format!("{}</span>", $text));
-        s
-    }};
+/// A calendar era counted from a different year than the Gregorian one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Era {
+    /// The Republic of China calendar, from 1912
+    Minguo,
+    /// The Buddhist calendar, from 543 BC
+    Buddhist,
 }
 
-pub struct Theme {
-    pub foreground: String,
-    pub background: String,
-    pub black: String,
-    pub red: String,
-    pub green: String,
-    pub yellow: String,
-    pub blue: String,
-    pub magenta: String,
-    pub cyan: String,
-    pub white: String,
-    pub index_16: String,
-    pub index_17: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Align {
+    Left,
+    Right,
+    Center,
 }
 
-macro_rules! impl_theme_color {
-    ($name: ident) => {
-        pub fn $name(&self) -> &str {
-            &self.$name
-        }
-    };
-}
+pub mod json;
 
-impl Theme {
-    impl_theme_color!(foreground);
-    impl_theme_color!(background);
-    impl_theme_color!(black);
-    impl_theme_color!(red);
-    impl_theme_color!(green);
-    impl_theme_color!(yellow);
-    impl_theme_color!(blue);
-    impl_theme_color!(magenta);
-    impl_theme_color!(cyan);
-    impl_theme_color!(white);
-    impl_theme_color!(index_16);
-    impl_theme_color!(index_17);
+/// A Prometheus `/metrics` endpoint with the numbers from the widget's JSON output, so the values
+/// of the bar can be graphed without polling the tools a second time
+pub mod metrics;
 
-    pub fn tokyonight_normal() -> Self {
-        Self {
-            foreground: "#c0caf5".to_string(),
-            background: "#1a1b26".to_string(),
-            black: "#15161e".to_string(),
-            red: "#f7768e".to_string(),
-            green: "#9ece6a".to_string(),
-            yellow: "#e0af68".to_string(),
-            blue: "#7aa2f7".to_string(),
-            magenta: "#bb9af7".to_string(),
-            cyan: "#7dcfff".to_string(),
-            white: "#a9b1d6".to_string(),
-            index_16: "#ff9e64".to_string(),
-            index_17: "#db4b4b".to_string(),
-        }
+/// A block of the i3bar JSON protocol
+#[derive(Default)]
+pub struct BarBlock {
+    pub full_text: String,
+    pub short_text: Option<String>,
+    pub markup: Option<String>,
+    pub separator: Option<bool>,
+    /// Color behind the block
+    pub background: Option<String>,
+    /// Color of the border around the block
+    pub border: Option<String>,
+    /// Widths of the border sides in pixels, i3bar draws 1 for the ones left out
+    pub border_top: Option<u32>,
+    pub border_bottom: Option<u32>,
+    pub border_left: Option<u32>,
+    pub border_right: Option<u32>,
+}
+
+impl Display for BarBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let block = json::Object::new()
+            .field("full_text", &self.full_text)
+            .optional("short_text", self.short_text.as_ref())
+            .optional("markup", self.markup.as_ref())
+            .optional("separator", self.separator)
+            .optional("background", self.background.as_ref())
+            .optional("border", self.border.as_ref())
+            .optional("border_top", self.border_top)
+            .optional("border_bottom", self.border_bottom)
+            .optional("border_left", self.border_left)
+            .optional("border_right", self.border_right);
+        write!(f, "{}", block)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum BatteryState {
-    Charging,
-    Discharging,
-    Full,
+struct Output {
+    format: OutputFormat,
+    align_right: bool,
+    lines: u8,
+    /// Blank the block instead of printing a widget with nothing to show
+    hide_zero: bool,
+    min_width: usize,
+    align: Align,
+    /// `--background-color`, `--border-color` and `--border-px`, for `--output i3bar`
+    background: Option<Color>,
+    border: Option<Color>,
+    border_px: Option<u32>,
 }
 
-#[derive(Debug)]
-struct BatteryInfo {
-    energy_full: f64,
-    energy: f64,
-    time_to_empty_full: f64,
-    state: BatteryState,
+/// The length of the text the markup shows, in chars rather than bytes or display columns, so
+/// `中` and `🔋` count as one each
+fn visible_width(markup: &str) -> usize {
+    polybar::spans(markup)
+        .iter()
+        .map(|span| span.text.chars().count())
+        .sum()
 }
 
-impl BatteryInfo {
-    fn percentage(&self) -> i32 {
-        (self.energy / self.energy_full * 100.0) as i32
+/// The markup with the leading, trailing and repeated spaces of its text removed, the tags are
+/// left as they are
+fn collapse_spaces(markup: &str) -> String {
+    let mut out = String::with_capacity(markup.len());
+    let mut in_tag = false;
+    // where the last visible char is, if it is a space
+    let mut trailing_space = None;
+    let mut seen_text = false;
+    for c in markup.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if in_tag => {}
+            ' ' if !seen_text || trailing_space.is_some() => continue,
+            ' ' => trailing_space = Some(out.len()),
+            _ => {
+                trailing_space = None;
+                seen_text = true;
+            }
+        }
+        out.push(c);
     }
+    if let Some(i) = trailing_space {
+        out.remove(i);
+    }
+    out
+}
 
-    fn time_to_empty_full(&self) -> Duration {
-        Duration::from_secs_f64(self.time_to_empty_full * 3600.0)
+impl Output {
+    /// The full markup, with the rows of `--lines 2` joined by a newline
+    fn render(&self, widget: &dyn Widget, style: &Style) -> String {
+        widget
+            .render_lines(style, self.lines)
+            .into_iter()
+            .map(|line| self.pad(style.tidy(line)))
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 
-    fn time_to_empty_full_str(&self) -> String {
-        let duration = self.time_to_empty_full();
-        let hours = duration.as_secs() / 3600;
-        let minutes = (duration.as_secs() % 3600) / 60;
-        format!("{:02}:{:02}", hours, minutes)
+    /// Pad the markup with spaces up to `--min-width` visible chars
+    fn pad(&self, markup: String) -> String {
+        let missing = self.min_width.saturating_sub(visible_width(&markup));
+        if missing == 0 {
+            return markup;
+        }
+        let (before, after) = match self.align {
+            Align::Left => (0, missing),
+            Align::Right => (missing, 0),
+            Align::Center => (missing / 2, missing - missing / 2),
+        };
+        format!("{}{}{}", " ".repeat(before), markup, " ".repeat(after))
     }
-}
 
-fn get_battery_info(device_path: &str) -> Result<BatteryInfo> {
-    let result = std::process::Command::new("upower")
-        .arg("-i")
-        .arg(device_path)
-        .output()
-        .context("running upower")?;
-    let output = String::from_utf8(result.stdout).context("converting upower output to utf-8")?;
-    let energy_full = output
-        .lines()
-        .find(|line| line.trim_start().starts_with("energy-full:"))
-        .ok_or_else(|| anyhow!("energy-full not found"))?
-        .split_whitespace()
-        .nth(1)
-        .ok_or_else(|| anyhow!("energy-full format is invalid"))?
-        .parse::<f64>()?;
-    let energy = output
-        .lines()
-        .find(|line| line.trim_start().starts_with("energy:"))
-        .ok_or_else(|| anyhow!("energy not found"))?
-        .split_whitespace()
-        .nth(1)
-        .ok_or_else(|| anyhow!("energy format is invalid"))?
-        .parse::<f64>()?;
-    let mut time_to_line = output
-        .lines()
-        .find(|line| line.trim_start().starts_with("time to"))
-        .ok_or_else(|| anyhow!("`time to {{empty | full}}` not found"))?
-        .split_whitespace();
-    let mut time_to_empty_full = time_to_line
-        .nth(3)
-        .ok_or_else(|| anyhow!("`time to {{empty | full}}` format is invalid"))?
-        .parse::<f64>()?;
-    let time_to_empty_full_unit = time_to_line
-        .nth(0)
-        .ok_or_else(|| anyhow!("`time to {{empty | full}}` format is invalid"))?;
-    if time_to_empty_full_unit == "minutes" {
-        time_to_empty_full /= 60.0;
+    /// Print an update that blanks the block, for when it is hidden with SIGUSR2
+    fn print_empty(&self) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        self.write_empty(&mut out)?;
+        out.flush()
     }
-    let state = output
-        .lines()
-        .find(|line| line.trim_start().starts_with("state:"))
-        .ok_or_else(|| anyhow!("state not found"))?
-        .split_whitespace()
-        .nth(1)
-        .ok_or_else(|| anyhow!("state format is invalid"))
-        .map(|s| match s {
-            "charging" => BatteryState::Charging,
-            "discharging" => BatteryState::Discharging,
-            _ => BatteryState::Full,
-        })?;
-    Ok(BatteryInfo {
-        energy_full,
-        energy,
-        time_to_empty_full,
-        state,
-    })
-}
 
-struct MemoryInfo {
-    total: u64,
-    used: u64,
-}
+    fn write_empty(&self, out: &mut impl Write) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Auto
+            | OutputFormat::Pango
+            | OutputFormat::I3blocks
+            | OutputFormat::Polybar
+            | OutputFormat::Lemonbar
+            | OutputFormat::Ansi => writeln!(out)?,
+            OutputFormat::I3bar => writeln!(out, "{}", BarBlock::default())?,
+            OutputFormat::Json => writeln!(out, "{}", json::Object::new())?,
+        }
+        Ok(())
+    }
 
-impl MemoryInfo {
-    pub fn total_mib(&self) -> u64 {
-        self.total / 1024
+    /// Print one update of the widget, flushing so a reading bar sees it right away
+    fn print(&self, widget: &dyn Widget, style: &Style) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        self.write(&mut out, widget, style)?;
+        out.flush()
     }
 
-    pub fn used_mib(&self) -> u64 {
-        self.used / 1024
+    /// Write one update of the widget in the output format
+    fn write(&self, out: &mut impl Write, widget: &dyn Widget, style: &Style) -> io::Result<()> {
+        if self.hide_zero && widget.is_empty() {
+            return self.write_empty(out);
+        }
+        match self.format {
+            // resolved by `configure`
+            OutputFormat::Auto | OutputFormat::Pango => {
+                writeln!(out, "{}", self.render(widget, style))?
+            }
+            OutputFormat::I3bar => {
+                let block = BarBlock {
+                    full_text: self.render(widget, style),
+                    short_text: widget.short(style).map(|short| style.tidy(short)),
+                    markup: Some("pango".to_string()),
+                    background: self.background.map(|color| color.to_string()),
+                    border: self.border.map(|color| color.to_string()),
+                    border_top: self.border_px,
+                    border_bottom: self.border_px,
+                    border_left: self.border_px,
+                    border_right: self.border_px,
+                    ..BarBlock::default()
+                };
+                if self.align_right {
+                    // i3bar lays blocks out left to right, so an empty block without a separator
+                    // in front of ours acts as a spacer that pushes the remaining blocks right
+                    let spacer = BarBlock {
+                        separator: Some(false),
+                        ..BarBlock::default()
+                    };
+                    writeln!(out, "{},{}", spacer, block)?;
+                } else {
+                    writeln!(out, "{}", block)?;
+                }
+            }
+            OutputFormat::Json => writeln!(out, "{}", widget.to_json())?,
+            OutputFormat::Polybar => {
+                writeln!(out, "{}", polybar::from_pango(&self.render(widget, style)))?
+            }
+            OutputFormat::Lemonbar => {
+                writeln!(out, "{}", lemonbar::from_pango(&self.render(widget, style)))?
+            }
+            OutputFormat::Ansi => {
+                writeln!(out, "{}", ansi::from_pango(&self.render(widget, style)))?
+            }
+            OutputFormat::I3blocks => {
+                let full = self.pad(style.tidy(widget.render(style)));
+                writeln!(out, "{}", full)?;
+                let short = widget.short(style).map(|short| style.tidy(short));
+                writeln!(out, "{}", short.unwrap_or(full))?;
+                if let Some(color) = widget.urgency_color(style) {
+                    writeln!(out, "{}", color)?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
-fn get_memory_info() -> Result<MemoryInfo> {
-    let result = std::process::Command::new("free")
-        .output()
-        .context("running `free`")?;
-    let output = String::from_utf8(result.stdout).context("converting `free` output to utf-8")?;
-    let line = output
-        .lines()
-        .nth(1)
-        .ok_or_else(|| anyhow!("`free` output is invalid"))?;
-    let mut fields = line.split_whitespace();
-    let total = fields
-        .nth(1)
-        .ok_or_else(|| anyhow!("`free` output is invalid"))?
-        .parse::<u64>()?;
-    let used = fields
-        .next()
-        .ok_or_else(|| anyhow!("`free` output is invalid"))?
-        .parse::<u64>()?;
-    Ok(MemoryInfo { total, used })
+/// Everything a widget needs to style its markup
+pub struct Style {
+    /// Turned into the theme, palette and overrides the first time a widget styles something,
+    /// which the JSON outputs never do
+    pub colors: ColorSpec,
+    resolved: OnceCell<Colors>,
+    pub icons: Icons,
+    /// Leave out the `%` after the values with `--hide-label`
+    pub hide_label: bool,
+    /// Render every icon empty with `--no-icons`
+    pub no_icons: bool,
+    /// Templates from the `[format]` section of the config by widget name
+    pub templates: HashMap<String, template::Template>,
+    /// The replacements for the units with `--unit-locale`, empty to keep them as they are
+    pub units: &'static [(&'static str, &'static str)],
 }
 
-pub mod pulseaudio {
-    use anyhow::{anyhow, Result};
+impl Style {
+    fn resolved(&self) -> &Colors {
+        self.resolved.get_or_init(|| {
+            self.colors
+                .resolve()
+                .expect("the colors are validated in `configure`")
+        })
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.resolved().theme
+    }
 
-    pub struct Volume {
-        left: u64,
-        right: u64,
-        mute: bool,
+    pub fn palette(&self) -> &Palette {
+        &self.resolved().palette
     }
 
-    impl Volume {
-        pub fn left_pct(&self) -> u64 {
-            self.left * 100 / 65530 // not std::u16::MAX for some reason
-        }
+    /// The color for the widget part `key`, e.g. `battery.pct`, unless it was overridden with
+    /// `--override-color`
+    pub fn color(&self, key: &str, default: Color) -> Color {
+        self.resolved()
+            .overrides
+            .get(key)
+            .copied()
+            .unwrap_or(default)
+    }
 
-        pub fn right_pct(&self) -> u64 {
-            self.right * 100 / 65530 // not std::u16::MAX for some reason
+    /// The icon `name` as a span, colored only if `<widget>.icon` was overridden
+    pub fn icon(&self, widget: &str, name: &str) -> Span {
+        if self.no_icons {
+            return Span::default();
         }
-
-        fn icon(value: u64, mute: bool) -> &'static str {
-            if mute {
-                return "🔇";
-            }
-            match value {
-                0 => "🔇",
-                1..=33 => "🔈",
-                34..=66 => "🔉",
-                _ => "🔊",
-            }
+        let span = Span::new(self.icons.get(name)).size(Size::Pct(120));
+        match self.resolved().overrides.get(&format!("{}.icon", widget)) {
+            Some(color) => span.color(*color),
+            None => span,
         }
+    }
 
-        pub fn left_icon(&self) -> &'static str {
-            Self::icon(self.left_pct(), self.mute)
+    /// Drop the spaces around the icons left out with `--no-icons`, so `{icon} {value}` doesn't
+    /// start with a space
+    pub fn tidy(&self, markup: String) -> String {
+        if self.no_icons {
+            collapse_spaces(&markup.replace(&Span::default().to_string(), ""))
+        } else {
+            markup
         }
+    }
 
-        pub fn right_icon(&self) -> &'static str {
-            Self::icon(self.right_pct(), self.mute)
-        }
+    /// `unit` as written in the `--unit-locale`
+    pub fn localize<'a>(&self, unit: &'a str) -> &'a str {
+        self.units
+            .iter()
+            .find(|(from, _)| *from == unit)
+            .map_or(unit, |(_, to)| to)
     }
 
-    pub fn volume() -> Result<Volume> {
-        let result = std::process::Command::new("pactl")
-            .arg("get-sink-volume")
-            .arg("@DEFAULT_SINK@")
-            .output()?;
-        let output = String::from_utf8(result.stdout)?;
-        let line = output
-            .lines()
-            .next()
-            .ok_or_else(|| anyhow!("`pactl` output is invalid"))?;
-        let mut fields = line.split_whitespace();
-        // Volume: front-left: 65530 / 100% / -0.00 dB,   front-right: 65530 / 100% / -0.00 dB
-        let left = fields
-            .nth(2)
-            .ok_or_else(|| anyhow!("`pactl` output is invalid"))?
-            .parse::<u64>()?;
-        let right = fields
-            .nth(6)
-            .ok_or_else(|| anyhow!("`pactl` output is invalid"))?
-            .parse::<u64>()?;
-        let result = std::process::Command::new("pactl")
-            .arg("get-sink-mute")
-            .arg("@DEFAULT_SINK@")
-            .output()?;
-        let output = String::from_utf8(result.stdout)?;
-        let line = output
-            .lines()
-            .next()
-            .ok_or_else(|| anyhow!("`pactl` output is invalid"))?;
-        let mute = line.contains("yes");
-        Ok(Volume { left, right, mute })
-    }
-}
-
-pub mod brightness {
-    use anyhow::Result;
-
-    pub struct BrightnessInfo {
-        pub current: u64,
-        pub max: u64,
-    }
-
-    impl BrightnessInfo {
-        pub fn pct(&self) -> u64 {
-            self.current * 100 / self.max
-        }
-
-        pub fn icon(&self) -> &'static str {
-            match self.pct() {
-                0 => "🌑",
-                1..=33 => "🌒",
-                34..=66 => "🌓",
-                67..=99 => "🌔",
-                _ => "🌕",
-            }
-        }
-    }
-
-    pub fn info() -> Result<BrightnessInfo> {
-        let result = std::process::Command::new("brightnessctl")
-            .arg("info")
-            .output()?;
-        let output = String::from_utf8(result.stdout).unwrap();
-        // Default max 1 to avoid div by 0
-        let mut out = BrightnessInfo { current: 0, max: 1 };
-        for line in output.lines() {
-            if line.trim_start().starts_with("Current brightness:") {
-                out.current = line
-                    .split_whitespace()
-                    .nth(2)
-                    .unwrap()
-                    .parse::<u64>()
-                    .unwrap();
-            } else if line.trim_start().starts_with("Max brightness:") {
-                out.max = line
-                    .split_whitespace()
-                    .nth(2)
-                    .unwrap()
-                    .parse::<u64>()
-                    .unwrap();
-            }
-        }
-        Ok(out)
-    }
-}
-
-pub mod virsh {
-    use anyhow::{anyhow, Result};
-    /// Represents the state returned by the virsh list command
-    #[allow(dead_code)]
-    #[derive(Debug)]
-    pub struct State {
-        /// the active vms
-        active: Vec<String>,
-        /// the inactive vms
-        inactive: Vec<String>,
-    }
-
-    pub fn list() -> Result<State> {
-        let result = std::process::Command::new("virsh")
-            .arg("list")
-            .arg("--all")
-            .output()?;
-        let output = String::from_utf8(result.stdout).unwrap();
-        let mut active = Vec::new();
-        let mut inactive = Vec::new();
-        for line in output.trim().lines().skip(2) {
-            let mut fields = line.trim_start().split_whitespace();
-            // skip the id
-            fields.next();
-            // FIXME: assume that the name has no spaces
-            let name = fields
-                .next()
-                .ok_or_else(|| anyhow!("invalid format"))?
-                .to_string();
-            let state = fields.next().ok_or_else(|| anyhow!("invalid format"))?;
-            match state {
-                "running" => active.push(name),
-                "shut" => inactive.push(name),
-                _ => {}
-            }
-        }
-        Ok(State { active, inactive })
-    }
-}
-
-fn main() -> Result<()> {
-    use Command::*;
-    let theme = Theme::tokyonight_normal();
-    let command = Cli::parse().command;
-    match command {
-        Battery { device_path, debug } => {
-            let battery_info = if debug {
-                get_battery_info(&device_path)?
-            } else {
-                if let Ok(battery_info) = get_battery_info(&device_path) {
-                    battery_info
-                } else {
-                    println!("🔌");
-                    return Ok(());
-                }
-            };
-            let icon = if battery_info.state == BatteryState::Charging {
-                "🔌"
-            } else {
-                if battery_info.percentage() >= 20 {
-                    "🔋"
-                } else {
-                    "🪫"
-                }
-            };
-            println!(
-                "{icon} {pct}{pct_sign} {time}",
-                icon = pango!(icon, font_size = "120%"),
-                pct = pango!(
-                    battery_info.percentage(),
-                    color = theme.foreground(),
-                    weight = "ultrabold",
-                    font_size = "110%",
-                ),
-                pct_sign = pango!("%", color = theme.white()),
-                time = pango!(battery_info.time_to_empty_full_str(), color = theme.white()),
-            );
-            Ok(())
+    /// The dim unit after a value, e.g. the `%` of `widget`'s percentage, or nothing with
+    /// `--hide-label`
+    pub fn unit(&self, widget: &str, unit: &str) -> String {
+        if self.hide_label {
+            return String::new();
         }
-        Time { seconds, date } => {
-            let time = chrono::Local::now();
-            let time_str = if seconds {
-                time.format("%H:%M:%S")
-            } else {
-                time.format("%H:%M")
-            };
-            let time_of_day = match time.hour() {
-                0..=11 => "AM",
-                12..=23 => "PM",
-                _ => unreachable!(),
-            };
-            if date {
-                println!(
-                    "{date} {time} {}",
-                    time_of_day,
-                    date = time.format("%Y-%m-%d"),
-                    time = time_str,
-                );
-            } else {
-                println!("{} {}", time_str, time_of_day);
-            }
-            Ok(())
+        Span::new(self.localize(unit))
+            .color(self.color(&format!("{}.unit", widget), self.palette().secondary))
+            .to_string()
+    }
+
+    /// Pick a warning color for `value` growing past the `warn` and `critical` thresholds
+    pub fn threshold_color(&self, value: u64, warn: u64, critical: u64) -> Color {
+        if value >= critical {
+            self.theme().red()
+        } else if value >= warn {
+            self.theme().yellow()
+        } else {
+            self.palette().primary
         }
+    }
+}
+
+/// Localized units by language, for the locales that don't write them like English
+const UNIT_LOCALES: &[(&str, &[(&str, &str)])] = &[(
+    "zh",
+    &[
+        ("%", "％"),
+        ("B", "字节"),
+        ("KiB", "千字节"),
+        ("MiB", "兆字节"),
+        ("GiB", "吉字节"),
+        ("TiB", "太字节"),
+        ("KB", "千字节"),
+        ("MB", "兆字节"),
+        ("GB", "吉字节"),
+        ("TB", "太字节"),
+        ("W", "瓦"),
+    ],
+)];
+
+/// The unit replacements for `locale`, e.g. `zh_CN.UTF-8`, by its language. Empty for the
+/// locales that keep the English units
+pub fn unit_table(locale: &str) -> &'static [(&'static str, &'static str)] {
+    let language = locale.split(['_', '.', '@']).next().unwrap_or(locale);
+    UNIT_LOCALES
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map_or(&[], |(_, units)| units)
+}
+
+/// A fetched reading of a widget. The markup and the JSON output are both produced from the same
+/// struct, so they can't drift apart.
+pub trait Widget {
+    fn render(&self, style: &Style) -> String;
+
+    /// The data behind the widget, with units spelled out in the field names
+    fn to_json(&self) -> json::Object;
+
+    /// The markup split over up to `lines` rows for stacked bars, the compact value first and
+    /// the detail below. Widgets without a natural split stay on one row
+    fn render_lines(&self, style: &Style, _lines: u8) -> Vec<String> {
+        vec![self.render(style)]
+    }
+
+    /// A compact form for when the bar runs out of space, `None` if there is no sensible one
+    fn short(&self, _style: &Style) -> Option<String> {
+        None
+    }
+
+    /// A color for the whole block when the state needs attention, `None` while nominal
+    fn urgency_color(&self, _style: &Style) -> Option<Color> {
+        None
+    }
+
+    /// Whether there is nothing to show, e.g. no VMs running, so `--hide-zero` can blank it
+    fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Everything behind the reading as plain text lines, for a notification on right click
+    /// with `--show-detail`. `None` for widgets that show it all already
+    fn detail(&self) -> Option<String> {
+        None
+    }
+
+    /// The widget and icon names of the icon the widget leads with, for the `{icon}` of
+    /// templates. `None` for widgets that can't be templated
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    /// The JSON of each widget to export under `--metrics-addr`, by widget name. `name` is the
+    /// subcommand the widget was fetched for; `combo` answers for each of its parts instead
+    fn metrics(&self, name: &'static str) -> Vec<(&'static str, json::Object)> {
+        vec![(name, self.to_json())]
+    }
+}
+
+/// Rendered in place of a widget whose fetch failed
+struct Fallback {
+    /// The failed widget's name, for its `<widget>.icon` override
+    widget: &'static str,
+    icon: &'static str,
+    error: String,
+    /// The widget's tool isn't installed, which is shown as a dim `n/a` instead of a warning
+    missing: bool,
+}
+
+impl Fallback {
+    /// `icon` is replaced by the timeout icon if the fetch timed out
+    fn new(widget: &'static str, icon: &'static str, error: &anyhow::Error) -> Self {
+        let icon = if error.is::<process::Timeout>() {
+            "timeout"
+        } else {
+            icon
+        };
+        Self {
+            widget,
+            icon,
+            error: format!("{:#}", error),
+            missing: process::is_not_found(error),
+        }
+    }
+
+    /// The block for a failed fetch of `command` in both one-shot and loop mode: the widget's
+    /// own icon when its tool is missing, and ⚠ for any other error
+    fn for_error(command: &Command, error: &anyhow::Error) -> Self {
+        let icon = if process::is_not_found(error) {
+            command.fallback_icon()
+        } else {
+            "error"
+        };
+        Self::new(command.name(), icon, error)
+    }
+}
+
+/// A widget followed by a sparkline of its recent values, for `--history`
+struct WithHistory {
+    inner: Box<dyn Widget>,
+    history: Vec<f64>,
+    width: usize,
+    scale: Scale,
+}
+
+impl WithHistory {
+    fn sparkline(&self, style: &Style) -> String {
+        Span::new(sparkline(&self.history, self.width, self.scale))
+            .color(style.palette().secondary)
+            .to_string()
+    }
+}
+
+impl Widget for WithHistory {
+    fn render(&self, style: &Style) -> String {
+        format!("{} {}", self.inner.render(style), self.sparkline(style))
+    }
+
+    fn render_lines(&self, style: &Style, lines: u8) -> Vec<String> {
+        let mut rows = self.inner.render_lines(style, lines);
+        if let Some(last) = rows.last_mut() {
+            *last = format!("{} {}", last, self.sparkline(style));
+        }
+        rows
+    }
+
+    fn to_json(&self) -> json::Object {
+        self.inner.to_json().field("history", self.history.clone())
+    }
+
+    fn detail(&self) -> Option<String> {
+        self.inner.detail()
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        self.inner.icon()
+    }
+
+    fn metrics(&self, name: &'static str) -> Vec<(&'static str, json::Object)> {
+        self.inner.metrics(name)
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        self.inner.short(style)
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        self.inner.urgency_color(style)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+/// Renders `inner` through the `[format]` template for `widget`, if the config has one
+struct Templated {
+    inner: Box<dyn Widget>,
+    widget: &'static str,
+}
+
+impl Widget for Templated {
+    fn render(&self, style: &Style) -> String {
+        match style.templates.get(self.widget) {
+            Some(template) => template.render(&self.inner.to_json(), self.inner.icon(), style),
+            None => self.inner.render(style),
+        }
+    }
+
+    fn render_lines(&self, style: &Style, lines: u8) -> Vec<String> {
+        match style.templates.contains_key(self.widget) {
+            true => vec![self.render(style)],
+            false => self.inner.render_lines(style, lines),
+        }
+    }
+
+    fn to_json(&self) -> json::Object {
+        self.inner.to_json()
+    }
+
+    fn detail(&self) -> Option<String> {
+        self.inner.detail()
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        self.inner.short(style)
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        self.inner.urgency_color(style)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        self.inner.icon()
+    }
+
+    fn metrics(&self, name: &'static str) -> Vec<(&'static str, json::Object)> {
+        self.inner.metrics(name)
+    }
+}
+
+/// Several widgets in one block, for `combo`
+struct Combo {
+    /// Each widget with the name of its subcommand
+    parts: Vec<(&'static str, Box<dyn Widget>)>,
+    separator: String,
+}
+
+impl Combo {
+    fn join(&self, style: &Style, part: impl Fn(&dyn Widget) -> String) -> String {
+        let separator = Span::new(&self.separator)
+            .color(style.color("combo.separator", style.palette().secondary))
+            .to_string();
+        self.parts
+            .iter()
+            .map(|(_, widget)| part(widget.as_ref()))
+            .collect::<Vec<_>>()
+            .join(&separator)
+    }
+}
+
+impl Widget for Combo {
+    fn render(&self, style: &Style) -> String {
+        self.join(style, |widget| widget.render(style))
+    }
+
+    fn to_json(&self) -> json::Object {
+        let parts = self
+            .parts
+            .iter()
+            .map(|(_, widget)| widget.to_json())
+            .collect::<Vec<_>>();
+        json::Object::new().field("widgets", parts)
+    }
+
+    /// The details of the parts that have any, a blank line apart
+    fn detail(&self) -> Option<String> {
+        let details = self
+            .parts
+            .iter()
+            .filter_map(|(_, widget)| widget.detail())
+            .collect::<Vec<_>>();
+        (!details.is_empty()).then(|| details.join("\n\n"))
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(self.join(style, |widget| {
+            widget.short(style).unwrap_or_else(|| widget.render(style))
+        }))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        self.parts
+            .iter()
+            .find_map(|(_, widget)| widget.urgency_color(style))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.parts.iter().all(|(_, widget)| widget.is_empty())
+    }
+
+    fn metrics(&self, _name: &'static str) -> Vec<(&'static str, json::Object)> {
+        self.parts
+            .iter()
+            .flat_map(|(name, widget)| widget.metrics(name))
+            .collect()
+    }
+}
+
+impl Widget for Fallback {
+    fn render(&self, style: &Style) -> String {
+        let dim = |text: &str| Span::new(text).color(style.palette().secondary);
+        let icon = style.icon(self.widget, self.icon);
+        if self.missing {
+            return format!("{} {}", icon, dim("n/a"));
+        }
+        // the block would be blank without the icon, as if the widget had nothing to show
+        if style.no_icons {
+            let text = if self.icon == "timeout" {
+                "timeout"
+            } else {
+                "error"
+            };
+            return dim(text).to_string();
+        }
+        icon.to_string()
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("error", &self.error)
+            .field("missing_tool", self.missing)
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        let icon = style.icon(self.widget, self.icon);
+        if self.missing {
+            return Some(format!("{} n/a", icon));
+        }
+        Some(format!("{} {}", icon, pango_escape(&self.error)))
+    }
+}
+
+/// Rendered for one update in loop mode when the saved config doesn't load
+struct ConfigError {
+    error: String,
+}
+
+impl ConfigError {
+    fn new(error: &anyhow::Error) -> Self {
+        Self {
+            error: format!("{:#}", error),
+        }
+    }
+}
+
+impl Widget for ConfigError {
+    fn render(&self, style: &Style) -> String {
+        let text = Span::new("config error").color(style.palette().secondary);
+        if style.no_icons {
+            return text.to_string();
+        }
+        format!("{} {}", style.icon("config", "error"), text)
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new().field("error", format!("config error: {}", self.error))
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(format!(
+            "{} {}",
+            style.icon("config", "error"),
+            pango_escape(&self.error)
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weight {
+    UltraLight,
+    Light,
+    Normal,
+    Bold,
+    UltraBold,
+    Heavy,
+}
+
+impl Display for Weight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Weight::UltraLight => "ultralight",
+            Weight::Light => "light",
+            Weight::Normal => "normal",
+            Weight::Bold => "bold",
+            Weight::UltraBold => "ultrabold",
+            Weight::Heavy => "heavy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Only for the `pango!` wrapper, unknown names fall back to normal
+impl From<&str> for Weight {
+    fn from(name: &str) -> Self {
+        match name {
+            "ultralight" => Weight::UltraLight,
+            "light" => Weight::Light,
+            "bold" => Weight::Bold,
+            "ultrabold" => Weight::UltraBold,
+            "heavy" => Weight::Heavy,
+            _ => Weight::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    /// Relative to the bar font
+    Pct(u32),
+    /// Absolute, in 1024ths of a point like pango's plain integer sizes
+    Units(u32),
+}
+
+impl Size {
+    /// An absolute size of `points`
+    pub fn pt(points: f64) -> Self {
+        Size::Units((points * 1024.0).round() as u32)
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Size::Pct(pct) => write!(f, "{}%", pct),
+            Size::Units(units) => write!(f, "{}", units),
+        }
+    }
+}
+
+/// Only for the `pango!` wrapper, accepts `N%`, `Npt` or pango units, anything else falls back
+/// to 100%
+impl From<&str> for Size {
+    fn from(size: &str) -> Self {
+        if let Some(pct) = size.strip_suffix('%') {
+            return Size::Pct(pct.parse().unwrap_or(100));
+        }
+        if let Some(points) = size.strip_suffix("pt") {
+            return points.parse().map_or(Size::Pct(100), Size::pt);
+        }
+        size.parse().map_or(Size::Pct(100), Size::Units)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Underline {
+    None,
+    Single,
+    Double,
+    Low,
+    Error,
+}
+
+impl Display for Underline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Underline::None => "none",
+            Underline::Single => "single",
+            Underline::Double => "double",
+            Underline::Low => "low",
+            Underline::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Fixed width digits, so numbers don't jitter as they change
+pub const TABULAR_NUMS: &str = "tnum=1";
+
+/// A pango `<span>` with its text and nested child spans. Attributes are always serialized in
+/// declaration order.
+#[derive(Debug, Clone, Default)]
+pub struct Span {
+    /// Already escaped markup
+    markup: String,
+    color: Option<String>,
+    background: Option<String>,
+    font_family: Option<String>,
+    font_features: Option<String>,
+    font_size: Option<Size>,
+    weight: Option<Weight>,
+    underline: Option<Underline>,
+    children: Vec<Span>,
+}
+
+impl Span {
+    /// A span around `text`, which is escaped
+    pub fn new(text: impl Display) -> Self {
+        let mut markup = String::new();
+        // writing to a string can't fail
+        let _ =
+            std::fmt::Write::write_fmt(&mut EscapeWriter(&mut markup), format_args!("{}", text));
+        Self::raw(markup)
+    }
+
+    /// A span around `markup`, which is inserted verbatim
+    pub fn raw(markup: impl Into<String>) -> Self {
+        Self {
+            markup: markup.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn color(mut self, color: impl Into<String>) -> Self {
+        self.color = Some(color.into());
+        self
+    }
+
+    pub fn background(mut self, background: impl Into<String>) -> Self {
+        self.background = Some(background.into());
+        self
+    }
+
+    pub fn font_family(mut self, font_family: impl Into<String>) -> Self {
+        self.font_family = Some(font_family.into());
+        self
+    }
+
+    /// OpenType features, e.g. [`TABULAR_NUMS`]
+    pub fn font_features(mut self, font_features: impl Into<String>) -> Self {
+        self.font_features = Some(font_features.into());
+        self
+    }
+
+    pub fn size(mut self, size: impl Into<Size>) -> Self {
+        self.font_size = Some(size.into());
+        self
+    }
+
+    /// An absolute size of `points`, e.g. `size="11264"` for 11
+    pub fn size_pt(self, points: f64) -> Self {
+        self.size(Size::pt(points))
+    }
+
+    /// Same as [`Span::size`], named after the pango attribute for the `pango!` wrapper
+    pub fn font_size(self, size: impl Into<Size>) -> Self {
+        self.size(size)
+    }
+
+    pub fn weight(mut self, weight: impl Into<Weight>) -> Self {
+        self.weight = Some(weight.into());
+        self
+    }
+
+    pub fn underline(mut self, underline: Underline) -> Self {
+        self.underline = Some(underline);
+        self
+    }
+
+    /// Append a nested span after the text
+    pub fn child(mut self, child: Span) -> Self {
+        self.children.push(child);
+        self
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<span")?;
+        if let Some(color) = &self.color {
+            write!(f, " color=\"{}\"", Escaped(color))?;
+        }
+        if let Some(background) = &self.background {
+            write!(f, " background=\"{}\"", Escaped(background))?;
+        }
+        if let Some(font_family) = &self.font_family {
+            write!(f, " font_family=\"{}\"", Escaped(font_family))?;
+        }
+        if let Some(font_features) = &self.font_features {
+            write!(f, " font_features=\"{}\"", Escaped(font_features))?;
+        }
+        if let Some(font_size) = &self.font_size {
+            write!(f, " font_size=\"{}\"", font_size)?;
+        }
+        if let Some(weight) = &self.weight {
+            write!(f, " weight=\"{}\"", weight)?;
+        }
+        if let Some(underline) = &self.underline {
+            write!(f, " underline=\"{}\"", underline)?;
+        }
+        write!(f, ">{}", self.markup)?;
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        write!(f, "</span>")
+    }
+}
+
+/// Write `text` escaped for pango markup, in runs between the escaped characters
+fn escape_into(out: &mut impl std::fmt::Write, text: &str) -> std::fmt::Result {
+    let mut rest = text;
+    while let Some(i) = rest.find(['&', '<', '>', '\'', '"']) {
+        out.write_str(&rest[..i])?;
+        out.write_str(match rest.as_bytes()[i] {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'\'' => "&apos;",
+            _ => "&quot;",
+        })?;
+        rest = &rest[i + 1..];
+    }
+    out.write_str(rest)
+}
+
+/// Displays as the text escaped for pango markup, without an intermediate string
+struct Escaped<'a>(&'a str);
+
+impl Display for Escaped<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        escape_into(f, self.0)
+    }
+}
+
+/// Escapes everything written through it into the inner sink
+struct EscapeWriter<W>(W);
+
+impl<W: std::fmt::Write> std::fmt::Write for EscapeWriter<W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        escape_into(&mut self.0, s)
+    }
+}
+
+/// Escape text for use inside pango markup
+pub fn pango_escape(text: &str) -> String {
+    Escaped(text).to_string()
+}
+
+/// Wrap text in a pango span. The text is escaped, unless it is prefixed with `raw` for markup
+/// we generated ourselves.
+///
+/// Deprecated: this is a thin wrapper over [`Span`] kept so existing call sites compile, new
+/// code should use the builder directly.
+macro_rules! pango {
+    (raw $text: expr, $($key:ident = $value:expr),* $(,)?) => {
+        Span::raw($text.to_string())
+            $(.$key($value))*
+            .to_string()
+    };
+    ($text: expr, $($key:ident = $value:expr),* $(,)?) => {
+        Span::new(&$text)
+            $(.$key($value))*
+            .to_string()
+    };
+}
+
+/// Leveled logging to stderr, where it can't corrupt the bar reading stdout
+pub mod logging;
+
+/// Polybar's `%{F#rrggbb}` formatting tags, translated from the pango markup the widgets render
+pub mod polybar;
+
+/// Lemonbar's `%{F#rrggbb}` formatting blocks, from the same runs as the polybar output
+pub mod lemonbar;
+
+/// 24-bit ANSI escapes for terminals, from the same runs as the polybar output
+pub mod ansi;
+
+/// Log a message under `target` if `level` is enabled, e.g. `log!(Debug, "cache", "hit")`
+macro_rules! log {
+    ($level: ident, $target: expr, $($arg: tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::$level) {
+            $crate::logging::write($crate::logging::Level::$level, $target, format_args!($($arg)*));
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum IconSet {
+    Emoji,
+    /// Glyphs from a Nerd Font patched font
+    Nerd,
+    /// Plain text labels for fonts without either
+    Ascii,
+}
+
+const EMOJI_ICONS: &[(&str, &str)] = &[
+    ("battery_0", "🪫"),
+    ("battery_1", "🪫"),
+    ("battery_2", "🔋"),
+    ("battery_3", "🔋"),
+    ("battery_4", "🔋"),
+    ("battery_5", "🔋"),
+    ("battery_6", "🔋"),
+    ("battery_7", "🔋"),
+    ("battery_8", "🔋"),
+    ("battery_9", "🔋"),
+    ("charging", "🔌"),
+    ("profile_performance", "🚀"),
+    ("profile_balanced", "⚖"),
+    ("profile_power_saver", "🍃"),
+    ("volume_muted", "🔇"),
+    ("volume_low", "🔈"),
+    ("volume_medium", "🔉"),
+    ("volume_high", "🔊"),
+    ("brightness_0", "🌑"),
+    ("brightness_1", "🌒"),
+    ("brightness_2", "🌓"),
+    ("brightness_3", "🌔"),
+    ("brightness_4", "🌕"),
+    ("vm", "💻"),
+    ("memory", "🧠"),
+    ("cpu", "🖥"),
+    ("fan", "🌀"),
+    ("disk", "💾"),
+    ("network", "🌐"),
+    ("rx", "⬇"),
+    ("tx", "⬆"),
+    ("rising", "↗"),
+    ("falling", "↘"),
+    ("countdown", "⏳"),
+    ("timeout", "⏱"),
+    ("host", "🏠"),
+    ("user", "👤"),
+    ("vpn", "🔒"),
+    ("sound_card", "🎵"),
+    ("swap_warning", "⚠"),
+    ("battery_wear", "⚠"),
+    ("lid", "💻"),
+    ("error", "⚠"),
+    ("failed", "✗"),
+];
+
+const NERD_ICONS: &[(&str, &str)] = &[
+    ("battery_0", "\u{f007a}"),
+    ("battery_1", "\u{f007b}"),
+    ("battery_2", "\u{f007c}"),
+    ("battery_3", "\u{f007d}"),
+    ("battery_4", "\u{f007e}"),
+    ("battery_5", "\u{f007f}"),
+    ("battery_6", "\u{f0080}"),
+    ("battery_7", "\u{f0081}"),
+    ("battery_8", "\u{f0082}"),
+    ("battery_9", "\u{f0079}"),
+    ("charging", "\u{f0e7}"),
+    ("profile_performance", "\u{f135}"),
+    ("profile_balanced", "\u{f24e}"),
+    ("profile_power_saver", "\u{f06c}"),
+    ("volume_muted", "\u{f6a9}"),
+    ("volume_low", "\u{f026}"),
+    ("volume_medium", "\u{f027}"),
+    ("volume_high", "\u{f028}"),
+    ("brightness_0", "\u{f186}"),
+    ("brightness_1", "\u{f185}"),
+    ("brightness_2", "\u{f185}"),
+    ("brightness_3", "\u{f185}"),
+    ("brightness_4", "\u{f185}"),
+    ("vm", "\u{f108}"),
+    ("memory", "\u{f538}"),
+    ("cpu", "\u{f2db}"),
+    ("fan", "\u{f863}"),
+    ("disk", "\u{f0a0}"),
+    ("network", "\u{f0ac}"),
+    ("rx", "\u{f063}"),
+    ("tx", "\u{f062}"),
+    ("rising", "\u{f0535}"),
+    ("falling", "\u{f0533}"),
+    ("countdown", "\u{f252}"),
+    ("timeout", "\u{f017}"),
+    ("host", "\u{f108}"),
+    ("user", "\u{f007}"),
+    ("vpn", "\u{f0582}"),
+    ("sound_card", "\u{f001}"),
+    ("swap_warning", "\u{f071}"),
+    ("battery_wear", "\u{f0083}"),
+    ("lid", "\u{f109}"),
+    ("error", "\u{f071}"),
+    ("failed", "\u{f00d}"),
+];
+
+const ASCII_ICONS: &[(&str, &str)] = &[
+    ("battery_0", "LOW"),
+    ("battery_1", "LOW"),
+    ("battery_2", "BAT"),
+    ("battery_3", "BAT"),
+    ("battery_4", "BAT"),
+    ("battery_5", "BAT"),
+    ("battery_6", "BAT"),
+    ("battery_7", "BAT"),
+    ("battery_8", "BAT"),
+    ("battery_9", "BAT"),
+    ("charging", "CHG"),
+    ("profile_performance", "PERF"),
+    ("profile_balanced", "BAL"),
+    ("profile_power_saver", "ECO"),
+    ("volume_muted", "MUTE"),
+    ("volume_low", "VOL"),
+    ("volume_medium", "VOL"),
+    ("volume_high", "VOL"),
+    ("brightness_0", "BRI"),
+    ("brightness_1", "BRI"),
+    ("brightness_2", "BRI"),
+    ("brightness_3", "BRI"),
+    ("brightness_4", "BRI"),
+    ("vm", "VM"),
+    ("memory", "MEM"),
+    ("cpu", "CPU"),
+    ("fan", "FAN"),
+    ("disk", "DISK"),
+    ("network", "NET"),
+    ("rx", "RX"),
+    ("tx", "TX"),
+    ("rising", "+"),
+    ("falling", "-"),
+    ("countdown", "TMR"),
+    ("timeout", "T/O"),
+    ("host", "HOST"),
+    ("user", "USR"),
+    ("vpn", "VPN"),
+    ("sound_card", "SND"),
+    ("swap_warning", "SWAP"),
+    ("battery_wear", "WORN"),
+    ("lid", "LID"),
+    ("error", "ERR"),
+    ("failed", "X"),
+];
+
+/// Named icons from one of the icon sets, so widgets don't hardcode glyphs
+pub struct Icons {
+    entries: &'static [(&'static str, &'static str)],
+    /// Replacements for single icons given on the command line
+    custom: HashMap<&'static str, String>,
+}
+
+impl Icons {
+    fn new(set: IconSet) -> Self {
+        let entries = match set {
+            IconSet::Emoji => EMOJI_ICONS,
+            IconSet::Nerd => NERD_ICONS,
+            IconSet::Ascii => ASCII_ICONS,
+        };
+        Self {
+            entries,
+            custom: HashMap::new(),
+        }
+    }
+
+    /// Use `icon` for `name` instead of the one from the set
+    pub fn set(&mut self, name: &'static str, icon: String) {
+        self.custom.insert(name, icon);
+    }
+
+    /// The icon called `name`, or `?` if the set is missing it
+    pub fn get(&self, name: &str) -> &str {
+        if let Some(icon) = self.custom.get(name) {
+            return icon;
+        }
+        self.entries
+            .iter()
+            .find(|(entry, _)| *entry == name)
+            .map_or("?", |(_, icon)| icon)
+    }
+}
+
+/// Battery icons from empty to full, each covering a tenth of the charge
+pub const BATTERY_RAMP: [&str; 10] = [
+    "battery_0",
+    "battery_1",
+    "battery_2",
+    "battery_3",
+    "battery_4",
+    "battery_5",
+    "battery_6",
+    "battery_7",
+    "battery_8",
+    "battery_9",
+];
+
+/// The icon of `ramp` for `pct`, in equal steps from the first at 0% to the last at 100%
+pub fn ramp_icon(ramp: &[&'static str], pct: u64) -> &'static str {
+    ramp[(pct as usize * ramp.len() / 100).min(ramp.len() - 1)]
+}
+
+/// The bounds of a sparkline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    Fixed {
+        min: f64,
+        max: f64,
+    },
+    /// From the lowest to the highest value shown, for rates with no natural bound
+    Auto,
+}
+
+impl Scale {
+    pub const PERCENT: Scale = Scale::Fixed {
+        min: 0.0,
+        max: 100.0,
+    };
+}
+
+const SPARK_GLYPHS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The last `width` values as block glyphs scaled by `scale`, oldest first. NaNs show as spaces,
+/// values outside a fixed scale are clamped, and a flat series sits on the bottom glyph.
+pub fn sparkline(values: &[f64], width: usize, scale: Scale) -> String {
+    let values = &values[values.len().saturating_sub(width)..];
+    let (min, max) = match scale {
+        Scale::Fixed { min, max } => (min, max),
+        Scale::Auto => values
+            .iter()
+            .filter(|value| !value.is_nan())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            }),
+    };
+    values
+        .iter()
+        .map(|&value| {
+            if value.is_nan() {
+                return ' ';
+            }
+            let fraction = if max > min {
+                ((value - min) / (max - min)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            SPARK_GLYPHS[(fraction * (SPARK_GLYPHS.len() - 1) as f64).round() as usize]
+        })
+        .collect()
+}
+
+const BAR_GLYPHS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+/// `fraction` of `width` characters filled in eighths, padded with light shade so the empty
+/// part is still visible. `fraction` is clamped to `0.0..=1.0`
+pub fn progress_bar(fraction: f64, width: usize) -> String {
+    let eighths = (fraction.clamp(0.0, 1.0) * (width * 8) as f64).round() as usize;
+    let mut bar = "█".repeat(eighths / 8);
+    if let Some(partial) = (eighths % 8).checked_sub(1) {
+        bar.push(BAR_GLYPHS[partial]);
+    }
+    let filled = bar.chars().count();
+    bar + &"░".repeat(width - filled)
+}
+
+/// Which way a reading moved since the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Rising,
+    Falling,
+    Steady,
+}
+
+impl Trend {
+    /// Steady unless `next` differs from `prev` by at least `min_change`, so noise doesn't make
+    /// the arrow flicker
+    pub fn between(prev: u64, next: u64, min_change: u64) -> Self {
+        if next > prev && next - prev >= min_change {
+            Trend::Rising
+        } else if next < prev && prev - next >= min_change {
+            Trend::Falling
+        } else {
+            Trend::Steady
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trend::Rising => "rising",
+            Trend::Falling => "falling",
+            Trend::Steady => "steady",
+        }
+    }
+
+    /// Name of the arrow in [`Icons`], `None` when steady
+    pub fn icon_name(&self) -> Option<&'static str> {
+        match self {
+            Trend::Rising => Some("rising"),
+            Trend::Falling => Some("falling"),
+            Trend::Steady => None,
+        }
+    }
+}
+
+pub mod color;
+
+#[derive(Clone)]
+pub struct Theme {
+    pub foreground: Color,
+    pub background: Color,
+    pub black: Color,
+    pub red: Color,
+    pub green: Color,
+    pub yellow: Color,
+    pub blue: Color,
+    pub magenta: Color,
+    pub cyan: Color,
+    pub white: Color,
+    pub index_16: Color,
+    pub index_17: Color,
+}
+
+macro_rules! impl_theme_color {
+    ($name: ident) => {
+        pub fn $name(&self) -> Color {
+            self.$name
+        }
+    };
+}
+
+impl Theme {
+    impl_theme_color!(foreground);
+    impl_theme_color!(background);
+    impl_theme_color!(black);
+    impl_theme_color!(red);
+    impl_theme_color!(green);
+    impl_theme_color!(yellow);
+    impl_theme_color!(blue);
+    impl_theme_color!(magenta);
+    impl_theme_color!(cyan);
+    impl_theme_color!(white);
+    impl_theme_color!(index_16);
+    impl_theme_color!(index_17);
+
+    pub fn tokyonight_normal() -> Self {
+        Self {
+            foreground: Color(0xc0, 0xca, 0xf5),
+            background: Color(0x1a, 0x1b, 0x26),
+            black: Color(0x15, 0x16, 0x1e),
+            red: Color(0xf7, 0x76, 0x8e),
+            green: Color(0x9e, 0xce, 0x6a),
+            yellow: Color(0xe0, 0xaf, 0x68),
+            blue: Color(0x7a, 0xa2, 0xf7),
+            magenta: Color(0xbb, 0x9a, 0xf7),
+            cyan: Color(0x7d, 0xcf, 0xff),
+            white: Color(0xa9, 0xb1, 0xd6),
+            index_16: Color(0xff, 0x9e, 0x64),
+            index_17: Color(0xdb, 0x4b, 0x4b),
+        }
+    }
+
+    /// Tokyo Night "day"
+    pub fn tokyonight_light() -> Self {
+        Self {
+            foreground: Color(0x37, 0x60, 0xbf),
+            background: Color(0xe1, 0xe2, 0xe7),
+            black: Color(0xe9, 0xe9, 0xed),
+            red: Color(0xf5, 0x2a, 0x65),
+            green: Color(0x58, 0x75, 0x39),
+            yellow: Color(0x8c, 0x6c, 0x3e),
+            blue: Color(0x2e, 0x7d, 0xe9),
+            magenta: Color(0x98, 0x54, 0xf1),
+            cyan: Color(0x00, 0x71, 0x97),
+            white: Color(0x61, 0x72, 0xb0),
+            index_16: Color(0xb1, 0x5c, 0x00),
+            index_17: Color(0xc6, 0x43, 0x43),
+        }
+    }
+
+    /// Every color `t` of the way from `a` to `b`, see [`Color::lerp`]
+    pub fn blend(a: &Theme, b: &Theme, t: f64) -> Theme {
+        Theme {
+            foreground: a.foreground.lerp(b.foreground, t),
+            background: a.background.lerp(b.background, t),
+            black: a.black.lerp(b.black, t),
+            red: a.red.lerp(b.red, t),
+            green: a.green.lerp(b.green, t),
+            yellow: a.yellow.lerp(b.yellow, t),
+            blue: a.blue.lerp(b.blue, t),
+            magenta: a.magenta.lerp(b.magenta, t),
+            cyan: a.cyan.lerp(b.cyan, t),
+            white: a.white.lerp(b.white, t),
+            index_16: a.index_16.lerp(b.index_16, t),
+            index_17: a.index_17.lerp(b.index_17, t),
+        }
+    }
+
+    /// The light or dark theme, going by `--appearance` if given and the desktop otherwise
+    pub fn auto(appearance: Option<Appearance>) -> Self {
+        let gtk_theme = std::env::var("GTK_THEME").ok();
+        Self::pick(appearance, gtk_theme.as_deref())
+    }
+
+    /// [`Theme::auto`] with the `GTK_THEME` value passed in
+    fn pick(appearance: Option<Appearance>, gtk_theme: Option<&str>) -> Self {
+        match appearance.unwrap_or_else(|| Appearance::detect(gtk_theme)) {
+            Appearance::Light => Self::tokyonight_light(),
+            Appearance::Dark => Self::tokyonight_normal(),
+        }
+    }
+
+    /// The names accepted by [`Theme::by_name`], in declaration order
+    pub const FIELDS: &'static [&'static str] = &[
+        "foreground",
+        "background",
+        "black",
+        "red",
+        "green",
+        "yellow",
+        "blue",
+        "magenta",
+        "cyan",
+        "white",
+        "index_16",
+        "index_17",
+    ];
+
+    pub fn by_name(&self, name: &str) -> Option<Color> {
+        let color = match name {
+            "foreground" => self.foreground(),
+            "background" => self.background(),
+            "black" => self.black(),
+            "red" => self.red(),
+            "green" => self.green(),
+            "yellow" => self.yellow(),
+            "blue" => self.blue(),
+            "magenta" => self.magenta(),
+            "cyan" => self.cyan(),
+            "white" => self.white(),
+            "index_16" => self.index_16(),
+            "index_17" => self.index_17(),
+            _ => return None,
+        };
+        Some(color)
+    }
+
+    /// This theme with the color `field` replaced by `value`, which is resolved with
+    /// [`color::resolve`] against the theme before the change
+    pub fn with_field(mut self, field: &str, value: &str) -> Result<Theme> {
+        let color = color::resolve(value, &self)?;
+        let slot = match field {
+            "foreground" => &mut self.foreground,
+            "background" => &mut self.background,
+            "black" => &mut self.black,
+            "red" => &mut self.red,
+            "green" => &mut self.green,
+            "yellow" => &mut self.yellow,
+            "blue" => &mut self.blue,
+            "magenta" => &mut self.magenta,
+            "cyan" => &mut self.cyan,
+            "white" => &mut self.white,
+            "index_16" => &mut self.index_16,
+            "index_17" => &mut self.index_17,
+            _ => return Err(anyhow!("unknown theme color `{}`", field)),
+        };
+        *slot = color;
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Appearance {
+    Light,
+    Dark,
+}
+
+impl Appearance {
+    /// Guess from a `GTK_THEME` value like `Adwaita:dark`. Only a theme without `dark` in its
+    /// name counts as light, anything unknown is dark
+    pub fn detect(gtk_theme: Option<&str>) -> Self {
+        match gtk_theme {
+            Some(theme) if !theme.to_lowercase().contains("dark") => Self::Light,
+            _ => Self::Dark,
+        }
+    }
+}
+
+/// The color flags and the `[theme]` section of the config, as given
+#[derive(Default)]
+pub struct ColorSpec {
+    pub theme_blend: Option<f64>,
+    pub appearance: Option<Appearance>,
+    /// `[theme]` fields to replace, in config order
+    pub theme: Vec<(String, String)>,
+    pub fg: Option<String>,
+    pub accent: Option<String>,
+    /// `--override-color` keys and colors
+    pub overrides: Vec<(String, String)>,
+}
+
+impl ColorSpec {
+    /// Check every color name and theme field up front, so that [`ColorSpec::resolve`] can't fail
+    /// once a widget renders and the user still gets a configuration error
+    pub fn validate(&self) -> Result<()> {
+        for (field, value) in &self.theme {
+            if !Theme::FIELDS.contains(&field.as_str()) {
+                return Err(anyhow!("unknown theme color `{}`", field));
+            }
+            color::validate(value)
+                .with_context(|| format!("setting theme.{} in the config", field))?;
+        }
+        if let Some(accent) = &self.accent {
+            color::validate(accent).context("resolving --accent")?;
+        }
+        if let Some(fg) = &self.fg {
+            color::validate(fg).context("resolving --fg")?;
+        }
+        for (key, value) in &self.overrides {
+            color::validate(value)
+                .with_context(|| format!("resolving --override-color {}", key))?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self) -> Result<Colors> {
+        let mut theme = match self.theme_blend {
+            Some(t) => Theme::blend(&Theme::tokyonight_normal(), &Theme::tokyonight_light(), t),
+            None => Theme::auto(self.appearance),
+        };
+        for (field, value) in &self.theme {
+            theme = theme.with_field(field, value)?;
+        }
+        let palette = Palette::new(&theme, self.fg.as_deref(), self.accent.as_deref())?;
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), color::resolve(value, &theme)?)))
+            .collect::<Result<_>>()?;
+        Ok(Colors {
+            theme,
+            palette,
+            overrides,
+        })
+    }
+}
+
+struct Colors {
+    theme: Theme,
+    palette: Palette,
+    /// Colors for specific widget parts from `--override-color`
+    overrides: HashMap<String, Color>,
+}
+
+/// The colors a widget actually renders with, derived from the theme and any overrides
+pub struct Palette {
+    /// Used for the bold segments, e.g. the numbers
+    pub primary: Color,
+    /// Used for the dim segments, e.g. units and separators
+    pub secondary: Color,
+}
+
+impl Palette {
+    pub fn new(theme: &Theme, fg: Option<&str>, accent: Option<&str>) -> Result<Self> {
+        let primary = match accent {
+            Some(accent) => color::resolve(accent, theme).context("resolving --accent")?,
+            None => theme.foreground(),
+        };
+        let secondary = match fg {
+            Some(fg) => color::resolve(fg, theme).context("resolving --fg")?,
+            None => theme.white(),
+        };
+        Ok(Self { primary, secondary })
+    }
+}
+
+/// A swatch per theme color, for checking what the active theme looks like
+struct ThemePreview {
+    theme: Theme,
+    /// Labeled blocks on one line to fit in the bar, instead of a wide swatch per line
+    single_line: bool,
+}
+
+impl Widget for ThemePreview {
+    fn render(&self, style: &Style) -> String {
+        let swatches = Theme::FIELDS.iter().filter_map(|&name| {
+            let color = self.theme.by_name(name)?;
+            if self.single_line {
+                let label = Span::new(name).color(style.palette().secondary);
+                Some(format!("{}{}", label, Span::new("██").color(color)))
+            } else {
+                let swatch = Span::new("█".repeat(8)).color(color).background(color);
+                Some(format!("{}: {}", name, swatch))
+            }
+        });
+        swatches
+            .collect::<Vec<_>>()
+            .join(if self.single_line { " " } else { "\n" })
+    }
+
+    fn to_json(&self) -> json::Object {
+        Theme::FIELDS
+            .iter()
+            .fold(json::Object::new(), |object, &name| {
+                object.optional(name, self.theme.by_name(name).map(String::from))
+            })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum BatteryState {
+    Charging,
+    Discharging,
+    Full,
+}
+
+#[derive(Debug)]
+struct BatteryInfo {
+    energy_full: f64,
+    /// What the battery held when new, if upower knows
+    energy_full_design: Option<f64>,
+    energy: f64,
+    time_to_empty_full: f64,
+    state: BatteryState,
+    /// The median of recent percentages with `--smooth`
+    smoothed_pct: Option<i32>,
+    /// The active power profile with `--show-profile`
+    profile: Option<PowerProfile>,
+    /// What the battery is charging from, if sysfs says
+    power_source: Option<PowerSource>,
+    /// The health percentage below which to show the wear marker, with `--wear-warn`
+    wear_warn: Option<u32>,
+    /// `charge-cycles` from upower, `None` if the battery doesn't report it
+    cycle_count: Option<u32>,
+    /// `energy-rate` in W, `None` if upower doesn't report it
+    energy_rate: Option<f64>,
+    /// Show the cycle count with `--show-cycles`
+    show_cycles: bool,
+}
+
+/// Whether a battery at `health_pct` of its design capacity has worn past `threshold`
+fn worn_out(health_pct: f64, threshold: u32) -> bool {
+    health_pct < f64::from(threshold)
+}
+
+/// The kind of supply the battery charges from, so slow USB charging stands out from a proper
+/// charger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerSource {
+    Mains,
+    Usb,
+    /// USB, negotiated up with USB Power Delivery
+    UsbPd,
+    Wireless,
+}
+
+impl PowerSource {
+    /// The supply described by the `type`, `online` and `usb_type` files of a
+    /// `/sys/class/power_supply` entry, `None` if it's offline or not a charger
+    fn parse(kind: &str, online: &str, usb_type: Option<&str>) -> Option<Self> {
+        if online.trim() != "1" {
+            return None;
+        }
+        match kind.trim() {
+            "Mains" => Some(PowerSource::Mains),
+            // `usb_type` lists the supported types with the active one in brackets,
+            // e.g. `C [PD] PD_PPS`
+            "USB" if usb_type.is_some_and(|types| types.contains("[PD")) => {
+                Some(PowerSource::UsbPd)
+            }
+            "USB" => Some(PowerSource::Usb),
+            "Wireless" => Some(PowerSource::Wireless),
+            _ => None,
+        }
+    }
+
+    /// The first online charger under `/sys/class/power_supply`
+    fn read() -> Option<Self> {
+        let mut entries = std::fs::read_dir("/sys/class/power_supply")
+            .ok()?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries.iter().find_map(|dir| {
+            let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok();
+            Self::parse(
+                &read("type")?,
+                &read("online")?,
+                read("usb_type").as_deref(),
+            )
+        })
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            PowerSource::Mains => "AC",
+            PowerSource::Usb => "USB",
+            PowerSource::UsbPd => "PD",
+            PowerSource::Wireless => "Qi",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerProfile {
+    Performance,
+    Balanced,
+    PowerSaver,
+}
+
+impl PowerProfile {
+    /// Parse the output of `powerprofilesctl get`
+    fn parse(output: &str) -> Option<Self> {
+        match output.trim() {
+            "performance" => Some(PowerProfile::Performance),
+            "balanced" => Some(PowerProfile::Balanced),
+            "power-saver" => Some(PowerProfile::PowerSaver),
+            _ => None,
+        }
+    }
+
+    /// The active profile, `None` if power-profiles-daemon isn't available
+    fn get(source: &dyn CommandSource) -> Option<Self> {
+        Self::parse(&source.output("powerprofilesctl", &["get"]).ok()?)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PowerProfile::Performance => "performance",
+            PowerProfile::Balanced => "balanced",
+            PowerProfile::PowerSaver => "power-saver",
+        }
+    }
+
+    fn icon_name(&self) -> &'static str {
+        match self {
+            PowerProfile::Performance => "profile_performance",
+            PowerProfile::Balanced => "profile_balanced",
+            PowerProfile::PowerSaver => "profile_power_saver",
+        }
+    }
+}
+
+impl BatteryInfo {
+    /// The percentage to show, smoothed if enabled
+    fn percentage(&self) -> i32 {
+        self.smoothed_pct.unwrap_or_else(|| self.raw_percentage())
+    }
+
+    fn raw_percentage(&self) -> i32 {
+        (self.energy / self.energy_full * 100.0) as i32
+    }
+
+    fn icon_name(&self) -> &'static str {
+        if self.state == BatteryState::Charging {
+            "charging"
+        } else {
+            ramp_icon(&BATTERY_RAMP, self.percentage().max(0) as u64)
+        }
+    }
+
+    /// The full charge as a percentage of the design capacity
+    fn health_pct(&self) -> Option<f64> {
+        let design = self.energy_full_design.filter(|&design| design > 0.0)?;
+        Some(self.energy_full / design * 100.0)
+    }
+
+    fn wear_warning(&self) -> bool {
+        matches!(
+            (self.health_pct(), self.wear_warn),
+            (Some(health_pct), Some(threshold)) if worn_out(health_pct, threshold)
+        )
+    }
+
+    fn time_to_empty_full(&self) -> Duration {
+        Duration::from_secs_f64(self.time_to_empty_full * 3600.0)
+    }
+
+    fn time_to_empty_full_str(&self) -> String {
+        let duration = self.time_to_empty_full();
+        let hours = duration.as_secs() / 3600;
+        let minutes = (duration.as_secs() % 3600) / 60;
+        format!("{:02}:{:02}", hours, minutes)
+    }
+}
+
+impl Widget for BatteryInfo {
+    fn render(&self, style: &Style) -> String {
+        self.render_lines(style, 1).join(" ")
+    }
+
+    fn render_lines(&self, style: &Style, lines: u8) -> Vec<String> {
+        let icon = self.icon_name();
+        let warning = if self.wear_warning() {
+            format!("{} ", style.icon("battery", "battery_wear"))
+        } else {
+            String::new()
+        };
+        let mut charge = format!(
+            "{warning}{icon} {pct}{pct_sign}",
+            icon = style.icon("battery", icon),
+            pct = pango!(
+                self.percentage(),
+                color = style.color("battery.pct", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            pct_sign = style.unit("battery", "%"),
+        );
+        if let Some(power_source) = self.power_source {
+            charge += &pango!(
+                format!(" {}", power_source.label()),
+                color = style.color("battery.source", style.palette().secondary),
+                font_size = "80%",
+            );
+        }
+        let mut time = pango!(
+            self.time_to_empty_full_str(),
+            color = style.color("battery.time", style.palette().secondary)
+        );
+        if let Some(profile) = self.profile {
+            time = format!("{} {}", time, style.icon("battery", profile.icon_name()));
+        }
+        if let (true, Some(cycles)) = (self.show_cycles, self.cycle_count) {
+            time += &pango!(
+                format!(" ({} cycles)", cycles),
+                color = style.color("battery.cycles", style.theme().white()),
+            );
+        }
+        if lines >= 2 {
+            vec![charge, time]
+        } else {
+            vec![format!("{} {}", charge, time)]
+        }
+    }
+
+    fn to_json(&self) -> json::Object {
+        let state = match self.state {
+            BatteryState::Charging => "charging",
+            BatteryState::Discharging => "discharging",
+            BatteryState::Full => "full",
+        };
+        let secs = self.time_to_empty_full().as_secs();
+        json::Object::new()
+            .field("state", state)
+            .field("charge_pct", self.percentage())
+            .field("energy_wh", self.energy)
+            .field("energy_full_wh", self.energy_full)
+            .optional("energy_full_design_wh", self.energy_full_design)
+            .optional("health_pct", self.health_pct())
+            .field("wear_warning", self.wear_warning())
+            .field(
+                "time_to_empty_secs",
+                (self.state == BatteryState::Discharging).then_some(secs),
+            )
+            .field(
+                "time_to_full_secs",
+                (self.state == BatteryState::Charging).then_some(secs),
+            )
+            .optional("profile", self.profile.map(|profile| profile.name()))
+            .optional("cycle_count", self.cycle_count)
+            .optional(
+                "power_source",
+                self.power_source.map(|power_source| power_source.label()),
+            )
+    }
+
+    fn detail(&self) -> Option<String> {
+        let state = match self.state {
+            BatteryState::Charging => "charging",
+            BatteryState::Discharging => "discharging",
+            BatteryState::Full => "full",
+        };
+        let mut lines = vec![
+            format!("State: {}", state),
+            format!(
+                "Charge: {}% ({:.1} of {:.1} Wh)",
+                self.percentage(),
+                self.energy,
+                self.energy_full
+            ),
+        ];
+        if let Some(rate) = self.energy_rate {
+            lines.push(format!("Rate: {:.1} W", rate));
+        }
+        match self.state {
+            BatteryState::Charging => {
+                lines.push(format!("Full in: {}", self.time_to_empty_full_str()))
+            }
+            BatteryState::Discharging => {
+                lines.push(format!("Empty in: {}", self.time_to_empty_full_str()))
+            }
+            BatteryState::Full => {}
+        }
+        if let (Some(health), Some(design)) = (self.health_pct(), self.energy_full_design) {
+            lines.push(format!("Health: {:.0}% of {:.1} Wh", health, design));
+        }
+        if let Some(cycles) = self.cycle_count {
+            lines.push(format!("Cycles: {}", cycles));
+        }
+        if let Some(profile) = self.profile {
+            lines.push(format!("Profile: {}", profile.name()));
+        }
+        if let Some(power_source) = self.power_source {
+            lines.push(format!("Charger: {}", power_source.label()));
+        }
+        Some(lines.join("\n"))
+    }
+
+    /// Just the percentage, i3 only falls back to this when the bar is out of space
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(format!(
+            "{}{}",
+            self.percentage(),
+            style.unit("battery", "%")
+        ))
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        (self.state != BatteryState::Charging && self.percentage() < 20)
+            .then(|| style.theme().red())
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        Some(("battery", self.icon_name()))
+    }
+}
+
+/// Collapses runs of identical errors in watch mode, so a persistently broken fetch is logged
+/// once with a count instead of on every tick
+#[derive(Default)]
+struct ErrorDedup {
+    last: Option<String>,
+    suppressed: u64,
+}
+
+impl ErrorDedup {
+    /// Record a failed tick, returning the lines that should be logged
+    fn error(&mut self, message: String) -> Vec<String> {
+        if self.last.as_ref() == Some(&message) {
+            self.suppressed += 1;
+            return Vec::new();
+        }
+        let mut lines = self.ok();
+        lines.push(message.clone());
+        self.last = Some(message);
+        lines
+    }
+
+    /// Record a successful tick, returning the suppression summary if errors were swallowed
+    fn ok(&mut self) -> Vec<String> {
+        let lines = match (self.last.take(), self.suppressed) {
+            (Some(last), suppressed) if suppressed > 0 => {
+                vec![format!("`{}` repeated {} more times", last, suppressed)]
+            }
+            _ => Vec::new(),
+        };
+        self.suppressed = 0;
+        lines
+    }
+}
+
+fn get_battery_info(device_path: &str, source: &dyn CommandSource) -> Result<BatteryInfo> {
+    parse_battery_info(&source.output("upower", &["-i", device_path])?)
+}
+
+/// `energy:  41.5 Wh` => 41.5 for the unit `Wh`, converting from the `mWh` and `kWh` some
+/// upower backends report instead
+fn parse_upower_quantity(line: &str, unit: &str) -> Result<f64> {
+    let mut fields = line.split_whitespace().skip(1);
+    let value = fields
+        .next()
+        .ok_or_else(|| anyhow!("`{}` has no value", line.trim()))?
+        .parse::<f64>()?;
+    // no unit at all is taken to be the usual one
+    let prefix = fields
+        .next()
+        .map_or(Some(""), |suffix| suffix.strip_suffix(unit));
+    let scale = match prefix {
+        Some("") => 1.0,
+        Some("m") => 0.001,
+        Some("k") => 1000.0,
+        _ => return Err(anyhow!("`{}` isn't in {}", line.trim(), unit)),
+    };
+    Ok(value * scale)
+}
+
+/// Parse the output of `upower -i <device>`
+fn parse_battery_info(output: &str) -> Result<BatteryInfo> {
+    let upower_line = |key: &str| {
+        output
+            .lines()
+            .find(|line| line.trim_start().starts_with(key))
+    };
+    let energy_full =
+        upower_line("energy-full:").ok_or_else(|| anyhow!("energy-full not found"))?;
+    let energy_full =
+        parse_upower_quantity(energy_full, "Wh").context("energy-full format is invalid")?;
+    // older batteries and some firmware don't report a design capacity
+    let energy_full_design =
+        upower_line("energy-full-design:").and_then(|line| parse_upower_quantity(line, "Wh").ok());
+    let energy_rate =
+        upower_line("energy-rate:").and_then(|line| parse_upower_quantity(line, "W").ok());
+    // `N/A` or missing for batteries that don't count them
+    let cycle_count = output
+        .lines()
+        .find(|line| line.trim_start().starts_with("charge-cycles:"))
+        .and_then(|line| line.split_whitespace().nth(1)?.parse::<u32>().ok());
+    let energy = upower_line("energy:").ok_or_else(|| anyhow!("energy not found"))?;
+    let energy = parse_upower_quantity(energy, "Wh").context("energy format is invalid")?;
+    let mut time_to_line = output
+        .lines()
+        .find(|line| line.trim_start().starts_with("time to"))
+        .ok_or_else(|| anyhow!("`time to {{empty | full}}` not found"))?
+        .split_whitespace();
+    let mut time_to_empty_full = time_to_line
+        .nth(3)
+        .ok_or_else(|| anyhow!("`time to {{empty | full}}` format is invalid"))?
+        .parse::<f64>()?;
+    let time_to_empty_full_unit = time_to_line
+        .next()
+        .ok_or_else(|| anyhow!("`time to {{empty | full}}` format is invalid"))?;
+    if time_to_empty_full_unit == "minutes" {
+        time_to_empty_full /= 60.0;
+    }
+    let state = output
+        .lines()
+        .find(|line| line.trim_start().starts_with("state:"))
+        .ok_or_else(|| anyhow!("state not found"))?
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("state format is invalid"))
+        .map(|s| match s {
+            "charging" => BatteryState::Charging,
+            "discharging" => BatteryState::Discharging,
+            _ => BatteryState::Full,
+        })?;
+    Ok(BatteryInfo {
+        energy_full,
+        energy_full_design,
+        energy,
+        time_to_empty_full,
+        state,
+        smoothed_pct: None,
+        profile: None,
+        power_source: None,
+        wear_warn: None,
+        cycle_count,
+        energy_rate,
+        show_cycles: false,
+    })
+}
+
+/// The middle value, or the lower of the two middle ones for an even count so the result is
+/// always an actual reading
+fn median(values: &[i32]) -> Option<i32> {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    sorted.get(sorted.len().checked_sub(1)? / 2).copied()
+}
+
+/// Push `pct` onto the last `window` readings for `device_path`, taken from `recent` if
+/// populated and otherwise from the state file, and return their median
+fn smooth_percentage(
+    device_path: &str,
+    pct: i32,
+    window: usize,
+    recent: &mut Vec<i32>,
+) -> Result<i32> {
+    let name = format!(
+        "battery-{}.pct",
+        device_path.rsplit('/').next().unwrap_or(device_path)
+    );
+    state::push_recent(&name, pct, window, recent)?;
+    Ok(median(recent).unwrap_or(pct))
+}
+
+/// `widget` with a sparkline of its recent values when `--history` is set
+fn with_history(
+    widget: Box<dyn Widget>,
+    name: &str,
+    value: f64,
+    len: usize,
+    scale: Scale,
+    samples: &mut Samples,
+) -> Result<Box<dyn Widget>> {
+    if len == 0 {
+        return Ok(widget);
+    }
+    let recent = samples.history.entry(name.to_string()).or_default();
+    state::push_recent(&format!("{}.history", name), value, len, recent)?;
+    Ok(Box::new(WithHistory {
+        inner: widget,
+        history: recent.clone(),
+        width: len,
+        scale,
+    }))
+}
+
+struct MemoryInfo {
+    total: u64,
+    used: u64,
+    /// `Buffers:` from `/proc/meminfo`, 0 if it can't be read
+    buffers: u64,
+    /// `Cached:` from `/proc/meminfo`, 0 if it can't be read
+    cached: u64,
+    /// 0 without swap
+    swap_total: u64,
+    swap_used: u64,
+    /// `full avg10` from PSI, `None` on kernels without it
+    pressure: Option<f64>,
+    /// `HugePages_Total:` and `HugePages_Free:` from `/proc/meminfo` in pages, 0 if they can't
+    /// be read
+    huge_pages_total: u64,
+    huge_pages_free: u64,
+}
+
+impl MemoryInfo {
+    pub fn total_bytes(&self) -> u64 {
+        self.total * 1024
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used * 1024
+    }
+
+    pub fn used_pct(&self) -> u64 {
+        (self.used * 100).checked_div(self.total).unwrap_or(0)
+    }
+
+    /// Used memory counting buffers and the page cache, which `free` leaves out
+    pub fn used_with_buffers_cache(&self) -> u64 {
+        (self.used + self.buffers + self.cached).min(self.total)
+    }
+
+    /// Some containers report no memory at all through `free`, and nothing can be shown then
+    pub fn is_unknown(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn swap_pct(&self) -> u64 {
+        (self.swap_used * 100)
+            .checked_div(self.swap_total)
+            .unwrap_or(0)
+    }
+
+    pub fn thrash_risk(&self) -> bool {
+        thrash_risk(self.used_pct(), self.swap_pct())
+    }
+
+    pub fn huge_pages_used(&self) -> u64 {
+        self.huge_pages_total.saturating_sub(self.huge_pages_free)
+    }
+
+    /// Share of the last 10 seconds in which all tasks were stalled on memory
+    pub fn pressure_pct(&self) -> u64 {
+        self.pressure.unwrap_or(0.0) as u64
+    }
+}
+
+struct MemoryWidget {
+    info: MemoryInfo,
+    units: ByteUnits,
+    /// The width of each bar with `--bars`, 0 to show the numbers
+    bars: usize,
+    show_hugepages: bool,
+    /// Show swap instead of RAM
+    swap: bool,
+}
+
+impl MemoryWidget {
+    /// RAM and swap as adjacent bars, each colored by how full it is. The swap bar is left out
+    /// without swap
+    fn bars(&self, style: &Style) -> String {
+        let ram = Span::new(progress_bar(self.info.used_pct() as f64 / 100.0, self.bars)).color(
+            style.color(
+                "memory.used",
+                self.urgency_color(style).unwrap_or(style.palette().primary),
+            ),
+        );
+        if self.info.swap_total == 0 {
+            return ram.to_string();
+        }
+        let swap = Span::new(progress_bar(self.info.swap_pct() as f64 / 100.0, self.bars)).color(
+            style.color(
+                "memory.swap",
+                style.threshold_color(self.info.swap_pct(), 50, 80),
+            ),
+        );
+        format!("{}{}", ram, swap)
+    }
+
+    /// ` HP 12/64` with `--show-hugepages`, nothing without it or when none are reserved
+    fn huge_pages(&self, style: &Style) -> String {
+        if !self.show_hugepages || self.info.huge_pages_total == 0 {
+            return String::new();
+        }
+        format!(
+            " {}{}",
+            pango!(
+                "HP ",
+                color = style.color("memory.unit", style.palette().secondary)
+            ),
+            pango!(
+                format!(
+                    "{}/{}",
+                    self.info.huge_pages_used(),
+                    self.info.huge_pages_total
+                ),
+                color = style.color("memory.hugepages", style.palette().primary),
+                font_features = TABULAR_NUMS,
+            ),
+        )
+    }
+}
+
+impl Widget for MemoryWidget {
+    fn render(&self, style: &Style) -> String {
+        if self.info.is_unknown() {
+            return format!(
+                "{} {}",
+                style.icon("memory", "memory"),
+                pango!(
+                    "n/a",
+                    color = style.color("memory.unit", style.palette().secondary)
+                ),
+            );
+        }
+        let warning = if self.info.thrash_risk() {
+            format!("{} ", style.icon("memory", "swap_warning"))
+        } else {
+            String::new()
+        };
+        if self.bars > 0 {
+            return format!(
+                "{warning}{icon} {bars}{huge_pages}",
+                icon = style.icon("memory", "memory"),
+                bars = self.bars(style),
+                huge_pages = self.huge_pages(style),
+            );
+        }
+        let (used, total, used_color) = if self.swap {
+            (
+                self.info.swap_used * 1024,
+                self.info.swap_total * 1024,
+                style.color(
+                    "memory.swap",
+                    style.threshold_color(self.info.swap_pct(), 50, 80),
+                ),
+            )
+        } else {
+            (
+                self.info.used_bytes(),
+                self.info.total_bytes(),
+                style.color(
+                    "memory.used",
+                    self.urgency_color(style).unwrap_or(style.palette().primary),
+                ),
+            )
+        };
+        format!(
+            "{warning}{used}{div}{total}{mib}{huge_pages}",
+            used = pango!(
+                self.units.scale(used, 2),
+                color = used_color,
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            total = pango!(
+                self.units.scale(total, 2),
+                color = style.color("memory.total", style.palette().primary),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            div = pango!(
+                "/",
+                color = style.color("memory.separator", style.palette().secondary)
+            ),
+            mib = pango!(
+                style.localize(self.units.suffix(2)),
+                color = style.color("memory.unit", style.palette().secondary)
+            ),
+            huge_pages = self.huge_pages(style),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("total_kib", self.info.total)
+            .field("used_kib", self.info.used)
+            .field("buffers_kib", self.info.buffers)
+            .field("cached_kib", self.info.cached)
+            .field("swap_total_kib", self.info.swap_total)
+            .field("swap_used_kib", self.info.swap_used)
+            .field("thrash_risk", self.info.thrash_risk())
+            .field("huge_pages_total", self.info.huge_pages_total)
+            .field("huge_pages_free", self.info.huge_pages_free)
+    }
+
+    fn detail(&self) -> Option<String> {
+        if self.info.is_unknown() {
+            return None;
+        }
+        let unit = self.units.suffix(2);
+        let amount = |kib: u64| format!("{} {}", self.units.scale(kib * 1024, 2), unit);
+        let mut lines = vec![
+            format!(
+                "Used: {} of {} ({}%)",
+                amount(self.info.used),
+                amount(self.info.total),
+                self.info.used_pct()
+            ),
+            format!("Buffers: {}", amount(self.info.buffers)),
+            format!("Cached: {}", amount(self.info.cached)),
+        ];
+        if self.info.swap_total > 0 {
+            lines.push(format!(
+                "Swap: {} of {} ({}%)",
+                amount(self.info.swap_used),
+                amount(self.info.swap_total),
+                self.info.swap_pct()
+            ));
+        }
+        if self.info.huge_pages_total > 0 {
+            lines.push(format!(
+                "Huge pages: {} of {}",
+                self.info.huge_pages_used(),
+                self.info.huge_pages_total
+            ));
+        }
+        if self.info.pressure.is_some() {
+            lines.push(format!(
+                "Stalled: {}% of the last 10s",
+                self.info.pressure_pct()
+            ));
+        }
+        Some(lines.join("\n"))
+    }
+
+    fn short(&self, style: &Style) -> Option<String> {
+        if self.info.is_unknown() {
+            return Some("n/a".to_string());
+        }
+        let pct = match self.swap {
+            true => self.info.swap_pct(),
+            false => self.info.used_pct(),
+        };
+        Some(format!("{}{}", pct, style.unit("memory", "%")))
+    }
+
+    /// With `--swap`, while none is in use
+    fn is_empty(&self) -> bool {
+        self.swap && self.info.swap_used == 0
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        if self.info.is_unknown() {
+            return None;
+        }
+        if self.info.thrash_risk() {
+            return Some(style.theme().red());
+        }
+        // stalls say more about memory trouble than usage, which includes reclaimable memory
+        let color = match self.info.pressure {
+            Some(_) => style.threshold_color(self.info.pressure_pct(), 5, 20),
+            None => style.threshold_color(self.info.used_pct(), 75, 90),
+        };
+        (color != style.palette().primary).then_some(color)
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        Some(("memory", "memory"))
+    }
+}
+
+/// Binary (KiB, 1024) or decimal (KB, 1000) byte units
+#[derive(Clone, Copy)]
+pub struct ByteUnits {
+    pub si: bool,
+}
+
+impl ByteUnits {
+    pub fn base(&self) -> u64 {
+        if self.si {
+            1000
+        } else {
+            1024
+        }
+    }
+
+    /// Suffix for the unit `base^exponent`, e.g. `MiB` or `MB` for 2
+    pub fn suffix(&self, exponent: u32) -> &'static str {
+        const BINARY: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        const DECIMAL: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+        let suffixes = if self.si { DECIMAL } else { BINARY };
+        suffixes[(exponent as usize).min(suffixes.len() - 1)]
+    }
+
+    /// Whole number of `base^exponent` units in `bytes`
+    pub fn scale(&self, bytes: u64, exponent: u32) -> u64 {
+        bytes / self.base().pow(exponent)
+    }
+
+    /// `bytes` in the largest unit it reaches, e.g. `(465.8, "GiB")`
+    pub fn human(&self, bytes: u64) -> (f64, &'static str) {
+        let mut value = bytes as f64;
+        let mut exponent = 0;
+        while value >= self.base() as f64 && exponent < 4 {
+            value /= self.base() as f64;
+            exponent += 1;
+        }
+        (value, self.suffix(exponent))
+    }
+}
+
+fn get_memory_info(source: &dyn CommandSource) -> Result<MemoryInfo> {
+    let mut info = parse_memory_info(&source.output("free", &[])?)?;
+    info.pressure = std::fs::read_to_string("/proc/pressure/memory")
+        .ok()
+        .and_then(|psi| parse_memory_pressure(&psi));
+    if let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") {
+        info.buffers = parse_meminfo_field(&meminfo, "Buffers").unwrap_or(0);
+        info.cached = parse_meminfo_field(&meminfo, "Cached").unwrap_or(0);
+        info.huge_pages_total = parse_meminfo_field(&meminfo, "HugePages_Total").unwrap_or(0);
+        info.huge_pages_free = parse_meminfo_field(&meminfo, "HugePages_Free").unwrap_or(0);
+    }
+    Ok(info)
+}
+
+/// RAM that is nearly full while swap is filling up too means the system is about to thrash,
+/// which neither figure shows on its own
+fn thrash_risk(ram_pct: u64, swap_pct: u64) -> bool {
+    ram_pct >= 90 && swap_pct >= 50
+}
+
+/// Parse the output of `free`, leaving the fields from `/proc` unset
+fn parse_memory_info(output: &str) -> Result<MemoryInfo> {
+    let line = output
+        .lines()
+        .nth(1)
+        .ok_or_else(|| anyhow!("`free` output is invalid"))?;
+    let mut fields = line.split_whitespace();
+    let total = fields
+        .nth(1)
+        .ok_or_else(|| anyhow!("`free` output is invalid"))?
+        .parse::<u64>()?;
+    let used = fields
+        .next()
+        .ok_or_else(|| anyhow!("`free` output is invalid"))?
+        .parse::<u64>()?;
+    // Swap:        8388604      262144     8126460
+    let swap = output
+        .lines()
+        .find_map(|line| line.strip_prefix("Swap:"))
+        .map(|line| {
+            line.split_whitespace()
+                .take(2)
+                .map(|field| field.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    let (swap_total, swap_used) = match swap.as_deref() {
+        Some(&[total, used]) => (total, used),
+        _ => (0, 0),
+    };
+    Ok(MemoryInfo {
+        total,
+        used,
+        buffers: 0,
+        cached: 0,
+        swap_total,
+        swap_used,
+        pressure: None,
+        huge_pages_total: 0,
+        huge_pages_free: 0,
+    })
+}
+
+/// `Cached:          1234567 kB` => 1234567 for `Cached`
+fn parse_meminfo_field(meminfo: &str, name: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// `full avg10=0.00 avg60=0.00 avg300=0.00 total=0` => the avg10 value
+fn parse_memory_pressure(psi: &str) -> Option<f64> {
+    psi.lines()
+        .find(|line| line.starts_with("full "))?
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+pub mod process;
+
+pub mod disk;
+
+pub mod pulseaudio;
+
+pub mod brightness;
+
+pub mod virsh;
+
+pub mod cpu;
+
+pub mod gpu;
+
+/// Shell completion scripts generated from the clap definitions
+pub mod completions;
+
+/// Format templates from the `[format]` section of the config, e.g.
+/// `battery = "{icon} {charge_pct:bold,accent}% {state:fg}"`, which replace a widget's markup
+/// with its icon and JSON fields
+pub mod template;
+
+/// Man pages in roff, one for the program and one per subcommand, generated from the same clap
+/// definitions as `--help`
+pub mod man;
+
+/// The config file, `$XDG_CONFIG_HOME/i3widgets/config.toml` unless `--config` is given. It
+/// only fills in what wasn't passed on the command line, and with `--interval` a save applies
+/// on the next update
+pub mod config;
+
+/// Short-lived copies of tool output under `$XDG_RUNTIME_DIR/i3widgets/cache`, so several bars
+/// showing the same widget don't all run the same tool
+pub mod cache;
+
+/// Small files under `$XDG_STATE_HOME/i3widgets` for widgets that need to remember something
+/// between runs
+pub mod state;
+
+pub mod network;
+
+pub mod tcp;
+
+pub mod hostname;
+
+pub mod lid;
+
+pub mod alsa;
+
+#[cfg(feature = "time")]
+pub mod countdown;
+
+pub mod logind;
+
+pub mod wireguard;
+
+/// The i3 IPC protocol, which sway speaks too, over the socket the window manager advertises
+pub mod sway;
+
+#[cfg(feature = "time")]
+pub mod clock;
+
+pub mod signal;
+
+/// Wakeups for `--interval` mode from sources that know when a widget's data changed, so the
+/// loop doesn't have to poll often to stay current
+pub mod events;
+
+#[cfg(test)]
+mod snapshots;
+
+/// Where the fetchers get the current time from, so the time widgets can be rendered for a fixed
+/// moment the same way [`CommandSource`] lets the parsers be fed canned output
+pub trait Clock {
+    fn now(&self) -> std::time::SystemTime;
+}
+
+/// The system's wall clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> std::time::SystemTime {
+        std::time::SystemTime::now()
+    }
+}
+
+/// Readings kept between updates in `--interval` mode, so stateful widgets can diff against the
+/// previous update instead of sampling again
+#[derive(Default)]
+struct Samples {
+    cpu: Option<cpu::CpuSnapshot>,
+    /// Recent readings for `--history`, by state file name
+    history: HashMap<String, Vec<f64>>,
+    /// Free bytes of each mount at the last tick, by mount point since a combo can show several
+    disk_available: HashMap<String, u64>,
+    battery: Vec<i32>,
+    network_rate: Option<network::Sample>,
+    network_total: Option<network::Total>,
+    /// The last unmuted sink volume
+    volume_level: Option<u64>,
+}
+
+/// Fetch the data behind a widget, ready to be rendered or serialized
+///
+/// `detail` also fetches what only [`Widget::detail`] shows, for `--show-detail`
+// only the time widgets read the clock
+#[cfg_attr(not(feature = "time"), allow(clippy::only_used_in_recursion))]
+fn fetch(
+    command: &Command,
+    source: &dyn CommandSource,
+    clock: &dyn Clock,
+    samples: &mut Samples,
+    detail: bool,
+) -> Result<Box<dyn Widget>> {
+    use Command::*;
+    let start = std::time::Instant::now();
+    let widget: Box<dyn Widget> = match command {
+        Battery {
+            device_path,
+            smooth,
+            show_profile,
+            show_cycles,
+            wear_warn,
+            ..
+        } => {
+            let device_path = device_path.as_deref().ok_or_else(|| {
+                anyhow!("--device-path is required unless battery.device_path is in the config")
+            })?;
+            let mut battery_info = get_battery_info(device_path, source)?;
+            if *show_profile {
+                battery_info.profile = PowerProfile::get(source);
+            }
+            battery_info.wear_warn = *wear_warn;
+            battery_info.show_cycles = *show_cycles;
+            if battery_info.state == BatteryState::Charging {
+                battery_info.power_source = PowerSource::read();
+            }
+            if *smooth > 1 {
+                let pct = battery_info.raw_percentage();
+                battery_info.smoothed_pct = Some(smooth_percentage(
+                    device_path,
+                    pct,
+                    *smooth,
+                    &mut samples.battery,
+                )?);
+            }
+            Box::new(battery_info)
+        }
+        #[cfg(feature = "time")]
+        Time {
+            seconds,
+            date,
+            locale_time,
+            millis,
+        } => Box::new(clock::Time {
+            now: clock.now().into(),
+            seconds: *seconds,
+            millis: *millis,
+            date: *date,
+            locale: locale_time.then(clock::LocaleFormats::get).flatten(),
+        }),
+        #[cfg(feature = "time")]
         TimeZh {
             seconds,
             date,
             am_pm,
+            era,
+        } => Box::new(clock::TimeZh {
+            now: clock.now().into(),
+            seconds: *seconds,
+            date: *date,
+            am_pm: *am_pm,
+            era: *era,
+        }),
+        Memory {
+            si,
+            include_buffers_cache,
+            history,
+            bars,
+            show_hugepages,
+            swap,
+        } => {
+            let mut info = get_memory_info(source)?;
+            if *include_buffers_cache {
+                info.used = info.used_with_buffers_cache();
+            }
+            let pct = if *swap {
+                info.swap_pct()
+            } else {
+                info.used_pct()
+            } as f64;
+            let widget = Box::new(MemoryWidget {
+                info,
+                units: ByteUnits { si: *si },
+                bars: *bars,
+                show_hugepages: *show_hugepages,
+                swap: *swap,
+            });
+            with_history(widget, "memory", pct, *history, Scale::PERCENT, samples)?
+        }
+        DiskTree {
+            min_size_gb,
+            bar_width,
+        } => Box::new(disk::DiskTree {
+            mounts: disk::mounts((min_size_gb * 1e9) as u64, source)?,
+            bar_width: *bar_width,
+        }),
+        Disk { mount, si } => {
+            let info = disk::info(mount, source)?;
+            let trend = samples
+                .disk_available
+                .insert(mount.clone(), info.available)
+                .map(|prev| Trend::between(prev, info.available, disk::TREND_MIN_CHANGE));
+            Box::new(disk::DiskWidget {
+                mount: mount.clone(),
+                info,
+                units: ByteUnits { si: *si },
+                trend,
+            })
+        }
+        Network {
+            interface,
+            si,
+            total,
+            reset,
+            history,
+        } => {
+            let interface = match interface {
+                Some(interface) => interface.clone(),
+                None => network::default_interface()?,
+            };
+            let traffic = if *total {
+                let total = network::total(&interface, *reset, samples.network_total)?;
+                samples.network_total = Some(total);
+                network::Traffic::Total(total.transferred)
+            } else {
+                let (traffic, sample) = network::rate(&interface, samples.network_rate)?;
+                samples.network_rate = Some(sample);
+                traffic
+            };
+            let rate = match traffic {
+                network::Traffic::Rate { rx, tx } => Some(rx + tx),
+                network::Traffic::Total(_) => None,
+            };
+            let name = format!("network-{}", interface);
+            let widget = Box::new(network::NetworkWidget {
+                interface,
+                traffic,
+                units: ByteUnits { si: *si },
+            });
+            // the sparkline follows the rate, the total only ever grows
+            match rate {
+                Some(rate) => with_history(widget, &name, rate, *history, Scale::Auto, samples)?,
+                None => widget,
+            }
+        }
+        TcpConnections { state } => Box::new(tcp::connections(*state)?),
+        SinkVolume { .. } => {
+            let mut volume = pulseaudio::volume(source)?;
+            volume.remember(&mut samples.volume_level);
+            if detail {
+                // the detail still has the levels without it
+                match pulseaudio::default_sink(source) {
+                    Ok(sink) => volume.sink = Some(sink),
+                    Err(err) => log!(Warn, "sink-volume", "looking up the sink: {:#}", err),
+                }
+            }
+            Box::new(volume)
+        }
+        AppVolume => Box::new(pulseaudio::app_volumes(source)?),
+        Brightness => Box::new(brightness::info(source)?),
+        VirshActive {
+            running_only,
+            virsh_timeout_ms,
+        } => Box::new(virsh::list(
+            *running_only,
+            Duration::from_millis(*virsh_timeout_ms),
+            source,
+        )?),
+        Cpu {
+            sample_interval_ms,
+            sample_count,
+            per_core,
+            history,
         } => {
-            let time = chrono::Local::now();
-            let time_str = {
-                let mut h = time.hour() % if am_pm { 12 } else { 24 };
-                // 12-hour clock 0:00 => 12:00, but in 24 hour clock 0:00 => 0:00
-                if h == 0 && am_pm {
-                    h = 12;
+            let (mut usage, last) = match samples.cpu.take() {
+                Some(prev) => {
+                    let next = cpu::CpuSnapshot::read()?;
+                    (cpu::between(&prev, &next), next)
+                }
+                None => cpu::usage(*sample_count, Duration::from_millis(*sample_interval_ms))?,
+            };
+            usage.per_core = *per_core;
+            samples.cpu = Some(last);
+            let pct = usage.usage_pct as f64;
+            with_history(
+                Box::new(usage),
+                "cpu",
+                pct,
+                *history,
+                Scale::PERCENT,
+                samples,
+            )?
+        }
+        GpuFan => Box::new(gpu::nvidia_fan(source)?),
+        Combo {
+            commands,
+            separator,
+            ..
+        } => {
+            let parts = commands
+                .iter()
+                .map(|command| -> (_, Box<dyn Widget>) {
+                    let name = command.name();
+                    match fetch(command, source, clock, samples, detail) {
+                        Ok(widget) => (name, widget),
+                        // one broken widget shouldn't take the others down with it
+                        Err(err) if process::is_not_found(&err) => (
+                            name,
+                            Box::new(Fallback::new(name, command.fallback_icon(), &err)),
+                        ),
+                        Err(err) => (name, Box::new(Fallback::new(name, "failed", &err))),
+                    }
+                })
+                .collect();
+            Box::new(self::Combo {
+                parts,
+                separator: separator.clone(),
+            })
+        }
+        PrintTheme | ColorTest | Doctor | List | Completions { .. } | Man { .. } => {
+            unreachable!("printed by main without fetching anything")
+        }
+        SoundCards { show_names } => Box::new(alsa::sound_cards(*show_names, source)?),
+        Sessions { show_users } => Box::new(logind::sessions(*show_users, source)?),
+        Wireguard { interface } => Box::new(wireguard::peers(interface, source)?),
+        SwayMode { default_text } => Box::new(sway::Mode {
+            name: sway::binding_mode()?,
+            default_text: default_text.clone(),
+        }),
+        Hostname { short_hostname } => Box::new(hostname::hostname(*short_hostname)?),
+        LidState => Box::new(lid::state()?),
+        #[cfg(feature = "time")]
+        Countdown {
+            until,
+            warn_minutes,
+            done_text,
+        } => {
+            let now = clock.now().into();
+            let target = countdown::parse_target(until, now)?;
+            Box::new(countdown::Countdown {
+                target,
+                remaining: countdown::remaining(now, target),
+                warn: Duration::from_secs(warn_minutes * 60),
+                done_text: done_text.clone(),
+            })
+        }
+        // `check_usage` rejects these before anything is fetched
+        #[cfg(not(feature = "time"))]
+        Time { .. } | TimeZh { .. } | Countdown { .. } => {
+            return Err(anyhow!("built without feature `time`"))
+        }
+    };
+    log!(Debug, command.name(), "fetched in {:.1?}", start.elapsed());
+    if template::fields(command.name()).is_some() {
+        return Ok(Box::new(Templated {
+            inner: widget,
+            widget: command.name(),
+        }));
+    }
+    Ok(widget)
+}
+
+/// A closed stdout just means nobody is reading anymore, so it isn't worth an error
+fn ignore_broken_pipe(result: io::Result<()>) -> Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Show the widget's detail as a desktop notification titled `name`
+fn notify_detail(name: &str, widget: &dyn Widget, system: &process::System) {
+    let Some(detail) = widget.detail() else {
+        return;
+    };
+    if let Err(err) = system.output("notify-send", &["--app-name=i3widgets", name, &detail]) {
+        log!(Warn, name, "sending the detail: {:#}", err);
+    }
+}
+
+fn write_man_pages(out_dir: &std::path::Path) -> Result<()> {
+    std::fs::create_dir_all(out_dir).with_context(|| format!("creating {}", out_dir.display()))?;
+    for (name, page) in man::pages(&mut Cli::command()) {
+        let path = out_dir.join(name);
+        std::fs::write(&path, page).with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Exits with 2 for usage and configuration errors, like clap does for bad flags. Fetch errors
+/// render a ⚠ block and exit with 0 so the bar still shows something, unless `--strict` is given,
+/// in which case they exit with 1 like any other runtime error.
+fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    match &cli.command {
+        Command::List => {
+            let widgets = completions::list(&Cli::command());
+            if matches!(cli.output, OutputFormat::Json | OutputFormat::I3bar) {
+                let objects = widgets
+                    .iter()
+                    .map(|(name, about)| {
+                        json::Object::new()
+                            .field("name", name.as_str())
+                            .field("description", about.as_str())
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json::ToJson::to_json(&objects));
+            } else {
+                for (name, about) in widgets {
+                    println!("{:<16}{}", name, about);
                 }
-                let m = time.minute();
-                let s = time.second();
-                if seconds {
-                    format!("{:02}:{:02}:{:02}", h, m, s)
-                } else {
-                    format!("{:02}:{:02}", h, m)
+            }
+            return ExitCode::SUCCESS;
+        }
+        Command::Completions { shell } => {
+            print!("{}", completions::generate(*shell, &mut Cli::command()));
+            return ExitCode::SUCCESS;
+        }
+        Command::Man { out_dir } => {
+            return match write_man_pages(out_dir) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("i3widgets: {:#}", err);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        _ => {}
+    }
+    let (style, output, config) = match configure(&mut cli, &matches) {
+        Ok(configured) => configured,
+        Err(err) => {
+            eprintln!("i3widgets: {:#}", err);
+            return ExitCode::from(2);
+        }
+    };
+    match run(&cli, &matches, config, &style, &output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("i3widgets: {:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Everything that only depends on the flags and the config file, so its errors are the user's
+fn configure(cli: &mut Cli, matches: &clap::ArgMatches) -> Result<(Style, Output, config::Config)> {
+    let level = match (cli.log_level, std::env::var("I3WIDGETS_LOG")) {
+        (Some(level), _) => Some(level),
+        (None, Ok(level)) => Some(
+            <logging::Level as clap::ValueEnum>::from_str(&level, true)
+                .map_err(|_| anyhow!("invalid I3WIDGETS_LOG level `{}`", level))?,
+        ),
+        (None, Err(_)) => None,
+    };
+    let debug = matches!(cli.command, Command::Battery { debug: true, .. });
+    if let Some(level) = level.or(debug.then_some(logging::Level::Debug)) {
+        logging::set_level(level);
+    }
+    let config = config::Config::load(cli.config.as_deref())?;
+    let (style, output) = configure_with(cli, matches, &config)?;
+    Ok((style, output, config))
+}
+
+/// Build the flags, style and output from scratch with `config`, for a reload of the config
+/// in loop mode. The command line still wins over it
+fn reconfigure(
+    matches: &clap::ArgMatches,
+    config: &config::Config,
+) -> Result<(Cli, Style, Output)> {
+    let mut cli = Cli::from_arg_matches(matches)?;
+    let (style, output) = configure_with(&mut cli, matches, config)?;
+    Ok((cli, style, output))
+}
+
+/// The part of [`configure`] after the config is loaded
+fn configure_with(
+    cli: &mut Cli,
+    matches: &clap::ArgMatches,
+    config: &config::Config,
+) -> Result<(Style, Output)> {
+    config.apply(cli, matches)?;
+    if let Command::Combo {
+        widgets, commands, ..
+    } = &mut cli.command
+    {
+        for widget in widgets.iter() {
+            let command = config
+                .command(widget)
+                .with_context(|| format!("combo widget `{}`", widget))?;
+            if matches!(command, Command::Combo { .. }) {
+                return Err(anyhow!("combo widgets can't be nested"));
+            }
+            commands.push(command);
+        }
+    }
+    match &cli.command {
+        Command::Combo { commands, .. } => commands.iter().try_for_each(check_usage)?,
+        command => check_usage(command)?,
+    }
+    let colors = ColorSpec {
+        theme_blend: cli.theme_blend,
+        appearance: cli.appearance,
+        theme: config.theme.clone(),
+        fg: cli.fg.clone(),
+        accent: cli.accent.clone(),
+        overrides: cli.override_colors.clone(),
+    };
+    colors.validate()?;
+    let units = match cli.unit_locale.as_deref() {
+        Some("auto") => ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+            .map_or(&[][..], |locale| unit_table(&locale)),
+        Some(locale) => unit_table(locale),
+        None => &[],
+    };
+    let mut icons = Icons::new(cli.icon_set);
+    let widgets = match &cli.command {
+        Command::Combo { commands, .. } => commands.iter().collect(),
+        command => vec![command],
+    };
+    for command in widgets {
+        let Command::Battery {
+            battery_icon_charging,
+            battery_icon_low,
+            battery_icon_normal,
+            ..
+        } = command
+        else {
+            continue;
+        };
+        // the custom icons replace the steps of the ramp they stand for
+        let (low, normal) = BATTERY_RAMP.split_at(2);
+        for (names, icon) in [
+            (&["charging"][..], battery_icon_charging),
+            (low, battery_icon_low),
+            (normal, battery_icon_normal),
+        ] {
+            if let Some(icon) = icon {
+                for name in names {
+                    icons.set(name, icon.clone());
+                }
+            }
+        }
+    }
+    let style = Style {
+        colors,
+        resolved: OnceCell::new(),
+        icons,
+        hide_label: cli.hide_label,
+        no_icons: cli.no_icons,
+        units,
+        templates: config.format.iter().cloned().collect(),
+    };
+    // only the i3bar output uses these, so the theme is only built when they're given
+    let background = cli
+        .background_color
+        .as_deref()
+        .map(|color| color::resolve(color, style.theme()).context("resolving --background-color"))
+        .transpose()?;
+    let border = cli
+        .border_color
+        .as_deref()
+        .map(|color| color::resolve(color, style.theme()).context("resolving --border-color"))
+        .transpose()?;
+    let output = Output {
+        format: cli.output.resolve(io::stdout().is_terminal()),
+        align_right: cli.align_right,
+        lines: cli.lines,
+        hide_zero: cli.hide_zero,
+        min_width: cli.min_width,
+        align: cli.align,
+        background,
+        border,
+        border_px: cli.border_px,
+    };
+    Ok((style, output))
+}
+
+/// The flag combinations clap can't reject on its own, so they exit with 2 like bad flags rather
+/// than rendering a ⚠ block on every tick
+fn check_usage(command: &Command) -> Result<()> {
+    match command {
+        Command::Battery {
+            device_path: None, ..
+        } => Err(anyhow!(
+            "--device-path is required unless battery.device_path is in the config"
+        )),
+        #[cfg(feature = "time")]
+        Command::Countdown { until, .. } => {
+            countdown::parse_target(until, chrono::Local::now()).map(|_| ())
+        }
+        // kept in the CLI so configs and bar setups naming them fail with a clear error
+        #[cfg(not(feature = "time"))]
+        Command::Time { .. } | Command::TimeZh { .. } | Command::Countdown { .. } => {
+            Err(anyhow!("built without feature `time`"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Print a table of the known external tools, whether they are installed and their versions
+fn doctor(system: &process::System) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "{:<18}{:<9}{:<28}VERSION", "TOOL", "STATUS", "USED BY")?;
+    for (name, version_args, widgets) in process::KNOWN_TOOLS {
+        let program = system.tools.resolve(name);
+        let (status, version) = match process::version(program, version_args, system.timeout) {
+            Ok(version) => ("found", version.unwrap_or_default()),
+            Err(err) if process::is_not_found(&err) => ("missing", String::new()),
+            Err(err) => ("error", format!("{:#}", err)),
+        };
+        let row = format!("{:<18}{:<9}{:<28}{}", name, status, widgets, version);
+        writeln!(out, "{}", row.trim_end())?;
+    }
+    out.flush()
+}
+
+/// How often `--interval` updates, `None` for a single run
+fn update_interval(cli: &Cli) -> Option<Duration> {
+    match &cli.command {
+        Command::Battery {
+            watch: Some(watch), ..
+        } => Some(Duration::from_secs(*watch)),
+        _ => cli.interval.map(Duration::from_secs_f64),
+    }
+}
+
+/// Load the config again after it was saved, `Ok(None)` if no setting changed. `config` is
+/// only replaced once the new one is applied, so an error keeps the old one running
+fn reload(
+    cli: &Cli,
+    matches: &clap::ArgMatches,
+    config: &mut config::Config,
+) -> Result<Option<(Cli, Style, Output)>> {
+    let new = config::Config::load(cli.config.as_deref())?;
+    let changes = config.changes(&new);
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    let reconfigured = reconfigure(matches, &new)?;
+    log!(Info, "config", "reloaded {}", changes.join(", "));
+    for name in changes.iter().filter(|name| config::needs_restart(name)) {
+        log!(
+            Warn,
+            "config",
+            "`{}` only takes effect after a restart",
+            name
+        );
+    }
+    *config = new;
+    Ok(Some(reconfigured))
+}
+
+fn run(
+    cli: &Cli,
+    matches: &clap::ArgMatches,
+    mut config: config::Config,
+    style: &Style,
+    output: &Output,
+) -> Result<()> {
+    let system = process::System {
+        timeout: Duration::from_secs_f64(cli.cmd_timeout),
+        tools: process::Tools::new(&cli.tool_paths),
+    };
+    if let Command::SinkVolume {
+        set: Some(pct),
+        max_volume,
+    } = cli.command
+    {
+        pulseaudio::set_volume(&system, pct, max_volume)?;
+    }
+    let cached;
+    // a cached reading would be from before `--set`
+    let cache = match cli.command {
+        Command::SinkVolume { set: Some(_), .. } => None,
+        _ => cli.cache,
+    };
+    let source: &dyn CommandSource = match cache {
+        Some(millis) => {
+            cached = cache::CachedSource {
+                inner: &system,
+                ttl: Duration::from_millis(millis),
+            };
+            &cached
+        }
+        None => &system,
+    };
+    if let Command::PrintTheme | Command::ColorTest = cli.command {
+        let preview = ThemePreview {
+            theme: style.theme().clone(),
+            single_line: matches!(cli.command, Command::ColorTest),
+        };
+        return ignore_broken_pipe(output.print(&preview, style));
+    }
+    if let Command::Doctor = cli.command {
+        return ignore_broken_pipe(doctor(&system));
+    }
+    let mut samples = Samples::default();
+    let Some(mut interval) = update_interval(cli) else {
+        let detail = cli.show_detail && std::env::var("BLOCK_BUTTON").as_deref() == Ok("3");
+        let widget = match fetch(&cli.command, source, &SystemClock, &mut samples, detail) {
+            Ok(widget) => widget,
+            Err(err) if cli.strict => return Err(err),
+            Err(err) if process::is_not_found(&err) => {
+                log!(Warn, cli.command.name(), "{:#}", err);
+                if cli.quiet {
+                    return ignore_broken_pipe(output.print_empty());
+                }
+                Box::new(Fallback::for_error(&cli.command, &err))
+            }
+            Err(err) => {
+                log!(Error, cli.command.name(), "{:#}", err);
+                Box::new(Fallback::for_error(&cli.command, &err))
+            }
+        };
+        if detail {
+            notify_detail(cli.command.name(), widget.as_ref(), &system);
+        }
+        if cli.metrics_addr.is_some() {
+            log!(
+                Warn,
+                "metrics",
+                "--metrics-addr only serves anything with --interval"
+            );
+        }
+        return ignore_broken_pipe(output.print(widget.as_ref(), style));
+    };
+    if let Some(addr) = cli.metrics_addr {
+        metrics::serve(addr)?;
+    }
+    signal::install_refresh_handler()?;
+    signal::install_toggle_hidden_handler()?;
+    signal::install_quit_handler()?;
+    let name = cli.command.name();
+    let mut errors = ErrorDedup::default();
+    let mut debounce = events::Debounce::default();
+    if let Some(source) = events::source(&cli.command) {
+        events::spawn(source, &system);
+    }
+    if let Some(path) = cli.config.clone().or_else(config::default_path) {
+        events::watch_config(path, &system);
+    }
+    let mut reloaded = None;
+    let mut hidden = false;
+    while !signal::QUIT.load(Ordering::SeqCst) {
+        let mut config_error = None;
+        if events::CONFIG_CHANGED.swap(false, Ordering::SeqCst) {
+            match reload(cli, matches, &mut config) {
+                Ok(Some(new)) => reloaded = Some(new),
+                Ok(None) => {}
+                Err(err) => {
+                    log!(Error, "config", "keeping the old config: {:#}", err);
+                    config_error = Some(err);
+                }
+            }
+        }
+        let (cli, style, output) = match &reloaded {
+            Some((cli, style, output)) => (cli, style, output),
+            None => (cli, style, output),
+        };
+        // the new config may leave the interval to the command line, which has none
+        interval = update_interval(cli).unwrap_or(interval);
+        if signal::TOGGLE_HIDDEN.swap(false, Ordering::SeqCst) {
+            hidden = !hidden;
+        }
+        if hidden {
+            if let Err(err) = output.print_empty() {
+                return ignore_broken_pipe(Err(err));
+            }
+            events::wait(interval, &mut debounce);
+            continue;
+        }
+        // shown for one update, after which the old config carries on
+        if let Some(err) = config_error {
+            if let Err(err) = output.print(&ConfigError::new(&err), style) {
+                return ignore_broken_pipe(Err(err));
+            }
+            events::wait(interval, &mut debounce);
+            continue;
+        }
+        let widget = match fetch(&cli.command, source, &SystemClock, &mut samples, false) {
+            Ok(widget) => {
+                for line in errors.ok() {
+                    log!(Warn, name, "{}", line);
+                }
+                if cli.metrics_addr.is_some() {
+                    for (name, object) in widget.metrics(name) {
+                        metrics::record(name, &object);
+                    }
+                }
+                widget
+            }
+            Err(err) if cli.strict => return Err(err),
+            Err(err) => {
+                // a missing tool is a warning, as in a single run
+                let missing = process::is_not_found(&err);
+                for line in errors.error(format!("{:#}", err)) {
+                    if missing {
+                        log!(Warn, name, "{}", line);
+                    } else {
+                        log!(Error, name, "{}", line);
+                    }
+                }
+                if cli.quiet && missing {
+                    if let Err(err) = output.print_empty() {
+                        return ignore_broken_pipe(Err(err));
+                    }
+                    events::wait(interval, &mut debounce);
+                    continue;
                 }
+                Box::new(Fallback::for_error(&cli.command, &err))
+            }
+        };
+        // the bar went away, which is the normal way to be stopped besides a signal
+        if let Err(err) = output.print(widget.as_ref(), style) {
+            return ignore_broken_pipe(Err(err));
+        }
+        events::wait(interval, &mut debounce);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn escapes_every_pango_special_character() {
+        assert_eq!(
+            pango_escape(r#"<Tom & Jerry's "VM">"#),
+            "&lt;Tom &amp; Jerry&apos;s &quot;VM&quot;&gt;"
+        );
+        assert_eq!(pango_escape("win11 (büro)"), "win11 (büro)");
+        // text is text, even when it looks escaped already
+        assert_eq!(pango_escape("&amp;"), "&amp;amp;");
+        assert_eq!(Span::new("a&b").to_string(), "<span>a&amp;b</span>");
+        assert_eq!(
+            pango!("<i>", color = "#fff"),
+            "<span color=\"#fff\">&lt;i&gt;</span>"
+        );
+        // markup we built ourselves goes through the raw path untouched
+        let markup = Span::new("a&b").weight(Weight::Bold).to_string();
+        assert_eq!(
+            Span::raw(markup.clone()).to_string(),
+            format!("<span>{}</span>", markup)
+        );
+        assert_eq!(
+            pango!(raw "&amp;", color = "#fff"),
+            "<span color=\"#fff\">&amp;</span>"
+        );
+        // attribute values are escaped like text
+        assert_eq!(
+            Span::new("x").font_family("\"Sans\" & Mono").to_string(),
+            "<span font_family=\"&quot;Sans&quot; &amp; Mono\">x</span>"
+        );
+    }
+
+    #[test]
+    fn serializes_spans_in_attribute_order() {
+        assert_eq!(Span::new("").to_string(), "<span></span>");
+        // set in reverse, written in the fixed order and without a space before `>`
+        let span = Span::new("42")
+            .underline(Underline::Low)
+            .weight(Weight::UltraBold)
+            .size(Size::Pct(110))
+            .font_features(TABULAR_NUMS)
+            .font_family("Iosevka")
+            .background("#000000")
+            .color("#ffffff");
+        assert_eq!(
+            span.to_string(),
+            "<span color=\"#ffffff\" background=\"#000000\" font_family=\"Iosevka\" \
+             font_features=\"tnum=1\" font_size=\"110%\" weight=\"ultrabold\" \
+             underline=\"low\">42</span>"
+        );
+        // the last value set wins
+        assert_eq!(
+            Span::new("x").color("#111111").color("#222222").to_string(),
+            "<span color=\"#222222\">x</span>"
+        );
+    }
+
+    #[test]
+    fn nests_child_spans_after_the_text() {
+        let span = Span::new("cpu ")
+            .color("#c0caf5")
+            .child(Span::new("37").weight(Weight::Bold))
+            .child(Span::new("%").child(Span::new("!").underline(Underline::Error)));
+        assert_eq!(
+            span.to_string(),
+            "<span color=\"#c0caf5\">cpu <span weight=\"bold\">37</span>\
+             <span>%<span underline=\"error\">!</span></span></span>"
+        );
+    }
+
+    #[test]
+    fn sizes_and_weights_from_the_pango_macro() {
+        let cases = [
+            ("110%", Size::Pct(110)),
+            ("9.5pt", Size::Units(9728)),
+            ("10240", Size::Units(10240)),
+            ("huge", Size::Pct(100)),
+        ];
+        for (text, size) in cases {
+            assert_eq!(Size::from(text), size, "{}", text);
+        }
+        assert_eq!(Size::pt(9.5).to_string(), "9728");
+        assert_eq!(Weight::from("ultrabold"), Weight::UltraBold);
+        assert_eq!(Weight::from("bolder"), Weight::Normal);
+        assert_eq!(
+            pango!("5", weight = "heavy", font_size = "120%"),
+            "<span font_size=\"120%\" weight=\"heavy\">5</span>"
+        );
+    }
+
+    #[test]
+    fn parses_upower_fixtures() {
+        // (fixture, state, energy, energy_full, design, rate, cycles, hours)
+        let cases = [
+            (
+                include_str!("../tests/fixtures/upower-discharging.txt"),
+                BatteryState::Discharging,
+                38.52,
+                51.95,
+                Some(57.0),
+                Some(7.458),
+                Some(214),
+                5.2,
+            ),
+            // mWh and minutes come out the same as Wh and hours
+            (
+                include_str!("../tests/fixtures/upower-charging-mwh.txt"),
+                BatteryState::Charging,
+                38.52,
+                51.95,
+                Some(57.0),
+                Some(7.458),
+                None,
+                48.3 / 60.0,
+            ),
+        ];
+        for (fixture, state, energy, full, design, rate, cycles, hours) in cases {
+            let info = parse_battery_info(fixture).unwrap();
+            assert_eq!(info.state, state);
+            assert!((info.energy - energy).abs() < 1e-9, "{}", info.energy);
+            assert!((info.energy_full - full).abs() < 1e-9);
+            assert_eq!(info.energy_full_design, design);
+            assert!((info.energy_rate.unwrap() - rate.unwrap()).abs() < 1e-9);
+            assert_eq!(info.cycle_count, cycles);
+            assert!((info.time_to_empty_full - hours).abs() < 1e-9);
+            assert_eq!(info.raw_percentage(), 74);
+        }
+    }
+
+    #[test]
+    fn rejects_upower_without_energy() {
+        let truncated = "    state:               discharging\n    energy-full:  51.95 Wh\n";
+        assert!(parse_battery_info(truncated).is_err());
+        let unit = include_str!("../tests/fixtures/upower-discharging.txt")
+            .replace("38.52 Wh", "38.52 Ah");
+        assert!(parse_battery_info(&unit).is_err());
+    }
+
+    #[test]
+    fn parses_free_fixtures() {
+        // (fixture, total, used, swap total, swap used)
+        let cases = [
+            (
+                include_str!("../tests/fixtures/free.txt"),
+                16127852,
+                6112340,
+                8388604,
+                262144,
+            ),
+            (
+                include_str!("../tests/fixtures/free-no-swap.txt"),
+                2014184,
+                402216,
+                0,
+                0,
+            ),
+        ];
+        for (fixture, total, used, swap_total, swap_used) in cases {
+            let info = parse_memory_info(fixture).unwrap();
+            assert_eq!(
+                (info.total, info.used, info.swap_total, info.swap_used),
+                (total, used, swap_total, swap_used)
+            );
+        }
+        assert!(parse_memory_info("").is_err());
+        assert!(parse_memory_info("total\nMem: lots").is_err());
+    }
+
+    #[test]
+    fn sparklines_scale_to_the_glyphs() {
+        let nan = f64::NAN;
+        let cases = [
+            (&[][..], 8, Scale::PERCENT, ""),
+            (&[][..], 8, Scale::Auto, ""),
+            (&[0.0, 50.0, 100.0], 8, Scale::PERCENT, "▁▅█"),
+            // outside a fixed scale is clamped
+            (&[-20.0, 150.0, f64::INFINITY], 8, Scale::PERCENT, "▁██"),
+            // auto goes from the lowest to the highest value shown
+            (&[10.0, 15.0, 20.0], 8, Scale::Auto, "▁▅█"),
+            (&[1e6, 2e6, 4e6, 8e6], 8, Scale::Auto, "▁▂▄█"),
+            // a constant series sits on the bottom, whatever the scale
+            (&[42.0; 4], 8, Scale::Auto, "▁▁▁▁"),
+            (
+                &[42.0; 4],
+                8,
+                Scale::Fixed {
+                    min: 42.0,
+                    max: 42.0,
+                },
+                "▁▁▁▁",
+            ),
+            // NaNs are gaps and don't take part in auto scaling
+            (&[nan, 0.0, nan, 10.0], 8, Scale::Auto, " ▁ █"),
+            (&[nan, nan], 8, Scale::Auto, "  "),
+            (&[nan, 100.0], 8, Scale::PERCENT, " █"),
+            // only the newest `width` values
+            (&[100.0, 0.0, 50.0, 100.0], 2, Scale::PERCENT, "▅█"),
+            (&[100.0, 0.0], 0, Scale::PERCENT, ""),
+        ];
+        for (values, width, scale, line) in cases {
+            assert_eq!(
+                sparkline(values, width, scale),
+                line,
+                "{:?} {} {:?}",
+                values,
+                width,
+                scale
+            );
+        }
+    }
+
+    #[test]
+    fn progress_bars_fill_in_eighths() {
+        // (fraction, width, bar)
+        let cases = [
+            (0.0, 5, "░░░░░"),
+            (1.0, 5, "█████"),
+            (0.37, 5, "█▉░░░"),
+            (0.5, 1, "▌"),
+            // half an eighth rounds up, just under it rounds down
+            (0.0625, 1, "▏"),
+            (0.06, 1, "░"),
+            (1.5, 3, "███"),
+            (-0.2, 3, "░░░"),
+            (f64::NAN, 3, "░░░"),
+            (0.5, 0, ""),
+        ];
+        for (fraction, width, bar) in cases {
+            assert_eq!(
+                progress_bar(fraction, width),
+                bar,
+                "{:?}",
+                (fraction, width)
+            );
+        }
+    }
+
+    #[test]
+    fn memory_bars_show_ram_then_swap() {
+        let style = crate::snapshots::style(false);
+        let bars = |free| {
+            MemoryWidget {
+                info: parse_memory_info(free).unwrap(),
+                units: ByteUnits { si: false },
+                bars: 5,
+                show_hugepages: false,
+                swap: false,
+            }
+            .bars(&style)
+        };
+        // 37% of RAM and 3% of swap used
+        assert_eq!(
+            bars(include_str!("../tests/fixtures/free.txt")),
+            "<span color=\"#c0caf5\">█▉░░░</span><span color=\"#c0caf5\">▏░░░░</span>"
+        );
+        // 19% of RAM and no swap
+        assert_eq!(
+            bars(include_str!("../tests/fixtures/free-no-swap.txt")),
+            "<span color=\"#c0caf5\">█░░░░</span>"
+        );
+    }
+
+    #[test]
+    fn widths_count_the_chars_of_the_text() {
+        let cases = [
+            ("", 0),
+            ("abc", 3),
+            ("<span color=\"#ffffff\">74</span><span>%</span>", 3),
+            // one per char, however many bytes or terminal columns it takes
+            ("中文", 2),
+            ("<span font_size=\"120%\">🔋</span> 74%", 5),
+            ("&amp;&lt;", 2),
+            // a ZWJ sequence is three chars, though it shows as one glyph
+            ("👩\u{200d}💻", 3),
+        ];
+        for (markup, width) in cases {
+            assert_eq!(visible_width(markup), width, "{}", markup);
+        }
+    }
+
+    #[test]
+    fn pads_to_the_min_width() {
+        let pad = |min_width, align, markup: &str| {
+            Output {
+                format: OutputFormat::Pango,
+                align_right: false,
+                lines: 1,
+                hide_zero: false,
+                min_width,
+                align,
+                background: None,
+                border: None,
+                border_px: None,
+            }
+            .pad(markup.to_string())
+        };
+        let markup = "<span>🔋</span>中文";
+        assert_eq!(pad(6, Align::Left, markup), format!("{}   ", markup));
+        assert_eq!(pad(6, Align::Right, markup), format!("   {}", markup));
+        // the odd space goes after
+        assert_eq!(pad(6, Align::Center, markup), format!(" {}  ", markup));
+        for align in [Align::Left, Align::Right, Align::Center] {
+            assert_eq!(pad(3, align, markup), markup);
+            assert_eq!(pad(0, align, markup), markup);
+        }
+    }
+
+    #[test]
+    fn a_zero_total_shows_a_placeholder() {
+        let info = parse_memory_info(include_str!("../tests/fixtures/free-container.txt")).unwrap();
+        assert!(info.is_unknown());
+        assert_eq!((info.used_pct(), info.swap_pct()), (0, 0));
+        assert!(!info.thrash_risk());
+        let style = crate::snapshots::style(false);
+        for bars in [0, 5] {
+            let widget = MemoryWidget {
+                info: parse_memory_info(include_str!("../tests/fixtures/free-container.txt"))
+                    .unwrap(),
+                units: ByteUnits { si: true },
+                bars,
+                show_hugepages: true,
+                swap: false,
+            };
+            let rendered = widget.render(&style);
+            assert!(
+                rendered.ends_with("<span color=\"#a9b1d6\">n/a</span>"),
+                "{}",
+                rendered
+            );
+            assert!(!rendered.contains("NaN"), "{}", rendered);
+            assert_eq!(widget.detail(), None);
+        }
+    }
+
+    #[test]
+    fn hide_zero_blanks_swap_only_while_none_is_used() {
+        let output = Output {
+            format: OutputFormat::Pango,
+            align_right: false,
+            lines: 1,
+            hide_zero: true,
+            min_width: 0,
+            align: Align::Left,
+            background: None,
+            border: None,
+            border_px: None,
+        };
+        let style = crate::snapshots::style(false);
+        // (free fixture, --swap, blanked)
+        let cases = [
+            (
+                include_str!("../tests/fixtures/free-no-swap.txt"),
+                true,
+                true,
+            ),
+            (include_str!("../tests/fixtures/free.txt"), true, false),
+            // RAM is never empty
+            (
+                include_str!("../tests/fixtures/free-no-swap.txt"),
+                false,
+                false,
+            ),
+        ];
+        for (free, swap, blanked) in cases {
+            let widget = MemoryWidget {
+                info: parse_memory_info(free).unwrap(),
+                units: ByteUnits { si: false },
+                bars: 0,
+                show_hugepages: false,
+                swap,
             };
-            let time_of_day = match time.hour() {
-                0..=5 => "凌晨",
-                6..=11 => "上午",
-                12..=13 => "中午",
-                14..=17 => "下午",
-                18..=23 => "晚上",
-                _ => unreachable!(),
+            let mut out = Vec::new();
+            output.write(&mut out, &widget, &style).unwrap();
+            let expected = match blanked {
+                true => "\n".to_string(),
+                false => format!("{}\n", widget.render(&style)),
             };
-            if date {
-                println!(
-                    "{date} {time} {tod}",
-                    time = pango!(
-                        time_str,
-                        color = theme.foreground(),
-                        weight = "ultrabold",
-                        font_size = "120%",
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                expected,
+                "{:?}",
+                (swap, free)
+            );
+        }
+    }
+
+    #[test]
+    fn picks_the_theme_for_the_appearance() {
+        let light = Theme::tokyonight_light().background();
+        let dark = Theme::tokyonight_normal().background();
+        assert_ne!(light, dark);
+        // (--appearance, GTK_THEME, background of the theme picked)
+        let cases = [
+            (None, None, dark),
+            (None, Some("Adwaita:dark"), dark),
+            (None, Some("Adwaita-Dark"), dark),
+            (None, Some("Adwaita"), light),
+            (Some(Appearance::Dark), Some("Adwaita"), dark),
+            (Some(Appearance::Light), Some("Adwaita:dark"), light),
+            (Some(Appearance::Light), None, light),
+        ];
+        for (appearance, gtk_theme, background) in cases {
+            assert_eq!(
+                Theme::pick(appearance, gtk_theme).background(),
+                background,
+                "{:?}",
+                (appearance, gtk_theme)
+            );
+        }
+    }
+
+    #[test]
+    fn looks_up_the_units_by_language() {
+        let zh = UNIT_LOCALES[0].1;
+        // (locale, table)
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            ("zh_CN.UTF-8", zh),
+            ("zh_TW", zh),
+            ("zh", zh),
+            ("de_DE.UTF-8", &[]),
+            ("en_US.UTF-8", &[]),
+            ("C", &[]),
+            ("C.UTF-8", &[]),
+            ("POSIX", &[]),
+            ("", &[]),
+            ("zh-but-not-really", &[]),
+        ];
+        for (locale, table) in cases {
+            assert_eq!(unit_table(locale), *table, "{}", locale);
+        }
+
+        let mut style = crate::snapshots::style(false);
+        assert_eq!(style.localize("%"), "%");
+        style.units = unit_table("zh_CN.UTF-8");
+        // (unit, localized)
+        let cases = [
+            ("%", "％"),
+            ("GiB", "吉字节"),
+            ("GB", "吉字节"),
+            ("dB", "dB"),
+        ];
+        for (unit, localized) in cases {
+            assert_eq!(style.localize(unit), localized, "{}", unit);
+        }
+    }
+
+    #[test]
+    fn battery_detail_has_the_energy_figures() {
+        let discharging =
+            parse_battery_info(include_str!("../tests/fixtures/upower-discharging.txt")).unwrap();
+        assert_eq!(
+            discharging.detail().unwrap(),
+            "State: discharging\n\
+             Charge: 74% (38.5 of 52.0 Wh)\n\
+             Rate: 7.5 W\n\
+             Empty in: 05:12\n\
+             Health: 91% of 57.0 Wh\n\
+             Cycles: 214"
+        );
+        let charging =
+            parse_battery_info(include_str!("../tests/fixtures/upower-charging-mwh.txt")).unwrap();
+        assert_eq!(
+            charging.detail().unwrap(),
+            "State: charging\n\
+             Charge: 74% (38.5 of 52.0 Wh)\n\
+             Rate: 7.5 W\n\
+             Full in: 00:48\n\
+             Health: 91% of 57.0 Wh"
+        );
+    }
+
+    #[test]
+    fn memory_detail_has_the_breakdown() {
+        let widget = |info| MemoryWidget {
+            info,
+            units: ByteUnits { si: false },
+            bars: 0,
+            show_hugepages: false,
+            swap: false,
+        };
+        // filled in from /proc the way `get_memory_info` does
+        let meminfo = include_str!("../tests/fixtures/proc-meminfo.txt");
+        let mut info = parse_memory_info(include_str!("../tests/fixtures/free.txt")).unwrap();
+        info.buffers = parse_meminfo_field(meminfo, "Buffers").unwrap();
+        info.cached = parse_meminfo_field(meminfo, "Cached").unwrap();
+        info.huge_pages_total = parse_meminfo_field(meminfo, "HugePages_Total").unwrap();
+        info.huge_pages_free = parse_meminfo_field(meminfo, "HugePages_Free").unwrap();
+        info.pressure =
+            parse_memory_pressure(include_str!("../tests/fixtures/proc-pressure-memory.txt"));
+        assert_eq!(
+            widget(info).detail().unwrap(),
+            "Used: 5969 MiB of 15749 MiB (37%)\n\
+             Buffers: 402 MiB\n\
+             Cached: 6592 MiB\n\
+             Swap: 256 MiB of 8191 MiB (3%)\n\
+             Huge pages: 2 of 8\n\
+             Stalled: 12% of the last 10s"
+        );
+        // only what `free` has, without swap
+        let info = parse_memory_info(include_str!("../tests/fixtures/free-no-swap.txt")).unwrap();
+        assert_eq!(
+            widget(info).detail().unwrap(),
+            "Used: 392 MiB of 1966 MiB (19%)\nBuffers: 0 MiB\nCached: 0 MiB"
+        );
+    }
+
+    #[test]
+    fn combo_detail_joins_the_parts_that_have_one() {
+        let volume = pulseaudio::parse(
+            include_str!("../tests/fixtures/pactl-get-sink-volume.txt"),
+            include_str!("../tests/fixtures/pactl-get-sink-mute-no.txt"),
+        )
+        .unwrap();
+        let mut combo = Combo {
+            parts: vec![
+                ("volume", Box::new(volume)),
+                (
+                    "hostname",
+                    Box::new(hostname::Hostname {
+                        name: "laptop".to_string(),
+                    }),
+                ),
+                (
+                    "virsh",
+                    Box::new(
+                        virsh::parse(include_str!("../tests/fixtures/virsh-list-all.txt")).unwrap(),
                     ),
-                    tod = pango!(time_of_day, color = theme.white(),),
-                    date = {
-                        let y = time.year();
-                        let m = time.month();
-                        let d = time.day();
-                        format!(
-                            "{y}{nian}{m}{yue}{d}{ri}",
-                            y = pango!(
-                                y,
-                                color = theme.foreground(),
-                                font_size = "110%",
-                                weight = "ultrabold"
-                            ),
-                            m = pango!(
-                                m,
-                                color = theme.foreground(),
-                                font_size = "110%",
-                                weight = "ultrabold"
-                            ),
-                            d = pango!(
-                                d,
-                                color = theme.foreground(),
-                                font_size = "110%",
-                                weight = "ultrabold"
-                            ),
-                            nian = pango!("年", color = theme.white()),
-                            yue = pango!("月", color = theme.white()),
-                            ri = pango!("日", color = theme.white()),
-                        )
-                    },
-                );
-            } else {
-                println!("{} {}", time_str, time_of_day);
-            }
-            Ok(())
-        }
-        Memory => {
-            let memory_info = get_memory_info()?;
-            println!(
-                "{used}{div}{total}{mib}",
-                used = pango!(
-                    memory_info.used_mib(),
-                    color = theme.foreground(),
-                    weight = "ultrabold",
-                    font_size = "110%",
                 ),
-                total = pango!(
-                    memory_info.total_mib(),
-                    color = theme.foreground(),
-                    weight = "ultrabold",
-                    font_size = "110%",
+            ],
+            separator: " ".to_string(),
+        };
+        assert_eq!(
+            combo.detail().unwrap(),
+            "Left: 70%\nRight: 60%\nMuted: no\n\n\
+             win11: running\nbuilder: running\ndebian: shut off"
+        );
+        combo.parts.drain(..1);
+        combo.parts.pop();
+        assert_eq!(combo.detail(), None);
+    }
+
+    #[test]
+    fn combo_exports_each_part_under_its_own_name() {
+        let combo = Combo {
+            parts: vec![
+                (
+                    "hostname",
+                    Box::new(hostname::Hostname {
+                        name: "laptop".to_string(),
+                    }),
                 ),
-                div = pango!("/", color = theme.white()),
-                mib = pango!("MiB", color = theme.white()),
-            );
-            Ok(())
-        }
-        SinkVolume => {
-            let volume_info = pulseaudio::volume()?;
-            println!(
-                "{icon} {left}{pct}",
-                icon = pango!(volume_info.left_icon(), font_size = "120%"),
-                left = pango!(
-                    volume_info.left_pct(),
-                    color = theme.foreground(),
-                    weight = "ultrabold",
-                    font_size = "110%",
+                (
+                    "virsh",
+                    Box::new(
+                        virsh::parse(include_str!("../tests/fixtures/virsh-list-all.txt")).unwrap(),
+                    ),
                 ),
-                pct = pango!("%", color = theme.white()),
+            ],
+            separator: " ".to_string(),
+        };
+        let metrics = combo.metrics("combo");
+        let names = metrics.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(names, ["hostname", "virsh"]);
+        assert_eq!(
+            metrics[1].1.to_string(),
+            combo.parts[1].1.to_json().to_string()
+        );
+    }
+
+    #[test]
+    fn a_reload_starts_over_from_the_command_line() {
+        let _env = crate::snapshots::test_env();
+        let matches = Cli::command()
+            .try_get_matches_from([
+                "i3widgets",
+                "--fg",
+                "green",
+                "combo",
+                "--widgets",
+                "memory,sink-volume",
+            ])
+            .unwrap();
+        let old =
+            config::Config::parse("[memory]\nsi = true\n[global]\naccent = \"red\"\n").unwrap();
+        let new = config::Config::parse(
+            "[global]\nfg = \"red\"\ninterval = 2\n[sink_volume]\nmax_volume = 150\n",
+        )
+        .unwrap();
+        assert_eq!(
+            old.changes(&new),
+            [
+                "memory.si",
+                "global.accent",
+                "global.fg",
+                "global.interval",
+                "sink_volume.max_volume"
+            ]
+        );
+        let (cli, _, _) = reconfigure(&matches, &new).unwrap();
+        assert_eq!(cli.fg.as_deref(), Some("green"));
+        // nothing is left over from the old config
+        assert_eq!(cli.accent, None);
+        assert_eq!(update_interval(&cli), Some(Duration::from_secs(2)));
+        let Command::Combo { commands, .. } = &cli.command else {
+            panic!("{:?}", cli.command.name());
+        };
+        assert!(matches!(
+            commands[..],
+            [
+                Command::Memory { si: false, .. },
+                Command::SinkVolume {
+                    max_volume: 150,
+                    ..
+                }
+            ]
+        ));
+        // a config that doesn't apply is an error rather than half a reload
+        let broken = config::Config::parse("[global]\noutput = \"paper\"\n").unwrap();
+        let err = reconfigure(&matches, &broken).err().unwrap();
+        assert!(format!("{:#}", err).contains("global.output"), "{:#}", err);
+    }
+
+    #[test]
+    fn a_config_error_shows_as_a_block() {
+        let err = anyhow!("line 2: `global.fg`: expected a string");
+        let widget = ConfigError::new(&err.context("in config.toml"));
+        let style = crate::snapshots::style(false);
+        assert!(widget.render(&style).ends_with("config error</span>"));
+        assert_eq!(
+            widget.render(&crate::snapshots::style(true)),
+            "<span color=\"#a9b1d6\">config error</span>"
+        );
+        assert_eq!(
+            widget.to_json().to_string(),
+            r#"{"error":"config error: in config.toml: line 2: `global.fg`: expected a string"}"#
+        );
+    }
+
+    #[test]
+    fn combo_joins_its_widgets_and_marks_a_failed_one() {
+        let _env = crate::snapshots::test_env();
+        let command = |args: &[&str]| {
+            Cli::try_parse_from([&["i3widgets"][..], args].concat())
+                .unwrap()
+                .command
+        };
+        let combo = Command::Combo {
+            widgets: Vec::new(),
+            separator: " | ".to_string(),
+            commands: vec![
+                command(&["gpu-fan"]),
+                command(&["sound-cards"]),
+                // there is no fixture for `wg`
+                command(&["wireguard", "--interface", "wg0"]),
+            ],
+        };
+        let mut samples = Samples::default();
+        let source = crate::snapshots::FixtureSource;
+        let clock = crate::snapshots::FixedClock;
+        let style = crate::snapshots::style(false);
+        let part = |args: &[&str]| {
+            fetch(
+                &command(args),
+                &source,
+                &clock,
+                &mut Samples::default(),
+                false,
+            )
+            .unwrap()
+            .render(&style)
+        };
+        let widget = fetch(&combo, &source, &clock, &mut samples, false).unwrap();
+        let separator = "<span color=\"#a9b1d6\"> | </span>";
+        assert_eq!(
+            widget.render(&style),
+            format!(
+                "{}{separator}{}{separator}<span font_size=\"120%\">✗</span>",
+                part(&["gpu-fan"]),
+                part(&["sound-cards"]),
+            )
+        );
+    }
+
+    /// Remembers the commands run, answering them from the fixtures
+    struct Recording(std::cell::RefCell<Vec<String>>);
+
+    impl CommandSource for Recording {
+        fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+            self.0
+                .borrow_mut()
+                .push(format!("{} {}", program, args.join(" ")));
+            crate::snapshots::FixtureSource.output(program, args)
+        }
+    }
+
+    #[test]
+    fn the_palette_takes_fg_and_accent() {
+        let theme = Theme::tokyonight_normal();
+        let plain = Palette::new(&theme, None, None).unwrap();
+        assert_eq!(
+            (plain.primary, plain.secondary),
+            (theme.foreground(), theme.white())
+        );
+        let overridden = Palette::new(&theme, Some("#f00"), Some("green")).unwrap();
+        assert_eq!(
+            (overridden.primary, overridden.secondary),
+            (theme.green(), Color(0xff, 0, 0))
+        );
+        let Err(err) = Palette::new(&theme, None, Some("crimson")) else {
+            panic!("crimson isn't a theme color");
+        };
+        assert!(format!("{:#}", err).starts_with("resolving --accent: unknown color `crimson`"));
+    }
+
+    #[test]
+    fn timeouts_are_finite_seconds() {
+        for valid in ["0", "0.5", "2", "86400"] {
+            assert!(parse_timeout(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["-1", "nan", "inf", "1e20", "two"] {
+            assert!(parse_timeout(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn intervals_are_at_least_the_minimum() {
+        for valid in ["0.1", "1", "5.5", "86400"] {
+            assert!(parse_interval(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["0", "0.05", "-1", "nan", "inf", "1e20"] {
+            assert!(parse_interval(invalid).is_err(), "{}", invalid);
+        }
+        assert!(Cli::try_parse_from(["i3widgets", "battery", "--watch", "0"]).is_err());
+    }
+
+    #[test]
+    fn auto_output_is_ansi_only_on_a_terminal() {
+        let cli = Cli::try_parse_from(["i3widgets", "memory"]).unwrap();
+        assert_eq!(cli.output, OutputFormat::Auto);
+        assert_eq!(cli.output.resolve(true), OutputFormat::Ansi);
+        assert_eq!(cli.output.resolve(false), OutputFormat::Pango);
+        // an explicit format is kept either way
+        for terminal in [false, true] {
+            assert_eq!(OutputFormat::I3bar.resolve(terminal), OutputFormat::I3bar);
+            assert_eq!(OutputFormat::Pango.resolve(terminal), OutputFormat::Pango);
+        }
+    }
+
+    #[test]
+    fn looks_up_the_sink_only_for_the_detail() {
+        let _env = crate::snapshots::test_env();
+        let cli = Cli::try_parse_from(["i3widgets", "sink-volume"]).unwrap();
+        for detail in [false, true] {
+            let source = Recording(Default::default());
+            let clock = crate::snapshots::FixedClock;
+            let widget = fetch(
+                &cli.command,
+                &source,
+                &clock,
+                &mut Samples::default(),
+                detail,
+            )
+            .unwrap();
+            let ran = source.0.into_inner();
+            assert_eq!(ran.contains(&"pactl list sinks".to_string()), detail);
+            assert_eq!(ran.contains(&"pactl get-default-sink".to_string()), detail);
+            assert_eq!(widget.detail().unwrap().starts_with("Sink: "), detail);
+        }
+    }
+
+    #[test]
+    fn only_styled_output_builds_the_theme() {
+        let widget = hostname::Hostname {
+            name: "laptop".to_string(),
+        };
+        let style = crate::snapshots::style(false);
+        let mut output = Output {
+            format: OutputFormat::Json,
+            align_right: false,
+            lines: 1,
+            hide_zero: false,
+            min_width: 0,
+            align: Align::Left,
+            background: None,
+            border: None,
+            border_px: None,
+        };
+        output.write(&mut Vec::new(), &widget, &style).unwrap();
+        assert!(style.resolved.get().is_none());
+        output.format = OutputFormat::Pango;
+        output.write(&mut Vec::new(), &widget, &style).unwrap();
+        assert!(style.resolved.get().is_some());
+    }
+
+    #[test]
+    fn validates_colors_without_a_theme() {
+        let valid = ColorSpec {
+            theme: vec![("red".to_string(), "#f00".to_string())],
+            fg: Some("cyan".to_string()),
+            overrides: vec![("battery.pct".to_string(), "index_16".to_string())],
+            ..ColorSpec::default()
+        };
+        assert!(valid.validate().is_ok());
+        assert!(valid.resolve().is_ok());
+        for invalid in [
+            ColorSpec {
+                theme: vec![("pink".to_string(), "#f00".to_string())],
+                ..ColorSpec::default()
+            },
+            ColorSpec {
+                accent: Some("#12".to_string()),
+                ..ColorSpec::default()
+            },
+            ColorSpec {
+                overrides: vec![("battery.pct".to_string(), "crimson".to_string())],
+                ..ColorSpec::default()
+            },
+        ] {
+            assert!(invalid.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn repeated_errors_are_logged_once_with_a_count() {
+        let mut errors = ErrorDedup::default();
+        assert_eq!(errors.error("timed out".to_string()), ["timed out"]);
+        assert!(errors.error("timed out".to_string()).is_empty());
+        assert!(errors.error("timed out".to_string()).is_empty());
+        assert_eq!(
+            errors.error("not found".to_string()),
+            ["`timed out` repeated 2 more times", "not found"]
+        );
+        assert_eq!(errors.ok(), Vec::<String>::new());
+        assert_eq!(errors.error("not found".to_string()), ["not found"]);
+        assert!(errors.error("not found".to_string()).is_empty());
+        assert_eq!(errors.ok(), ["`not found` repeated 1 more times"]);
+        assert!(errors.ok().is_empty());
+    }
+
+    #[test]
+    fn median_of_the_window() {
+        for (values, expected) in [
+            (&[][..], None),
+            (&[40][..], Some(40)),
+            (&[50, 10, 30][..], Some(30)),
+            (&[40, 10, 30, 20][..], Some(20)),
+            (&[90, 90, 10, 90, 91][..], Some(90)),
+        ] {
+            assert_eq!(median(values), expected, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn thrash_risk_needs_both_ram_and_swap() {
+        for (ram_pct, swap_pct, expected) in [
+            (90, 50, true),
+            (100, 100, true),
+            (89, 100, false),
+            (100, 49, false),
+            (0, 0, false),
+        ] {
+            assert_eq!(
+                thrash_risk(ram_pct, swap_pct),
+                expected,
+                "ram {}% swap {}%",
+                ram_pct,
+                swap_pct
             );
-            Ok(())
-        }
-        Brightness => {
-            let brightness_info = brightness::info()?;
-            println!(
-                "{icon} {value}{pct}",
-                icon = pango!(brightness_info.icon(), font_size = "120%"),
-                value = pango!(
-                    brightness_info.pct(),
-                    color = theme.foreground(),
-                    weight = "ultrabold",
-                    font_size = "110%",
-                ),
-                pct = pango!("%", color = theme.white()),
+        }
+    }
+
+    #[test]
+    fn trend_ignores_changes_below_the_minimum() {
+        for (prev, next, expected) in [
+            (50, 55, Trend::Rising),
+            (50, 52, Trend::Steady),
+            (50, 50, Trend::Steady),
+            (50, 48, Trend::Steady),
+            (50, 45, Trend::Falling),
+            (2, 0, Trend::Steady),
+            (u64::MAX - 1, u64::MAX, Trend::Steady),
+        ] {
+            assert_eq!(
+                Trend::between(prev, next, 5),
+                expected,
+                "{} -> {}",
+                prev,
+                next
+            );
+        }
+        assert_eq!(Trend::between(50, 51, 0), Trend::Rising);
+        assert_eq!(Trend::between(50, 50, 0), Trend::Steady);
+    }
+
+    #[test]
+    fn ramps_from_the_first_icon_to_the_last() {
+        for (pct, expected) in [
+            (0, "battery_0"),
+            (9, "battery_0"),
+            (10, "battery_1"),
+            (50, "battery_5"),
+            (99, "battery_9"),
+            (100, "battery_9"),
+            (150, "battery_9"),
+        ] {
+            assert_eq!(ramp_icon(&BATTERY_RAMP, pct), expected, "{}%", pct);
+        }
+        assert_eq!(ramp_icon(&["low", "high"], 49), "low");
+        assert_eq!(ramp_icon(&["low", "high"], 50), "high");
+    }
+
+    #[test]
+    fn parses_the_power_profile() {
+        for (output, expected) in [
+            ("performance\n", Some(PowerProfile::Performance)),
+            ("balanced\n", Some(PowerProfile::Balanced)),
+            ("power-saver\n", Some(PowerProfile::PowerSaver)),
+            ("power_saver\n", None),
+            ("Balanced\n", None),
+            ("", None),
+        ] {
+            assert_eq!(PowerProfile::parse(output), expected, "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn parses_the_power_source() {
+        for (kind, online, usb_type, expected) in [
+            ("Mains\n", "1\n", None, Some(PowerSource::Mains)),
+            (
+                "USB\n",
+                "1\n",
+                Some("C PD PD_PPS\n"),
+                Some(PowerSource::Usb),
+            ),
+            (
+                "USB\n",
+                "1\n",
+                Some("C [PD] PD_PPS\n"),
+                Some(PowerSource::UsbPd),
+            ),
+            (
+                "USB\n",
+                "1\n",
+                Some("C PD [PD_PPS]\n"),
+                Some(PowerSource::UsbPd),
+            ),
+            ("USB\n", "1\n", None, Some(PowerSource::Usb)),
+            ("Wireless\n", "1\n", None, Some(PowerSource::Wireless)),
+            ("Mains\n", "0\n", None, None),
+            ("Battery\n", "1\n", None, None),
+            ("UPS\n", "1\n", None, None),
+        ] {
+            assert_eq!(
+                PowerSource::parse(kind, online, usb_type),
+                expected,
+                "{:?} {:?} {:?}",
+                kind,
+                online,
+                usb_type
+            );
+        }
+    }
+
+    #[test]
+    fn worn_out_below_the_threshold() {
+        for (health_pct, threshold, expected) in [
+            (79.9, 80, true),
+            (80.0, 80, false),
+            (100.0, 80, false),
+            (104.2, 100, false),
+            (0.0, 0, false),
+            (f64::NAN, 80, false),
+        ] {
+            assert_eq!(
+                worn_out(health_pct, threshold),
+                expected,
+                "{}% against {}%",
+                health_pct,
+                threshold
             );
-            Ok(())
         }
-        VirshActive => {
-            let state = virsh::list()?;
-            print!("{state:?}");
-            Ok(())
+    }
+
+    #[test]
+    fn theme_preview_shows_every_color_field() {
+        let style = crate::snapshots::style(false);
+        let theme = Theme::tokyonight_normal();
+        let colors = Theme::FIELDS
+            .iter()
+            .map(|&name| (name, theme.by_name(name).unwrap().to_string()))
+            .collect::<Vec<_>>();
+        let swatches = ThemePreview {
+            theme: theme.clone(),
+            single_line: false,
+        }
+        .render(&style);
+        let lines = swatches.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), colors.len(), "{}", swatches);
+        for (line, (name, color)) in lines.iter().zip(&colors) {
+            assert!(line.starts_with(&format!("{}: ", name)), "{}", line);
+            assert!(line.contains(&format!("color=\"{}\"", color)), "{}", line);
+        }
+        let blocks = ThemePreview {
+            theme,
+            single_line: true,
+        }
+        .render(&style);
+        assert_eq!(blocks.matches("██").count(), colors.len(), "{}", blocks);
+        for (name, color) in &colors {
+            let block = format!(">{}</span><span color=\"{}\">██</span>", name, color);
+            assert!(blocks.contains(&block), "{} lacks {}", blocks, block);
+        }
+    }
+
+    #[test]
+    fn byte_units_switch_at_their_base() {
+        let binary = ByteUnits { si: false };
+        let decimal = ByteUnits { si: true };
+        for (units, bytes, expected) in [
+            (binary, 1023, (1023.0, "B")),
+            (binary, 1024, (1.0, "KiB")),
+            (binary, 1000, (1000.0, "B")),
+            (binary, 1536 << 20, (1.5, "GiB")),
+            (decimal, 999, (999.0, "B")),
+            (decimal, 1000, (1.0, "KB")),
+            (decimal, 1024, (1.024, "KB")),
+            (decimal, 1_500_000_000, (1.5, "GB")),
+            (binary, 1 << 50, (1024.0, "TiB")),
+            (decimal, 2_000_000_000_000_000, (2000.0, "TB")),
+        ] {
+            assert_eq!(units.human(bytes), expected, "{} bytes", bytes);
+        }
+        assert_eq!(binary.scale(5 << 20, 2), 5);
+        assert_eq!(decimal.scale(5 << 20, 2), 5);
+        assert_eq!(decimal.scale(5_000_000, 2), 5);
+        assert_eq!(binary.scale(5_000_000, 2), 4);
+        assert_eq!(binary.suffix(9), "TiB");
+    }
+
+    /// Every icon name the widgets and their fallback blocks ask [`Icons`] for
+    const WIDGET_ICONS: &[&str] = &[
+        "battery_0",
+        "battery_1",
+        "battery_2",
+        "battery_3",
+        "battery_4",
+        "battery_5",
+        "battery_6",
+        "battery_7",
+        "battery_8",
+        "battery_9",
+        "charging",
+        "battery_wear",
+        "profile_performance",
+        "profile_balanced",
+        "profile_power_saver",
+        "volume_muted",
+        "volume_low",
+        "volume_medium",
+        "volume_high",
+        "brightness_0",
+        "brightness_1",
+        "brightness_2",
+        "brightness_3",
+        "brightness_4",
+        "vm",
+        "memory",
+        "swap_warning",
+        "cpu",
+        "fan",
+        "disk",
+        "rising",
+        "falling",
+        "network",
+        "rx",
+        "tx",
+        "countdown",
+        "host",
+        "user",
+        "vpn",
+        "sound_card",
+        "lid",
+        "error",
+        "failed",
+        "timeout",
+    ];
+
+    #[test]
+    fn every_icon_set_has_every_icon() {
+        // what the ramps and the state helpers can pick is declared
+        let mut picked = BATTERY_RAMP.to_vec();
+        picked.extend(
+            (0..=100).map(|current| brightness::BrightnessInfo { current, max: 100 }.icon_name()),
+        );
+        for mute in ["Mute: no", "Mute: yes"] {
+            picked.extend((0..=65530).step_by(655).map(|raw| {
+                let volume = format!(
+                    "Volume: front-left: {raw} / 0% / 0.00 dB,   front-right: {raw} / 0% / 0.00 dB"
+                );
+                pulseaudio::parse(&volume, mute).unwrap().left_icon_name()
+            }));
+        }
+        picked.extend(
+            [
+                PowerProfile::Performance,
+                PowerProfile::Balanced,
+                PowerProfile::PowerSaver,
+            ]
+            .map(|profile| profile.icon_name()),
+        );
+        picked.extend(
+            [Trend::Rising, Trend::Falling, Trend::Steady]
+                .iter()
+                .filter_map(Trend::icon_name),
+        );
+        for subcommand in Cli::command().get_subcommands() {
+            let args = ["i3widgets", subcommand.get_name()];
+            if let Ok(cli) = Cli::try_parse_from(args) {
+                picked.push(cli.command.fallback_icon());
+            }
+        }
+        for name in picked {
+            assert!(WIDGET_ICONS.contains(&name), "{} isn't declared", name);
+        }
+
+        let mut declared = WIDGET_ICONS.to_vec();
+        declared.sort_unstable();
+        for (set, entries) in [
+            (IconSet::Emoji, EMOJI_ICONS),
+            (IconSet::Nerd, NERD_ICONS),
+            (IconSet::Ascii, ASCII_ICONS),
+        ] {
+            // no duplicates and none that no widget uses
+            let mut names = entries.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            names.sort_unstable();
+            assert_eq!(names, declared, "{:?}", set);
+            let icons = Icons::new(set);
+            for name in WIDGET_ICONS {
+                let icon = icons.get(name);
+                assert!(icon != "?" && !icon.is_empty(), "{:?} lacks {}", set, name);
+            }
         }
     }
 }
@@ -0,0 +1,173 @@
+use std::fmt::Write;
+
+use clap::{builder::PossibleValue, Arg};
+
+/// Escape `text` for roff, including a leading `.` or `'` that would start a request
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            match line.starts_with(['.', '\'']) {
+                true => format!("\\&{}", line),
+                false => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn title(out: &mut String, name: &str, about: &str) {
+    let _ = writeln!(out, ".TH \"{}\" 1", name.to_uppercase());
+    let _ = writeln!(out, ".SH NAME");
+    let _ = writeln!(out, "{} \\- {}", escape(name), escape(about));
+}
+
+/// `.TP` paragraphs for `args`
+fn options<'a>(out: &mut String, args: impl Iterator<Item = &'a Arg>) {
+    let mut args = args.filter(|arg| !arg.is_hide_set()).peekable();
+    if args.peek().is_none() {
+        return;
+    }
+    let _ = writeln!(out, ".SH OPTIONS");
+    for arg in args {
+        let takes_value = arg.get_num_args().is_some_and(|num| num.takes_values());
+        let value = arg
+            .get_value_names()
+            .and_then(|names| names.first())
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| arg.get_id().to_string().to_uppercase());
+        let _ = writeln!(out, ".TP");
+        match (arg.get_long(), takes_value) {
+            (Some(long), true) => {
+                let _ = writeln!(out, "\\fB\\-\\-{}\\fR \\fI<{}>\\fR", escape(long), value);
+            }
+            (Some(long), false) => {
+                let _ = writeln!(out, "\\fB\\-\\-{}\\fR", escape(long));
+            }
+            (None, _) => {
+                let _ = writeln!(out, "\\fI<{}>\\fR", value);
+            }
+        }
+        let help = arg
+            .get_long_help()
+            .or(arg.get_help())
+            .map(|help| help.to_string())
+            .unwrap_or_default();
+        if !help.is_empty() {
+            let _ = writeln!(out, "{}", escape(&help));
+        }
+        let values = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| PossibleValue::get_name(value).to_string())
+            .collect::<Vec<_>>();
+        if !values.is_empty() && arg.get_action().takes_values() {
+            let _ = writeln!(out, ".br");
+            let _ = writeln!(out, "One of: {}", escape(&values.join(", ")));
+        }
+        if let (true, Some(default)) = (takes_value, arg.get_default_values().first()) {
+            let _ = writeln!(out, ".br");
+            let _ = writeln!(out, "Default: {}", escape(&default.to_string_lossy()));
+        }
+    }
+}
+
+fn about(command: &clap::Command) -> String {
+    command
+        .get_about()
+        .map(|about| about.to_string())
+        .unwrap_or_default()
+}
+
+/// `(file name, page)` for the program and each visible subcommand
+pub fn pages(command: &mut clap::Command) -> Vec<(String, String)> {
+    // propagates the global options into the subcommands
+    command.build();
+    let name = command.get_name().to_string();
+    let subs = command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+        .collect::<Vec<_>>();
+
+    let mut main = String::new();
+    title(
+        &mut main,
+        &name,
+        about(command).lines().next().unwrap_or(&name),
+    );
+    let _ = writeln!(main, ".SH SYNOPSIS");
+    let _ = writeln!(
+        main,
+        "\\fB{}\\fR [\\fIOPTIONS\\fR] \\fICOMMAND\\fR",
+        escape(&name)
+    );
+    options(&mut main, command.get_arguments());
+    let _ = writeln!(main, ".SH COMMANDS");
+    for sub in &subs {
+        let _ = writeln!(main, ".TP");
+        let _ = writeln!(main, "\\fB{}\\fR", escape(sub.get_name()));
+        let _ = writeln!(main, "{}", escape(&about(sub)));
+        let _ = writeln!(
+            main,
+            "See \\fB{}\\-{}\\fR(1).",
+            escape(&name),
+            escape(sub.get_name())
+        );
+    }
+    let mut pages = vec![(format!("{}.1", name), main)];
+
+    for sub in subs {
+        let page_name = format!("{}-{}", name, sub.get_name());
+        let mut page = String::new();
+        title(&mut page, &page_name, &about(sub));
+        let _ = writeln!(page, ".SH SYNOPSIS");
+        let _ = writeln!(
+            page,
+            "\\fB{} {}\\fR [\\fIOPTIONS\\fR]",
+            escape(&name),
+            escape(sub.get_name())
+        );
+        // the global options are documented once on the main page
+        options(
+            &mut page,
+            sub.get_arguments().filter(|arg| !arg.is_global_set()),
+        );
+        let _ = writeln!(page, ".SH SEE ALSO");
+        let _ = writeln!(page, "\\fB{}\\fR(1)", escape(&name));
+        pages.push((format!("{}.1", page_name), page));
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn every_page_has_its_name_and_an_option() {
+        let mut command = crate::Cli::command();
+        let pages = pages(&mut command);
+        let subs = command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+            .count();
+        assert_eq!(pages.len(), subs + 1);
+        for (file, page) in &pages {
+            let name = file.strip_suffix(".1").unwrap();
+            assert!(
+                page.starts_with(&format!(".TH \"{}\" 1\n", name.to_uppercase())),
+                "{}",
+                file
+            );
+            assert!(page.contains(&escape(name)), "{}", file);
+            let options = page
+                .split_once(".SH OPTIONS\n")
+                .unwrap_or_else(|| panic!("{} has no OPTIONS", file))
+                .1;
+            assert!(options.starts_with(".TP\n\\fB\\-\\-"), "{}", file);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use crate::json;
+
+/// String fields that tell instances of a widget apart, exported as labels of the same name
+const LABELS: &[&str] = &["interface", "mount"];
+
+/// Field suffixes with the base unit they're exported in and the factor that gets there,
+/// longest first so `_bytes_per_sec` isn't taken for `_sec`
+const UNITS: &[(&str, &str, f64)] = &[
+    ("_bytes_per_sec", "_bytes_per_second", 1.0),
+    ("_kib", "_bytes", 1024.0),
+    ("_wh", "_joules", 3600.0),
+    ("_secs", "_seconds", 1.0),
+    ("_pct", "_percent", 1.0),
+];
+
+/// Fields that are the widget's headline reading, exported as just the unit, e.g.
+/// `i3widgets_battery_percent` rather than `i3widgets_battery_charge_percent`
+const HEADLINES: &[(&str, &str)] = &[("battery", "charge_pct"), ("brightness", "brightness_pct")];
+
+/// The gauges from the last successful update of each widget
+static REGISTRY: Mutex<Vec<(String, Vec<Gauge>)>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gauge {
+    /// e.g. `i3widgets_cpu_usage_percent`
+    pub name: String,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+/// A gauge for every number and bool in the widget's JSON output, in base units. Strings,
+/// nulls, arrays and nested objects have no single value and are left out.
+pub fn gauges(widget: &str, object: &json::Object) -> Vec<Gauge> {
+    let labels = object
+        .fields()
+        .iter()
+        .filter(|(key, _)| LABELS.contains(key))
+        .filter_map(|(key, value)| Some((*key, json::parse_string(value)?)))
+        .collect::<Vec<_>>();
+    object
+        .fields()
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value.as_str() {
+                "true" => 1.0,
+                "false" => 0.0,
+                value => value.parse::<f64>().ok()?,
+            };
+            let (suffix, factor) = base_unit(widget, key);
+            Some(Gauge {
+                name: format!("i3widgets_{}{}", widget.replace('-', "_"), suffix),
+                labels: labels.clone(),
+                value: value * factor,
+            })
+        })
+        .collect()
+}
+
+/// The metric name after the widget's for the field `key`, and the factor to its base unit
+fn base_unit(widget: &str, key: &str) -> (String, f64) {
+    let unit = UNITS
+        .iter()
+        .find_map(|(suffix, unit, factor)| Some((key.strip_suffix(suffix)?, *unit, *factor)));
+    match unit {
+        Some((_, unit, factor)) if HEADLINES.contains(&(widget, key)) => (unit.to_string(), factor),
+        Some((stem, unit, factor)) => (format!("_{}{}", stem, unit), factor),
+        None => (format!("_{}", key), 1.0),
+    }
+}
+
+/// The gauges in the Prometheus text exposition format, those of a name together under one
+/// `# TYPE` line as the format requires
+pub fn exposition(gauges: &[Gauge]) -> String {
+    let mut out = String::new();
+    let mut names = Vec::new();
+    for gauge in gauges {
+        if !names.contains(&&gauge.name) {
+            names.push(&gauge.name);
+        }
+    }
+    let grouped = names
+        .into_iter()
+        .flat_map(|name| gauges.iter().filter(move |gauge| &gauge.name == name));
+    let mut last_name = None;
+    for gauge in grouped {
+        if last_name != Some(&gauge.name) {
+            let _ = writeln!(out, "# TYPE {} gauge", gauge.name);
+            last_name = Some(&gauge.name);
+        }
+        out.push_str(&gauge.name);
+        if !gauge.labels.is_empty() {
+            let labels = gauge
+                .labels
+                .iter()
+                .map(|(key, value)| {
+                    let value = value
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace('\n', "\\n");
+                    format!("{}=\"{}\"", key, value)
+                })
+                .collect::<Vec<_>>();
+            let _ = write!(out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(out, " {}", gauge.value);
+    }
+    out
+}
+
+/// Replace the served gauges of `widget` with the ones from `object`, keeping those of the
+/// other widgets
+pub fn record(widget: &str, object: &json::Object) {
+    let gauges = gauges(widget, object);
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    match registry.iter_mut().find(|(name, _)| name == widget) {
+        Some((_, old)) => *old = gauges,
+        None => registry.push((widget.to_string(), gauges)),
+    }
+}
+
+/// Listen on `addr`, answering scrapes on a background thread. Binding happens before
+/// returning, so a taken port is an error here.
+pub fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr).with_context(|| format!("listening on {}", addr))?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = respond(stream);
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    // one slow client is served at a time, so it can't hold the others up for long
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        let registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+        let gauges = registry
+            .iter()
+            .flat_map(|(_, gauges)| gauges.iter().cloned())
+            .collect::<Vec<_>>();
+        ("200 OK", exposition(&gauges))
+    } else {
+        ("404 Not Found", "not found\n".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gauge(name: &str, labels: &[(&'static str, &str)], value: f64) -> Gauge {
+        Gauge {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (*key, value.to_string()))
+                .collect(),
+            value,
+        }
+    }
+
+    #[test]
+    fn a_gauge_per_number_and_bool() {
+        let object = json::Object::new()
+            .field("interface", "wlan0")
+            .field("rx_bytes_per_sec", 1536.5)
+            .field("tx_bytes_per_sec", 0u64)
+            .field("up", true)
+            .field("metered", false)
+            .field("state", "up")
+            .optional("ssid", None::<&str>)
+            .field("history", vec![1u64, 2]);
+        let labels = &[("interface", "wlan0")][..];
+        assert_eq!(
+            gauges("network", &object),
+            [
+                gauge("i3widgets_network_rx_bytes_per_second", labels, 1536.5),
+                gauge("i3widgets_network_tx_bytes_per_second", labels, 0.0),
+                gauge("i3widgets_network_up", labels, 1.0),
+                gauge("i3widgets_network_metered", labels, 0.0),
+            ]
+        );
+        let object = json::Object::new().field("usage_pct", 12u64);
+        assert_eq!(
+            gauges("gpu-fan", &object),
+            [gauge("i3widgets_gpu_fan_usage_percent", &[], 12.0)]
+        );
+    }
+
+    #[test]
+    fn labels_come_unquoted_and_go_out_escaped() {
+        let object = json::Object::new()
+            .field("mount", r#"/media/a "b" \c"#)
+            .field("free_bytes", 1u64);
+        let gauges = gauges("disk", &object);
+        assert_eq!(
+            gauges[0].labels,
+            [("mount", r#"/media/a "b" \c"#.to_string())]
+        );
+        assert_eq!(
+            exposition(&gauges),
+            "# TYPE i3widgets_disk_free_bytes gauge\n\
+             i3widgets_disk_free_bytes{mount=\"/media/a \\\"b\\\" \\\\c\"} 1\n"
+        );
+        let newline = gauge("i3widgets_x", &[("mount", "a\nb")], 1.0);
+        assert_eq!(
+            exposition(&[newline]),
+            "# TYPE i3widgets_x gauge\ni3widgets_x{mount=\"a\\nb\"} 1\n"
+        );
+        let escapes = json::Object::new()
+            .field("interface", "a\tb\nc\u{1}")
+            .field("up", true);
+        assert_eq!(
+            super::gauges("network", &escapes)[0].labels,
+            [("interface", "a\tb\nc\u{1}".to_string())]
+        );
+    }
+
+    #[test]
+    fn fields_go_out_in_base_units() {
+        // (widget, key, expected name, factor)
+        let cases = [
+            ("memory", "used_kib", "i3widgets_memory_used_bytes", 1024.0),
+            ("battery", "charge_pct", "i3widgets_battery_percent", 1.0),
+            (
+                "battery",
+                "health_pct",
+                "i3widgets_battery_health_percent",
+                1.0,
+            ),
+            (
+                "battery",
+                "energy_wh",
+                "i3widgets_battery_energy_joules",
+                3600.0,
+            ),
+            (
+                "battery",
+                "time_to_empty_secs",
+                "i3widgets_battery_time_to_empty_seconds",
+                1.0,
+            ),
+            (
+                "brightness",
+                "brightness_pct",
+                "i3widgets_brightness_percent",
+                1.0,
+            ),
+            (
+                "network",
+                "rx_bytes_per_sec",
+                "i3widgets_network_rx_bytes_per_second",
+                1.0,
+            ),
+            ("disk", "free_bytes", "i3widgets_disk_free_bytes", 1.0),
+            (
+                "memory",
+                "huge_pages_free",
+                "i3widgets_memory_huge_pages_free",
+                1.0,
+            ),
+        ];
+        for (widget, key, name, factor) in cases {
+            let object = json::Object::new().field(key, 2u64);
+            assert_eq!(
+                gauges(widget, &object),
+                [gauge(name, &[], 2.0 * factor)],
+                "{:?}",
+                (widget, key)
+            );
+        }
+    }
+
+    #[test]
+    fn one_type_line_per_name() {
+        let gauges = [
+            gauge("i3widgets_disk_free_bytes", &[("mount", "/")], 10.0),
+            gauge("i3widgets_disk_used_percent", &[("mount", "/")], 50.0),
+            gauge("i3widgets_disk_free_bytes", &[("mount", "/home")], 20.5),
+        ];
+        assert_eq!(
+            exposition(&gauges),
+            "# TYPE i3widgets_disk_free_bytes gauge\n\
+             i3widgets_disk_free_bytes{mount=\"/\"} 10\n\
+             i3widgets_disk_free_bytes{mount=\"/home\"} 20.5\n\
+             # TYPE i3widgets_disk_used_percent gauge\n\
+             i3widgets_disk_used_percent{mount=\"/\"} 50\n"
+        );
+        assert_eq!(exposition(&[]), "");
+    }
+
+    #[test]
+    fn records_the_latest_update_of_each_widget() {
+        record("cpu", &json::Object::new().field("usage_pct", 10u64));
+        record("memory", &json::Object::new().field("used_kib", 1u64));
+        record("cpu", &json::Object::new().field("usage_pct", 20u64));
+        let registry = REGISTRY.lock().unwrap();
+        assert_eq!(
+            *registry,
+            [
+                (
+                    "cpu".to_string(),
+                    vec![gauge("i3widgets_cpu_usage_percent", &[], 20.0)]
+                ),
+                (
+                    "memory".to_string(),
+                    vec![gauge("i3widgets_memory_used_bytes", &[], 1024.0)]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, state, ByteUnits, Span, Style, Widget, TABULAR_NUMS};
+
+/// Byte counters of an interface from `/sys/class/net/<interface>/statistics`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counters {
+    pub rx: u64,
+    pub tx: u64,
+}
+
+impl Counters {
+    pub fn read(interface: &str) -> Result<Self> {
+        let read = |name: &str| -> Result<u64> {
+            let path = format!("/sys/class/net/{}/statistics/{}", interface, name);
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path))?
+                .trim()
+                .parse()
+                .with_context(|| format!("parsing {}", path))
+        };
+        Ok(Self {
+            rx: read("rx_bytes")?,
+            tx: read("tx_bytes")?,
+        })
+    }
+
+    /// Bytes moved since `prev`. A counter that went backwards was reset, by a reboot or
+    /// the interface going away, so everything it shows now is new traffic
+    pub fn since(&self, prev: Counters) -> Counters {
+        let delta = |now: u64, prev: u64| now.checked_sub(prev).unwrap_or(now);
+        Counters {
+            rx: delta(self.rx, prev.rx),
+            tx: delta(self.tx, prev.tx),
+        }
+    }
+}
+
+/// Counters along with when they were read, as seconds since the epoch
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub at: f64,
+    pub counters: Counters,
+}
+
+impl Sample {
+    pub fn now(interface: &str) -> Result<Self> {
+        Ok(Self {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            counters: Counters::read(interface)?,
+        })
+    }
+
+    /// Bytes per second from `prev` to this sample, zero if there is no time between them
+    pub fn rate_since(&self, prev: &Sample) -> (f64, f64) {
+        let elapsed = self.at - prev.at;
+        if elapsed <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let delta = self.counters.since(prev.counters);
+        (delta.rx as f64 / elapsed, delta.tx as f64 / elapsed)
+    }
+}
+
+impl state::Stored for Sample {
+    fn parse(contents: &str) -> Option<Self> {
+        let mut fields = contents.split_whitespace();
+        Some(Self {
+            at: fields.next()?.parse().ok()?,
+            counters: Counters {
+                rx: fields.next()?.parse().ok()?,
+                tx: fields.next()?.parse().ok()?,
+            },
+        })
+    }
+
+    fn serialize(&self) -> String {
+        format!("{} {} {}\n", self.at, self.counters.rx, self.counters.tx)
+    }
+}
+
+/// Traffic since the last `--reset`, accumulated across counter resets
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Total {
+    pub transferred: Counters,
+    /// The counters at the previous update, what the next delta is taken against
+    pub last: Counters,
+}
+
+impl Total {
+    /// Start counting from `current`
+    pub fn reset(current: Counters) -> Self {
+        Self {
+            transferred: Counters::default(),
+            last: current,
+        }
+    }
+
+    /// Add the traffic between the previous update and `current`
+    pub fn advance(&mut self, current: Counters) {
+        let delta = current.since(self.last);
+        self.transferred.rx = self.transferred.rx.saturating_add(delta.rx);
+        self.transferred.tx = self.transferred.tx.saturating_add(delta.tx);
+        self.last = current;
+    }
+}
+
+impl state::Stored for Total {
+    fn parse(contents: &str) -> Option<Self> {
+        let fields = contents
+            .split_whitespace()
+            .map(|field| field.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        match fields[..] {
+            [rx, tx, last_rx, last_tx] => Some(Self {
+                transferred: Counters { rx, tx },
+                last: Counters {
+                    rx: last_rx,
+                    tx: last_tx,
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    fn serialize(&self) -> String {
+        format!(
+            "{} {} {} {}\n",
+            self.transferred.rx, self.transferred.tx, self.last.rx, self.last.tx
+        )
+    }
+}
+
+pub enum Traffic {
+    /// Bytes per second
+    Rate { rx: f64, tx: f64 },
+    /// Bytes since the reference point
+    Total(Counters),
+}
+
+pub struct NetworkWidget {
+    pub interface: String,
+    pub traffic: Traffic,
+    pub units: ByteUnits,
+}
+
+impl NetworkWidget {
+    /// The rx and tx amounts, formatted with the unit suffix after them
+    fn amounts(&self) -> [(f64, String); 2] {
+        let (rx, tx, per) = match self.traffic {
+            Traffic::Rate { rx, tx } => (rx as u64, tx as u64, "/s"),
+            Traffic::Total(total) => (total.rx, total.tx, ""),
+        };
+        [rx, tx].map(|bytes| {
+            let (value, suffix) = self.units.human(bytes);
+            (value, format!("{}{}", suffix, per))
+        })
+    }
+}
+
+impl Widget for NetworkWidget {
+    fn render(&self, style: &Style) -> String {
+        let [rx, tx] = self.amounts();
+        let part = |icon: &str, key: &str, (value, unit): (f64, String)| {
+            format!(
+                "{icon} {value}{unit}",
+                icon = style.icon("network", icon),
+                value = pango!(
+                    format!("{:.1}", value),
+                    color = style.color(&format!("network.{}", key), style.palette().primary),
+                    weight = "ultrabold",
+                    font_features = TABULAR_NUMS,
+                    font_size = "110%",
+                ),
+                unit = pango!(
+                    unit,
+                    color = style.color("network.unit", style.palette().secondary)
+                ),
+            )
+        };
+        format!("{} {}", part("rx", "rx", rx), part("tx", "tx", tx))
+    }
+
+    fn to_json(&self) -> json::Object {
+        let object = json::Object::new().field("interface", &self.interface);
+        match self.traffic {
+            Traffic::Rate { rx, tx } => object
+                .field("rx_bytes_per_sec", rx)
+                .field("tx_bytes_per_sec", tx),
+            Traffic::Total(total) => object
+                .field("rx_bytes", total.rx)
+                .field("tx_bytes", total.tx),
+        }
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        let [(rx, rx_unit), (tx, tx_unit)] = self.amounts();
+        Some(format!("{:.1}{} {:.1}{}", rx, rx_unit, tx, tx_unit))
+    }
+
+    fn is_empty(&self) -> bool {
+        match self.traffic {
+            Traffic::Rate { rx, tx } => rx < 1.0 && tx < 1.0,
+            Traffic::Total(total) => total.rx == 0 && total.tx == 0,
+        }
+    }
+}
+
+/// `--interface`, which names the state files too, so it can't be a path
+pub fn parse_interface(s: &str) -> Result<String> {
+    if s.is_empty() || s == "." || s == ".." || s.contains('/') {
+        return Err(anyhow!("not an interface name"));
+    }
+    Ok(s.to_string())
+}
+
+/// The first interface that is up, other than loopback
+pub fn default_interface() -> Result<String> {
+    let mut interfaces = std::fs::read_dir("/sys/class/net")
+        .context("reading /sys/class/net")?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name != "lo")
+        .collect::<Vec<_>>();
+    interfaces.sort();
+    interfaces
+        .into_iter()
+        .find(|name| {
+            std::fs::read_to_string(format!("/sys/class/net/{}/operstate", name))
+                .is_ok_and(|state| state.trim() == "up")
+        })
+        .ok_or_else(|| anyhow!("no network interface is up"))
+}
+
+/// Current rate, against `prev` if given and otherwise against the sample the previous run
+/// left in the state file
+pub fn rate(interface: &str, prev: Option<Sample>) -> Result<(Traffic, Sample)> {
+    let name = format!("network-{}.rate", interface);
+    let sample = Sample::now(interface)?;
+    let mut saved = None;
+    state::update(&name, |previous| {
+        saved = previous;
+        Ok(sample)
+    })?;
+    let (rx, tx) = prev
+        .or(saved)
+        .map(|prev| sample.rate_since(&prev))
+        .unwrap_or_default();
+    Ok((Traffic::Rate { rx, tx }, sample))
+}
+
+/// Add the traffic since the last update to `prev` if given, and otherwise to the total
+/// in the state file, which starts fresh on `reset` or when there is none yet
+pub fn total(interface: &str, reset: bool, prev: Option<Total>) -> Result<Total> {
+    let name = format!("network-{}.total", interface);
+    let current = Counters::read(interface)?;
+    state::update(&name, |saved| {
+        let base = match (prev, reset) {
+            (Some(prev), _) => Some(prev),
+            (None, true) => None,
+            (None, false) => saved,
+        };
+        Ok(match base {
+            Some(mut total) => {
+                total.advance(current);
+                total
+            }
+            None => Total::reset(current),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use state::Stored;
+
+    use super::*;
+
+    #[test]
+    fn counts_the_bytes_since_the_previous_reading() {
+        let prev = Counters { rx: 1000, tx: 500 };
+        let now = Counters { rx: 4000, tx: 700 };
+        assert_eq!(now.since(prev), Counters { rx: 3000, tx: 200 });
+        assert_eq!(now.since(now), Counters::default());
+    }
+
+    #[test]
+    fn a_counter_that_went_backwards_starts_over() {
+        // rx wrapped or the machine rebooted, tx kept counting
+        let prev = Counters { rx: 9000, tx: 500 };
+        let now = Counters { rx: 300, tx: 800 };
+        assert_eq!(now.since(prev), Counters { rx: 300, tx: 300 });
+
+        let mut total = Total::reset(prev);
+        total.advance(Counters { rx: 9500, tx: 600 });
+        total.advance(now);
+        assert_eq!(total.transferred, Counters { rx: 800, tx: 300 });
+        assert_eq!(total.last, now);
+    }
+
+    #[test]
+    fn the_total_saturates() {
+        let mut total = Total {
+            transferred: Counters {
+                rx: u64::MAX - 10,
+                tx: 0,
+            },
+            last: Counters::default(),
+        };
+        total.advance(Counters { rx: 100, tx: 100 });
+        assert_eq!(
+            total.transferred,
+            Counters {
+                rx: u64::MAX,
+                tx: 100
+            }
+        );
+    }
+
+    #[test]
+    fn a_reset_counts_from_the_current_counters() {
+        let current = Counters { rx: 5000, tx: 6000 };
+        let mut total = Total::reset(current);
+        assert_eq!(total.transferred, Counters::default());
+        total.advance(Counters { rx: 5100, tx: 6000 });
+        assert_eq!(total.transferred, Counters { rx: 100, tx: 0 });
+    }
+
+    #[test]
+    fn state_files_round_trip() {
+        let total = Total {
+            transferred: Counters { rx: 1, tx: 2 },
+            last: Counters { rx: 3, tx: 4 },
+        };
+        let parsed = Total::parse(&total.serialize()).unwrap();
+        assert_eq!(
+            (parsed.transferred, parsed.last),
+            (total.transferred, total.last)
+        );
+        for corrupt in ["", "1 2 3", "1 2 3 4 5", "1 2 x 4", "-1 2 3 4"] {
+            assert!(Total::parse(corrupt).is_none(), "{:?}", corrupt);
+        }
+
+        let sample = Sample {
+            at: 1700000000.25,
+            counters: Counters { rx: 10, tx: 20 },
+        };
+        let parsed = Sample::parse(&sample.serialize()).unwrap();
+        assert_eq!((parsed.at, parsed.counters), (sample.at, sample.counters));
+        assert!(Sample::parse("1700000000.25 10").is_none());
+    }
+
+    #[test]
+    fn rates_need_time_in_between() {
+        let prev = Sample {
+            at: 100.0,
+            counters: Counters { rx: 0, tx: 0 },
+        };
+        let now = Sample {
+            at: 102.0,
+            counters: Counters { rx: 2048, tx: 100 },
+        };
+        assert_eq!(now.rate_since(&prev), (1024.0, 50.0));
+        assert_eq!(prev.rate_since(&now), (0.0, 0.0));
+        assert_eq!(now.rate_since(&now), (0.0, 0.0));
+    }
+
+    #[test]
+    fn interface_names_are_not_paths() {
+        for valid in ["eth0", "wlp3s0", "wg-home", "br.100"] {
+            assert_eq!(parse_interface(valid).unwrap(), valid);
+        }
+        for invalid in ["", ".", "..", "../../etc", "eth0/", "a/b"] {
+            assert!(parse_interface(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
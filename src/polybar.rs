@@ -0,0 +1,159 @@
+use std::fmt::Display;
+
+/// A run of text with the colors it ends up with in the markup
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PolybarSpan {
+    pub color: Option<String>,
+    pub background: Option<String>,
+    /// Set by a bold or heavier weight, which only the ANSI output can show
+    pub bold: bool,
+    pub text: String,
+}
+
+impl PolybarSpan {
+    /// Append the span, keeping text and tags from running into each other
+    pub fn write_to(&self, out: &mut String) {
+        if let Some(color) = &self.color {
+            push_tag(out, &format!("%{{F{}}}", color));
+        }
+        if let Some(background) = &self.background {
+            push_tag(out, &format!("%{{B{}}}", background));
+        }
+        // a literal `%{` would start a tag
+        out.push_str(&self.text.replace("%{", "%%{"));
+        if self.background.is_some() {
+            push_tag(out, "%{B-}");
+        }
+        if self.color.is_some() {
+            push_tag(out, "%{F-}");
+        }
+    }
+}
+
+impl Display for PolybarSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write_to(&mut out);
+        f.write_str(&out)
+    }
+}
+
+/// Text like `50%` right before a tag would turn it into the `%%{` escape, so a zero width
+/// space goes between them
+fn push_tag(out: &mut String, tag: &str) {
+    if out.ends_with('%') {
+        out.push('\u{200b}');
+    }
+    out.push_str(tag);
+}
+
+/// The value of `name="..."` in the attributes of a span tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&apos;", "'")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// Split markup made of nested `<span>`s into runs of text with the colors and boldness in
+/// effect for them. The other attributes have no polybar equivalent and are dropped.
+pub fn spans(markup: &str) -> Vec<PolybarSpan> {
+    let mut spans = Vec::new();
+    // the colors and boldness of the open spans, innermost last
+    let mut stack: Vec<(Option<String>, Option<String>, bool)> = Vec::new();
+    let mut rest = markup;
+    while !rest.is_empty() {
+        let (text, tag) = match rest.find('<') {
+            Some(start) => {
+                let end = rest[start..]
+                    .find('>')
+                    .map_or(rest.len(), |end| start + end + 1);
+                (&rest[..start], Some(&rest[start..end]))
+            }
+            None => (rest, None),
+        };
+        if !text.is_empty() {
+            let (color, background, bold) = stack.last().cloned().unwrap_or_default();
+            spans.push(PolybarSpan {
+                color,
+                background,
+                bold,
+                text: unescape(text),
+            });
+        }
+        match tag {
+            Some("</span>") => {
+                stack.pop();
+            }
+            Some(tag) => {
+                let (color, background, bold) = stack.last().cloned().unwrap_or_default();
+                stack.push((
+                    attribute(tag, "color").map(str::to_string).or(color),
+                    attribute(tag, "background")
+                        .map(str::to_string)
+                        .or(background),
+                    match attribute(tag, "weight") {
+                        Some(weight) => matches!(weight, "bold" | "ultrabold" | "heavy"),
+                        None => bold,
+                    },
+                ));
+            }
+            None => {}
+        }
+        rest = &rest[text.len() + tag.map_or(0, str::len)..];
+    }
+    spans
+}
+
+/// The markup with its colors as polybar tags
+pub fn from_pango(markup: &str) -> String {
+    let mut out = String::new();
+    for span in spans(markup) {
+        span.write_to(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_percent_signs_from_escaping_tags() {
+        // (markup, polybar)
+        let cases = [
+            ("plain", "plain"),
+            ("<span color=\"#c0caf5\">74</span>%", "%{F#c0caf5}74%{F-}%"),
+            // a trailing % would make the closing tag `%%{F-}`, the escape for a literal `%{F-}`
+            (
+                "<span color=\"#c0caf5\">74%</span>",
+                "%{F#c0caf5}74%\u{200b}%{F-}",
+            ),
+            (
+                "50%<span color=\"#f7768e\">hot</span>",
+                "50%\u{200b}%{F#f7768e}hot%{F-}",
+            ),
+            (
+                "<span color=\"#c0caf5\" background=\"#1a1b26\">9%</span>",
+                "%{F#c0caf5}%{B#1a1b26}9%\u{200b}%{B-}%{F-}",
+            ),
+            // a literal `%{` in the text isn't a tag
+            ("a %{F#fff} b", "a %%{F#fff} b"),
+            (
+                "<span color=\"#c0caf5\">%{B-}</span>",
+                "%{F#c0caf5}%%{B-}%{F-}",
+            ),
+            ("&lt;%{&gt;", "<%%{>"),
+        ];
+        for (markup, polybar) in cases {
+            assert_eq!(from_pango(markup), polybar, "{:?}", markup);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::Read,
+    os::unix::process::CommandExt,
+    process::{Command, Output, Stdio},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returned when a command didn't exit within its timeout and had to be killed
+#[derive(Debug)]
+pub struct Timeout {
+    pub program: String,
+    pub timeout: Duration,
+}
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` timed out after {:.1}s",
+            self.program,
+            self.timeout.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Returned when a command exited with a failure status, whatever it printed to stdout
+#[derive(Debug)]
+pub struct Failed {
+    pub program: String,
+    /// `None` when it was killed by a signal
+    pub code: Option<i32>,
+    /// The first line of its stderr
+    pub stderr: String,
+}
+
+impl Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "`{}` exited with {}", self.program, code)?,
+            None => write!(f, "`{}` was killed by a signal", self.program)?,
+        }
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Returned when a program can't be run because it isn't installed, or isn't where
+/// `--tool-path` says
+#[derive(Debug)]
+pub struct ToolMissing {
+    pub name: String,
+}
+
+impl Display for ToolMissing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is not installed", self.name)
+    }
+}
+
+impl std::error::Error for ToolMissing {}
+
+/// Whether `err` came from a program that isn't installed
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.is::<ToolMissing>()
+}
+
+/// The external tools the widgets use, with the arguments that make them print their version
+/// and the widgets that need them, for `doctor`
+pub const KNOWN_TOOLS: &[(&str, &[&str], &str)] = &[
+    ("upower", &["--version"], "battery"),
+    ("powerprofilesctl", &["version"], "battery --show-profile"),
+    ("free", &["--version"], "memory"),
+    ("df", &["--version"], "disk"),
+    ("pactl", &["--version"], "sink-volume, app-volume"),
+    ("brightnessctl", &["--version"], "brightness"),
+    ("virsh", &["--version"], "virsh-active"),
+    ("nvidia-smi", &["--version"], "gpu-fan"),
+    ("aplay", &["--version"], "sound-cards"),
+    ("loginctl", &["--version"], "sessions"),
+    ("wg", &["--version"], "wireguard"),
+    ("notify-send", &["--version"], "--show-detail"),
+];
+
+/// The first line `program` prints for `version_args`, `Ok(None)` if it printed nothing
+pub fn version(program: &str, version_args: &[&str], timeout: Duration) -> Result<Option<String>> {
+    let output = run_command(program, version_args, timeout)?;
+    let text = if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    Ok(String::from_utf8_lossy(&text)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string))
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// Where to find the external tools, for ones installed outside `PATH` or run through a
+/// wrapper
+#[derive(Debug, Clone, Default)]
+pub struct Tools {
+    paths: HashMap<String, String>,
+}
+
+impl Tools {
+    /// Later entries win, so the command line can go after the config
+    pub fn new(paths: &[(String, String)]) -> Self {
+        Self {
+            paths: paths.iter().cloned().collect(),
+        }
+    }
+
+    /// What to run for `program`, the program itself unless overridden
+    pub fn resolve<'a>(&'a self, program: &'a str) -> &'a str {
+        self.paths.get(program).map_or(program, String::as_str)
+    }
+}
+
+/// Where the fetchers get the output of external tools from, so the parsers behind them can
+/// be fed canned output
+pub trait CommandSource {
+    /// The stdout of `program` run with `args`
+    fn output(&self, program: &str, args: &[&str]) -> Result<String>;
+
+    /// Like [`CommandSource::output`] with `timeout` instead of `--cmd-timeout`, for tools
+    /// that are known to hang. Canned output has nothing to time out, so it ignores it
+    fn output_within(&self, program: &str, args: &[&str], _timeout: Duration) -> Result<String> {
+        self.output(program, args)
+    }
+}
+
+/// Runs the actual tools through [`run_command`]
+#[derive(Clone)]
+pub struct System {
+    pub timeout: Duration,
+    pub tools: Tools,
+}
+
+impl CommandSource for System {
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.output_within(program, args, self.timeout)
+    }
+
+    fn output_within(&self, program: &str, args: &[&str], timeout: Duration) -> Result<String> {
+        let start = Instant::now();
+        let result = run(program, self.tools.resolve(program), args, timeout)?;
+        log!(
+            Debug,
+            "process",
+            "`{} {}` took {:.1?}",
+            program,
+            args.join(" "),
+            start.elapsed()
+        );
+        if !result.status.success() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            return Err(Failed {
+                program: program.to_string(),
+                code: result.status.code(),
+                stderr: stderr.lines().next().unwrap_or_default().trim().to_string(),
+            }
+            .into());
+        }
+        let output = String::from_utf8(result.stdout)
+            .with_context(|| format!("converting `{}` output to utf-8", program))?;
+        log!(
+            Trace,
+            "process",
+            "`{}` printed:\n{}",
+            program,
+            output.trim_end()
+        );
+        Ok(output)
+    }
+}
+
+/// Why the tool `name` couldn't be started from `path`, a [`ToolMissing`] if it isn't there
+pub fn spawn_error(name: &str, path: &str, err: std::io::Error) -> anyhow::Error {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => anyhow::Error::new(ToolMissing {
+            name: path.to_string(),
+        }),
+        _ => anyhow::Error::new(err).context(format!("running `{}`", name)),
+    }
+}
+
+/// Run `program` to completion, killing it and returning a [`Timeout`] error if it takes
+/// longer than `timeout`, so one wedged tool can't stall the whole bar
+pub fn run_command(program: &str, args: &[&str], timeout: Duration) -> Result<Output> {
+    run(program, program, args, timeout)
+}
+
+/// [`run_command`] for the tool `name`, run from `path`. Errors name the tool, except a
+/// missing one, where the path is what's wrong
+fn run(name: &str, path: &str, args: &[&str], timeout: Duration) -> Result<Output> {
+    // in a group of its own, so a timeout kills whatever it started too
+    let mut child = Command::new(path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|err| spawn_error(name, path, err))?;
+    // read concurrently so a chatty child can't block on a full pipe
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+    let join = |pipe: Option<JoinHandle<Vec<u8>>>| {
+        pipe.and_then(|pipe| pipe.join().ok()).unwrap_or_default()
+    };
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // grandchildren holding the pipes would keep the drain threads waiting
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+            let _ = child.wait();
+            join(stdout);
+            join(stderr);
+            return Err(Timeout {
+                program: name.to_string(),
+                timeout,
+            }
+            .into());
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout),
+        stderr: join(stderr),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kills_a_tool_that_hangs() {
+        let pid_file = std::env::temp_dir().join(format!("i3widgets-hang-{}", std::process::id()));
+        let script = format!("echo $$ > {}; exec sleep 30", pid_file.display());
+        let start = Instant::now();
+        let err = run_command("sh", &["-c", &script], Duration::from_millis(200)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let timeout = err.downcast_ref::<Timeout>().unwrap();
+        assert_eq!(timeout.program, "sh");
+        assert_eq!(err.to_string(), "`sh` timed out after 0.2s");
+        // killed and reaped, so the pid is gone rather than a zombie
+        let pid = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let alive = unsafe { libc::kill(pid, 0) } == 0;
+        assert!(!alive, "pid {} is still around", pid);
+        let _ = std::fs::remove_file(pid_file);
+    }
+
+    #[test]
+    fn times_out_a_fake_tool_from_tool_paths() {
+        // `virsh list` becomes `sleep 30`, like libvirtd being wedged
+        let system = System {
+            timeout: Duration::from_secs(30),
+            tools: Tools::new(&[("virsh".to_string(), "sleep".to_string())]),
+        };
+        let start = Instant::now();
+        let err = system
+            .output_within("virsh", &["30"], Duration::from_millis(100))
+            .unwrap_err();
+        assert!(err.is::<Timeout>());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // the tool, not the path it was run from
+        assert_eq!(err.to_string(), "`virsh` timed out after 0.1s");
+    }
+
+    #[test]
+    fn kills_what_a_hanging_tool_started() {
+        let pid_file =
+            std::env::temp_dir().join(format!("i3widgets-grandchild-{}", std::process::id()));
+        // the background sleep holds the pipes, and would outlive a kill of sh alone
+        let script = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+        let start = Instant::now();
+        let err = run_command("sh", &["-c", &script], Duration::from_millis(200)).unwrap_err();
+        assert!(err.is::<Timeout>());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // gone, or a zombie for init to reap, once the kill has gone through
+        let dead = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                .map(|stat| stat.rsplit(')').next().unwrap().trim().starts_with('Z'))
+                .unwrap_or(true)
+        };
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !dead() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dead(), "pid {} is still running", pid.trim());
+        let _ = std::fs::remove_file(pid_file);
+    }
+
+    #[test]
+    fn a_tool_that_fails_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("i3widgets-failing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wg = dir.join("wg");
+        std::fs::write(
+            &wg,
+            "#!/bin/sh\necho 'Unable to access interface: Operation not permitted' >&2\nexit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&wg, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let system = System {
+            timeout: DEFAULT_TIMEOUT,
+            tools: Tools::new(&[("wg".to_string(), wg.display().to_string())]),
+        };
+        let err = system.output("wg", &["show", "wg0", "peers"]).unwrap_err();
+        let failed = err.downcast_ref::<Failed>().unwrap();
+        assert_eq!(failed.code, Some(1));
+        assert_eq!(
+            err.to_string(),
+            "`wg` exited with 1: Unable to access interface: Operation not permitted"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn collects_the_output_of_a_tool_that_exits() {
+        let script = "head -c 1000000 /dev/zero; echo oops >&2; exit 3";
+        let output = run_command("sh", &["-c", script], DEFAULT_TIMEOUT).unwrap();
+        // more than a pipe buffer, which would block the child if nothing read it
+        assert_eq!(output.stdout.len(), 1_000_000);
+        assert_eq!(output.stderr, b"oops\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn a_missing_tool_is_not_a_timeout() {
+        let err = run_command("/nonexistent/pactl", &[], DEFAULT_TIMEOUT).unwrap_err();
+        assert!(is_not_found(&err));
+        assert!(!err.is::<Timeout>());
+    }
+}
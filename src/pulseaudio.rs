@@ -0,0 +1,627 @@
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    thread::JoinHandle,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    json,
+    process::{self, CommandSource, System},
+    state, Span, Style, Widget, TABULAR_NUMS,
+};
+
+/// The state file with the last level the sink had unmuted
+const LEVEL: &str = "sink-volume.level";
+
+pub struct Volume {
+    left: u64,
+    right: u64,
+    mute: bool,
+    /// The default sink, only looked up for the detail
+    pub sink: Option<Sink>,
+    /// The level from before muting, shown while muted so unmuting doesn't jump
+    pub remembered: Option<u64>,
+}
+
+/// A sink from `pactl list sinks`
+#[derive(Debug, Default, PartialEq)]
+pub struct Sink {
+    pub name: String,
+    /// e.g. `Built-in Audio Analog Stereo`
+    pub description: String,
+    /// Names and descriptions, e.g. `analog-output-speaker` and `Speakers`
+    pub ports: Vec<(String, String)>,
+    /// Name of the port in use
+    pub active_port: Option<String>,
+}
+
+/// The last unmuted level in percent, as stored in [`LEVEL`]
+struct Level(u64);
+
+impl state::Stored for Level {
+    fn parse(contents: &str) -> Option<Self> {
+        contents.trim().parse().ok().map(Self)
+    }
+
+    fn serialize(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// The level to show for a reading of `pct` and the level to remember from now on. An
+/// unmuted, audible reading is remembered, while muted the remembered level is shown since
+/// some mixers drop the volume to 0 on mute and that is what unmuting restores
+pub fn restore(mute: bool, pct: u64, remembered: Option<u64>) -> (u64, Option<u64>) {
+    match (mute, remembered) {
+        (false, _) if pct > 0 => (pct, Some(pct)),
+        (false, remembered) => (pct, remembered),
+        (true, Some(level)) => (level, Some(level)),
+        (true, None) => (pct, (pct > 0).then_some(pct)),
+    }
+}
+
+impl Volume {
+    pub fn left_pct(&self) -> u64 {
+        self.left * 100 / 65530 // not std::u16::MAX for some reason
+    }
+
+    pub fn right_pct(&self) -> u64 {
+        self.right * 100 / 65530 // not std::u16::MAX for some reason
+    }
+
+    fn icon_name(value: u64, mute: bool) -> &'static str {
+        if mute {
+            return "volume_muted";
+        }
+        match value {
+            0 => "volume_muted",
+            1..=33 => "volume_low",
+            34..=66 => "volume_medium",
+            _ => "volume_high",
+        }
+    }
+
+    /// The left level, or the remembered one while muted
+    pub fn shown_pct(&self) -> u64 {
+        restore(self.mute, self.left_pct(), self.remembered).0
+    }
+
+    /// Keep track of the last unmuted level in `recent`, filled from the state file when
+    /// empty so single-shot runs remember it too, and written back when it changes
+    pub fn remember(&mut self, recent: &mut Option<u64>) {
+        if recent.is_none() {
+            match state::load::<Level>(LEVEL) {
+                Ok(level) => *recent = level.map(|level| level.0),
+                Err(err) => log!(Warn, "sink-volume", "reading the level: {:#}", err),
+            }
+        }
+        let (_, level) = restore(self.mute, self.left_pct(), *recent);
+        if level != *recent {
+            if let Some(level) = level {
+                if let Err(err) = state::store(LEVEL, &Level(level)) {
+                    log!(Warn, "sink-volume", "remembering the level: {:#}", err);
+                }
+            }
+        }
+        *recent = level;
+        self.remembered = level;
+    }
+
+    /// Name of the icon in [`Icons`](crate::Icons)
+    pub fn left_icon_name(&self) -> &'static str {
+        Self::icon_name(self.left_pct(), self.mute)
+    }
+
+    /// Name of the icon in [`Icons`](crate::Icons)
+    pub fn right_icon_name(&self) -> &'static str {
+        Self::icon_name(self.right_pct(), self.mute)
+    }
+}
+
+impl Widget for Volume {
+    fn render(&self, style: &Style) -> String {
+        format!(
+            "{icon} {left}{pct}",
+            icon = style.icon("volume", self.left_icon_name()),
+            left = pango!(
+                self.shown_pct(),
+                // dim while muted, it's the level unmuting goes back to
+                color = if self.mute {
+                    style.color("volume.pct", style.palette().secondary)
+                } else {
+                    style.color("volume.pct", style.palette().primary)
+                },
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            pct = style.unit("volume", "%"),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("left_pct", self.left_pct())
+            .field("right_pct", self.right_pct())
+            .field("muted", self.mute)
+            .optional("remembered_pct", self.remembered)
+    }
+
+    fn detail(&self) -> Option<String> {
+        let mut lines = Vec::new();
+        if let Some(sink) = &self.sink {
+            lines.push(match sink.description.as_str() {
+                "" => format!("Sink: {}", sink.name),
+                description => format!("Sink: {} ({})", description, sink.name),
+            });
+            let ports = sink
+                .ports
+                .iter()
+                .map(|(name, description)| {
+                    if sink.active_port.as_ref() == Some(name) {
+                        format!("{} (active)", description)
+                    } else {
+                        description.clone()
+                    }
+                })
+                .collect::<Vec<_>>();
+            if !ports.is_empty() {
+                lines.push(format!("Ports: {}", ports.join(", ")));
+            }
+        }
+        lines.push(format!("Left: {}%", self.left_pct()));
+        lines.push(format!("Right: {}%", self.right_pct()));
+        lines.push(format!("Muted: {}", if self.mute { "yes" } else { "no" }));
+        Some(lines.join("\n"))
+    }
+
+    /// Just the icon, or the level when `--no-icons` would leave nothing
+    fn short(&self, style: &Style) -> Option<String> {
+        if style.no_icons {
+            return Some(format!("{}{}", self.shown_pct(), style.unit("volume", "%")));
+        }
+        Some(style.icon("volume", self.left_icon_name()).to_string())
+    }
+
+    fn icon(&self) -> Option<(&'static str, &'static str)> {
+        Some(("volume", self.left_icon_name()))
+    }
+}
+
+/// `pct` clamped to `max` in the form `pactl set-sink-volume` takes, e.g. `50%`
+pub fn volume_arg(pct: u32, max: u32) -> String {
+    format!("{}%", pct.min(max))
+}
+
+/// Set the default sink to `pct`, at most `max`
+pub fn set_volume(source: &dyn CommandSource, pct: u32, max: u32) -> Result<()> {
+    source.output(
+        "pactl",
+        &["set-sink-volume", "@DEFAULT_SINK@", &volume_arg(pct, max)],
+    )?;
+    Ok(())
+}
+
+pub fn volume(source: &dyn CommandSource) -> Result<Volume> {
+    parse(
+        &source.output("pactl", &["get-sink-volume", "@DEFAULT_SINK@"])?,
+        &source.output("pactl", &["get-sink-mute", "@DEFAULT_SINK@"])?,
+    )
+}
+
+/// The default sink with its ports. `pactl list sinks` doesn't say which sink is the default,
+/// so this takes a `pactl get-default-sink` as well
+pub fn default_sink(source: &dyn CommandSource) -> Result<Sink> {
+    let name = source.output("pactl", &["get-default-sink"])?;
+    let name = name.trim();
+    parse_sinks(&source.output("pactl", &["list", "sinks"])?)
+        .into_iter()
+        .find(|sink| sink.name == name)
+        .ok_or_else(|| anyhow!("the default sink `{}` isn't in `pactl list sinks`", name))
+}
+
+/// Parse the sinks out of `pactl list sinks`, where each sink's properties are indented by a
+/// tab and its ports by two
+pub fn parse_sinks(output: &str) -> Vec<Sink> {
+    let mut sinks = Vec::<Sink>::new();
+    let mut in_ports = false;
+    for line in output.lines() {
+        if line.starts_with("Sink #") {
+            sinks.push(Sink::default());
+            continue;
+        }
+        let Some(sink) = sinks.last_mut() else {
+            continue;
+        };
+        let depth = line.len() - line.trim_start_matches('\t').len();
+        let line = line.trim();
+        if depth == 1 {
+            in_ports = line == "Ports:";
+            match line.split_once(": ") {
+                Some(("Name", name)) => sink.name = name.to_string(),
+                Some(("Description", description)) => sink.description = description.to_string(),
+                Some(("Active Port", port)) => sink.active_port = Some(port.to_string()),
+                _ => {}
+            }
+        } else if depth == 2 && in_ports {
+            // analog-output-speaker: Speakers (type: Speaker, priority: 10000, available)
+            if let Some((name, rest)) = line.split_once(": ") {
+                let description = rest.split(" (").next().unwrap_or(rest);
+                sink.ports.push((name.to_string(), description.to_string()));
+            }
+        }
+    }
+    sinks
+}
+
+/// Whether a line of `pactl subscribe` may mean the default sink's volume changed, e.g.
+/// `Event 'change' on sink #0`, or a server event for a default sink switch
+pub fn is_sink_event(line: &str) -> bool {
+    line.contains(" sink ") || line.contains(" server")
+}
+
+/// Call `cb` with the new volume each time `pactl subscribe` reports a sink change, on a
+/// background thread that runs until `pactl` exits. Starting `pactl` happens before
+/// returning, so a missing `pactl` is a [`process::ToolMissing`] here.
+///
+/// `system` runs `pactl`, both the subscription and the volume reads after each event, so
+/// they honour `--tool-path` and `--cmd-timeout` like every other fetch.
+pub fn watch(system: System, cb: impl Fn(Volume) + Send + 'static) -> Result<JoinHandle<()>> {
+    let program = system.tools.resolve("pactl").to_string();
+    let mut child = Command::new(&program)
+        .arg("subscribe")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| process::spawn_error("pactl", &program, err))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("`pactl subscribe` has no stdout"))?;
+    Ok(std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if is_sink_event(&line) {
+                if let Ok(volume) = volume(&system) {
+                    cb(volume);
+                }
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }))
+}
+
+/// Parse the outputs of `pactl get-sink-volume` and `pactl get-sink-mute`
+pub fn parse(volume: &str, mute: &str) -> Result<Volume> {
+    let line = volume
+        .lines()
+        .next()
+        .ok_or_else(|| anyhow!("`pactl` output is invalid"))?;
+    let mut fields = line.split_whitespace();
+    // Volume: front-left: 65530 / 100% / -0.00 dB,   front-right: 65530 / 100% / -0.00 dB
+    let left = fields
+        .nth(2)
+        .ok_or_else(|| anyhow!("`pactl` output is invalid"))?
+        .parse::<u64>()?;
+    let right = fields
+        .nth(6)
+        .ok_or_else(|| anyhow!("`pactl` output is invalid"))?
+        .parse::<u64>()?;
+    let line = mute
+        .lines()
+        .next()
+        .ok_or_else(|| anyhow!("`pactl` output is invalid"))?;
+    let mute = line.contains("yes");
+    Ok(Volume {
+        left,
+        right,
+        mute,
+        sink: None,
+        remembered: None,
+    })
+}
+
+/// A playback stream from `pactl list sink-inputs`
+pub struct SinkInput {
+    /// `application.name`, falling back to the binary name
+    pub app: String,
+    /// Volume of the first channel in percent
+    pub pct: u64,
+    pub mute: bool,
+}
+
+pub struct AppVolumes {
+    pub inputs: Vec<SinkInput>,
+}
+
+impl Widget for AppVolumes {
+    fn render(&self, style: &Style) -> String {
+        let icon = style.icon("app_volume", "volume_high");
+        if self.inputs.is_empty() {
+            return format!(
+                "{icon} {}",
+                pango!(
+                    "none",
+                    color = style.color("app_volume.app", style.palette().secondary)
+                )
+            );
+        }
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let volume = if input.mute {
+                    style.icon("app_volume", "volume_muted").to_string()
+                } else {
+                    pango!(
+                        input.pct,
+                        color = style.color("app_volume.pct", style.palette().primary),
+                        weight = "ultrabold",
+                        font_features = TABULAR_NUMS,
+                    )
+                };
+                format!(
+                    "{}{volume}",
+                    pango!(
+                        format!("{}:", input.app),
+                        color = style.color("app_volume.app", style.palette().secondary)
+                    )
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("{icon} {}", inputs.join(" "))
+    }
+
+    fn to_json(&self) -> json::Object {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| {
+                json::Object::new()
+                    .field("app", &input.app)
+                    .field("pct", input.pct)
+                    .field("muted", input.mute)
+            })
+            .collect::<Vec<_>>();
+        json::Object::new().field("inputs", inputs)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.inputs.len().to_string())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+pub fn app_volumes(source: &dyn CommandSource) -> Result<AppVolumes> {
+    let inputs = parse_sink_inputs(&source.output("pactl", &["list", "sink-inputs"])?)?;
+    Ok(AppVolumes { inputs })
+}
+
+/// Parse the output of `pactl list sink-inputs`, one block per stream:
+///
+/// ```text
+/// Sink Input #42
+///     Mute: no
+///     Volume: front-left: 65536 / 100% / 0.00 dB,   front-right: 65536 / 100% / 0.00 dB
+///     Properties:
+///         application.name = "Firefox"
+/// ```
+pub fn parse_sink_inputs(output: &str) -> Result<Vec<SinkInput>> {
+    let mut inputs = Vec::new();
+    for block in output.split("Sink Input #").skip(1) {
+        let mut name = None;
+        let mut binary = None;
+        let mut pct = None;
+        let mut mute = false;
+        for line in block.lines().map(str::trim) {
+            if let Some(value) = line.strip_prefix("Mute:") {
+                mute = value.trim() == "yes";
+            } else if let Some(value) = line.strip_prefix("Volume:") {
+                pct = value
+                    .split_whitespace()
+                    .find_map(|field| field.strip_suffix('%')?.parse::<u64>().ok());
+            } else if let Some((key, value)) = line.split_once(" = ") {
+                let value = value.trim_matches('"').to_string();
+                match key {
+                    "application.name" => name = Some(value),
+                    "application.process.binary" => binary = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        let index = block.lines().next().unwrap_or_default().trim();
+        let pct = pct.ok_or_else(|| anyhow!("sink input #{index} has no volume"))?;
+        let app = name.or(binary).unwrap_or_else(|| format!("#{index}"));
+        inputs.push(SinkInput { app, pct, mute });
+    }
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watching_without_pactl_is_a_missing_tool() {
+        let system = System {
+            timeout: process::DEFAULT_TIMEOUT,
+            tools: process::Tools::new(&[("pactl".to_string(), "/nonexistent/pactl".to_string())]),
+        };
+        let err = watch(system, |_| {}).unwrap_err();
+        assert!(process::is_not_found(&err), "{:#}", err);
+        assert_eq!(err.to_string(), "`/nonexistent/pactl` is not installed");
+    }
+
+    #[test]
+    fn parses_pactl_volume_and_mute() {
+        let volume = include_str!("../tests/fixtures/pactl-get-sink-volume.txt");
+        // (mute fixture, muted)
+        let cases = [
+            (
+                include_str!("../tests/fixtures/pactl-get-sink-mute-no.txt"),
+                false,
+            ),
+            (
+                include_str!("../tests/fixtures/pactl-get-sink-mute-yes.txt"),
+                true,
+            ),
+        ];
+        for (mute, muted) in cases {
+            let parsed = parse(volume, mute).unwrap();
+            assert_eq!((parsed.left_pct(), parsed.right_pct()), (70, 60));
+            assert_eq!(parsed.mute, muted);
+        }
+    }
+
+    #[test]
+    fn clamps_the_volume_to_set() {
+        // (pct, max, argument)
+        let cases = [
+            (50, 100, "50%"),
+            (100, 100, "100%"),
+            (150, 100, "100%"),
+            (130, 150, "130%"),
+            (0, 100, "0%"),
+        ];
+        for (pct, max, arg) in cases {
+            assert_eq!(volume_arg(pct, max), arg, "{:?}", (pct, max));
+        }
+    }
+
+    #[test]
+    fn shows_the_remembered_level_while_muted() {
+        // (mute, pct, remembered) -> (shown, remembered from now on)
+        let cases = [
+            ((false, 70, None), (70, Some(70))),
+            ((false, 40, Some(70)), (40, Some(40))),
+            // silent but unmuted is not a level to restore
+            ((false, 0, Some(70)), (0, Some(70))),
+            ((true, 0, Some(70)), (70, Some(70))),
+            ((true, 30, Some(70)), (70, Some(70))),
+            ((true, 30, None), (30, Some(30))),
+            ((true, 0, None), (0, None)),
+        ];
+        for ((mute, pct, remembered), expected) in cases {
+            assert_eq!(
+                restore(mute, pct, remembered),
+                expected,
+                "{:?}",
+                (mute, pct, remembered)
+            );
+        }
+    }
+
+    #[test]
+    fn remembers_the_level_across_runs() {
+        let _env = crate::snapshots::test_env();
+        let volume = include_str!("../tests/fixtures/pactl-get-sink-volume.txt");
+        let unmuted = include_str!("../tests/fixtures/pactl-get-sink-mute-no.txt");
+        // the sink-volume snapshot stores the same 70%, so running alongside it is fine
+        let mut recent = None;
+        let mut before = parse(volume, unmuted).unwrap();
+        before.remember(&mut recent);
+        assert_eq!((recent, before.remembered), (Some(70), Some(70)));
+
+        // a mixer that drops to 0 on mute, read by the next single-shot run
+        let mut muted = Volume {
+            left: 0,
+            right: 0,
+            mute: true,
+            sink: None,
+            remembered: None,
+        };
+        let mut recent = None;
+        muted.remember(&mut recent);
+        assert_eq!(recent, Some(70));
+        assert_eq!(muted.shown_pct(), 70);
+        assert_eq!(muted.left_icon_name(), "volume_muted");
+    }
+
+    #[test]
+    fn parses_pactl_list_sinks() {
+        let sinks = parse_sinks(include_str!("../tests/fixtures/pactl-list-sinks.txt"));
+        assert_eq!(sinks.len(), 2);
+        assert_eq!(sinks[0].ports.len(), 1);
+        assert_eq!(
+            sinks[1],
+            Sink {
+                name: "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+                description: "Built-in Audio Analog Stereo".to_string(),
+                ports: vec![
+                    ("analog-output-speaker".to_string(), "Speakers".to_string()),
+                    (
+                        "analog-output-headphones".to_string(),
+                        "Headphones".to_string()
+                    ),
+                ],
+                active_port: Some("analog-output-speaker".to_string()),
+            }
+        );
+        assert!(parse_sinks("").is_empty());
+    }
+
+    #[test]
+    fn detail_has_the_sink_ports_and_channels() {
+        let mut volume = parse(
+            include_str!("../tests/fixtures/pactl-get-sink-volume.txt"),
+            include_str!("../tests/fixtures/pactl-get-sink-mute-yes.txt"),
+        )
+        .unwrap();
+        assert_eq!(
+            volume.detail().unwrap(),
+            "Left: 70%\nRight: 60%\nMuted: yes"
+        );
+        volume.sink = parse_sinks(include_str!("../tests/fixtures/pactl-list-sinks.txt")).pop();
+        assert_eq!(
+            volume.detail().unwrap(),
+            "Sink: Built-in Audio Analog Stereo (alsa_output.pci-0000_00_1f.3.analog-stereo)\n\
+             Ports: Speakers (active), Headphones\n\
+             Left: 70%\n\
+             Right: 60%\n\
+             Muted: yes"
+        );
+    }
+
+    #[test]
+    fn parses_pactl_list_sink_inputs() {
+        let inputs =
+            parse_sink_inputs(include_str!("../tests/fixtures/pactl-list-sink-inputs.txt"))
+                .unwrap();
+        let parsed = inputs
+            .iter()
+            .map(|input| (input.app.as_str(), input.pct, input.mute))
+            .collect::<Vec<_>>();
+        // the binary stands in for a missing application.name
+        assert_eq!(parsed, [("Firefox <beta>", 80, false), ("mpv", 100, true)]);
+        let rendered = AppVolumes { inputs }.render(&crate::snapshots::style(false));
+        assert!(
+            rendered.contains(">Firefox &lt;beta&gt;:</span>"),
+            "{}",
+            rendered
+        );
+        assert!(!rendered.contains("<beta>"), "{}", rendered);
+
+        assert!(parse_sink_inputs("").unwrap().is_empty());
+        let unnamed =
+            parse_sink_inputs("Sink Input #7\n\tVolume: mono: 6554 / 10% / -60.00 dB\n").unwrap();
+        assert_eq!((unnamed[0].app.as_str(), unnamed[0].pct), ("#7", 10));
+        assert!(parse_sink_inputs("Sink Input #7\n\tMute: no\n").is_err());
+    }
+
+    #[test]
+    fn rejects_broken_pactl_output() {
+        let mute = "Mute: no\n";
+        assert!(parse("", mute).is_err());
+        assert!(parse("Volume: front-left: 45875 / 70%", mute).is_err());
+        assert!(parse("Volume: mono: loud", mute).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, Result};
+
+/// Set by SIGUSR1 to ask watch mode for an immediate refresh
+pub static REFRESH: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_refresh(_: libc::c_int) {
+    // only async-signal-safe work here, the watch loop does the rest
+    REFRESH.store(true, Ordering::SeqCst);
+}
+
+/// Set by SIGUSR2 to show or hide the widget in loop mode
+pub static TOGGLE_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_toggle_hidden(_: libc::c_int) {
+    TOGGLE_HIDDEN.store(true, Ordering::SeqCst);
+}
+
+/// Set by SIGTERM and SIGINT to make the loop exit after the current update
+pub static QUIT: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_quit(_: libc::c_int) {
+    QUIT.store(true, Ordering::SeqCst);
+}
+
+fn install(signum: libc::c_int, handler: extern "C" fn(libc::c_int)) -> Result<()> {
+    // SAFETY: the handlers only store to an atomic
+    let previous = unsafe { libc::signal(signum, handler as libc::sighandler_t) };
+    if previous == libc::SIG_ERR {
+        return Err(anyhow!(
+            "installing the handler for signal {} failed",
+            signum
+        ));
+    }
+    Ok(())
+}
+
+/// Make SIGUSR1 set [`REFRESH`], like i3blocks' `signal=` mechanism expects
+pub fn install_refresh_handler() -> Result<()> {
+    install(libc::SIGUSR1, on_refresh)
+}
+
+/// Make SIGUSR2 set [`TOGGLE_HIDDEN`]
+pub fn install_toggle_hidden_handler() -> Result<()> {
+    install(libc::SIGUSR2, on_toggle_hidden)
+}
+
+/// Make SIGTERM and SIGINT set [`QUIT`] instead of killing the process mid-line
+pub fn install_quit_handler() -> Result<()> {
+    install(libc::SIGTERM, on_quit)?;
+    install(libc::SIGINT, on_quit)
+}
@@ -0,0 +1,496 @@
+//! Golden-file tests of every widget in every output format, built from the outputs in
+//! `tests/fixtures/` and compared against `tests/snapshots/<widget>.txt`. Run with
+//! `UPDATE_SNAPSHOTS=1` to rewrite the golden files after an intended change.
+
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, Once},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use crate::{
+    cpu, hostname, lid, network, process::CommandSource, sway, tcp, template, wireguard, Align,
+    Appearance, ByteUnits, Cli, Clock, ColorSpec, Fallback, IconSet, Icons, MemoryWidget, Output,
+    OutputFormat, Samples, Style, Widget,
+};
+
+/// Point the state files, the cache and the local time zone somewhere fixed, setting them up on
+/// the first call. Tests run in parallel, so every test that reads them holds the returned
+/// guard, which keeps the others from reading while they are set
+pub fn test_env() -> MutexGuard<'static, ()> {
+    static ENV: Mutex<()> = Mutex::new(());
+    static ONCE: Once = Once::new();
+    // a test that failed while holding it left the environment as set up
+    let guard = ENV.lock().unwrap_or_else(|err| err.into_inner());
+    ONCE.call_once(|| {
+        for (var, dir) in [
+            ("XDG_STATE_HOME", "target/test-state"),
+            ("XDG_RUNTIME_DIR", "target/test-runtime"),
+        ] {
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+            let _ = std::fs::remove_dir_all(&dir);
+            std::env::set_var(var, dir);
+        }
+        std::env::set_var("TZ", "UTC");
+    });
+    guard
+}
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Answers the commands the widgets run with the files in `tests/fixtures/`
+pub struct FixtureSource;
+
+impl CommandSource for FixtureSource {
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        let command = format!("{} {}", program, args.join(" "));
+        let name = match command.as_str() {
+            "upower -i /org/freedesktop/UPower/devices/battery_BAT0" => "upower-discharging.txt",
+            "upower -i /org/freedesktop/UPower/devices/battery_BAT1" => "upower-charging-mwh.txt",
+            "pactl get-sink-volume @DEFAULT_SINK@" => "pactl-get-sink-volume.txt",
+            "pactl get-sink-mute @DEFAULT_SINK@" => "pactl-get-sink-mute-no.txt",
+            "pactl get-default-sink" => "pactl-get-default-sink.txt",
+            "pactl list sinks" => "pactl-list-sinks.txt",
+            "pactl list sink-inputs" => "pactl-list-sink-inputs.txt",
+            "brightnessctl info" => "brightnessctl-info.txt",
+            "virsh list --all" => "virsh-list-all.txt",
+            "virsh list --name --state-running" => "virsh-list-running.txt",
+            "df -B1 --output=size,used,avail /" => "df-root.txt",
+            "df -B1 --output=target,size,pcent" => "df-mounts.txt",
+            "aplay -l" => "aplay-l.txt",
+            "loginctl list-sessions --no-legend" => "loginctl-list-sessions.txt",
+            "nvidia-smi --query-gpu=fan.speed --format=csv,noheader,nounits" => {
+                "nvidia-smi-fan.txt"
+            }
+            _ => return Err(anyhow!("no fixture for `{}`", command)),
+        };
+        Ok(std::fs::read_to_string(fixtures().join(name))?)
+    }
+}
+
+/// 2026-10-14 18:13:20 UTC
+pub struct FixedClock;
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_792_001_600)
+    }
+}
+
+/// The default dark theme and emoji icons, as configured without flags or a config file
+pub fn style(no_icons: bool) -> Style {
+    Style {
+        colors: ColorSpec {
+            appearance: Some(Appearance::Dark),
+            ..ColorSpec::default()
+        },
+        resolved: OnceCell::new(),
+        icons: Icons::new(IconSet::Emoji),
+        hide_label: false,
+        no_icons,
+        templates: HashMap::new(),
+        units: &[],
+    }
+}
+
+const FORMATS: &[(&str, OutputFormat)] = &[
+    ("pango", OutputFormat::Pango),
+    ("i3bar", OutputFormat::I3bar),
+    ("json", OutputFormat::Json),
+    ("i3blocks", OutputFormat::I3blocks),
+    ("polybar", OutputFormat::Polybar),
+    ("lemonbar", OutputFormat::Lemonbar),
+    ("ansi", OutputFormat::Ansi),
+];
+
+/// The widget in every output format, under a `--- <format>` header each
+pub fn render_all(widget: &dyn Widget, style: &Style) -> String {
+    let mut text = String::new();
+    for (name, format) in FORMATS {
+        let output = Output {
+            format: *format,
+            align_right: false,
+            lines: 1,
+            hide_zero: false,
+            min_width: 0,
+            align: Align::Left,
+            background: None,
+            border: None,
+            border_px: None,
+        };
+        let mut out = Vec::new();
+        output.write(&mut out, widget, style).unwrap();
+        text.push_str(&format!("--- {}\n", name));
+        text.push_str(&String::from_utf8(out).unwrap());
+    }
+    text
+}
+
+/// Compare `actual` with `tests/snapshots/<name>.txt`, or write it with `UPDATE_SNAPSHOTS`.
+/// Returns a description of the mismatch
+pub fn check(name: &str, actual: &str) -> Option<String> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return None;
+    }
+    match std::fs::read_to_string(&path) {
+        Ok(expected) if expected == actual => None,
+        Ok(expected) => Some(format!(
+            "{} differs\n--- expected\n{}--- actual\n{}",
+            path.display(),
+            expected,
+            actual
+        )),
+        Err(err) => Some(format!("{}: {}", path.display(), err)),
+    }
+}
+
+/// The widget `args` fetch from the fixtures
+fn fetched(args: &str) -> Box<dyn Widget> {
+    let cli = Cli::try_parse_from(std::iter::once("i3widgets").chain(args.split(' '))).unwrap();
+    let mut samples = Samples::default();
+    crate::fetch(
+        &cli.command,
+        &FixtureSource,
+        &FixedClock,
+        &mut samples,
+        false,
+    )
+    .unwrap_or_else(|err| panic!("fetching `{}`: {:#}", args, err))
+}
+
+/// The widgets whose fetch reads `/proc` or `/sys` directly, built from the same parsers
+fn built(name: &str) -> Box<dyn Widget> {
+    let fixture = |name: &str| std::fs::read_to_string(fixtures().join(name)).unwrap();
+    match name {
+        "memory" => Box::new(MemoryWidget {
+            info: crate::parse_memory_info(&fixture("free.txt")).unwrap(),
+            units: ByteUnits { si: false },
+            bars: 0,
+            show_hugepages: false,
+            swap: false,
+        }),
+        "memory-bars" => Box::new(MemoryWidget {
+            info: crate::parse_memory_info(&fixture("free.txt")).unwrap(),
+            units: ByteUnits { si: false },
+            bars: 5,
+            show_hugepages: false,
+            swap: false,
+        }),
+        "cpu" => Box::new(cpu::CpuUsage {
+            usage_pct: 37,
+            cores: vec![12, 95, 40, 3],
+            per_core: true,
+        }),
+        "tcp-connections" => Box::new(tcp::Connections {
+            counts: tcp::parse(&fixture("proc-net-tcp.txt")).unwrap(),
+            filter: None,
+        }),
+        "network" => Box::new(network::NetworkWidget {
+            interface: "wlan0".to_string(),
+            traffic: network::Traffic::Rate {
+                rx: 1_250_000.0,
+                tx: 48_000.0,
+            },
+            units: ByteUnits { si: false },
+        }),
+        "wireguard" => Box::new(wireguard::Peers {
+            interface: "wg0".to_string(),
+            total: 3,
+            active: 2,
+        }),
+        "sway-mode" => Box::new(sway::Mode {
+            name: "resize".to_string(),
+            default_text: None,
+        }),
+        "hostname" => Box::new(hostname::Hostname {
+            name: "laptop.example.com".to_string(),
+        }),
+        "lid-state" => Box::new(lid::Lid { open: false }),
+        _ => unreachable!("no widget `{}`", name),
+    }
+}
+
+/// Snapshot name and the arguments that fetch it
+const FETCHED: &[(&str, &str)] = &[
+    (
+        "battery",
+        "battery --device-path /org/freedesktop/UPower/devices/battery_BAT0",
+    ),
+    (
+        "battery-charging",
+        "battery --device-path /org/freedesktop/UPower/devices/battery_BAT1 --show-cycles",
+    ),
+    ("sink-volume", "sink-volume"),
+    ("app-volume", "app-volume"),
+    ("brightness", "brightness"),
+    ("virsh-active", "virsh-active"),
+    ("disk", "disk"),
+    ("disk-tree", "disk-tree"),
+    ("sound-cards", "sound-cards"),
+    ("sessions", "sessions --show-users"),
+    ("gpu-fan", "gpu-fan"),
+    #[cfg(feature = "time")]
+    ("time", "time --seconds"),
+    #[cfg(feature = "time")]
+    ("time-zh", "time-zh --era minguo"),
+    #[cfg(feature = "time")]
+    ("countdown", "countdown --until 2026-10-14T18:30:00+00:00"),
+];
+
+const BUILT: &[&str] = &[
+    "memory",
+    "memory-bars",
+    "cpu",
+    "tcp-connections",
+    "network",
+    "wireguard",
+    "sway-mode",
+    "hostname",
+    "lid-state",
+];
+
+/// Compare each of `widgets` rendered with `style` against the snapshot of its name followed
+/// by `suffix`
+fn check_all<'a>(
+    widgets: impl Iterator<Item = (&'a str, Box<dyn Widget>)>,
+    style: &Style,
+    suffix: &str,
+) {
+    let failures = widgets
+        .filter_map(|(name, widget)| {
+            check(
+                &format!("{}{}", name, suffix),
+                &render_all(widget.as_ref(), style),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        failures.is_empty(),
+        "{}\nrerun with UPDATE_SNAPSHOTS=1 if the change is intended",
+        failures.join("\n")
+    );
+}
+
+/// The blocks shown instead of a widget whose tool is missing, whose fetch failed and whose
+/// tool timed out
+fn fallbacks() -> impl Iterator<Item = (&'static str, Box<dyn Widget>)> {
+    let missing = anyhow::Error::new(crate::process::ToolMissing {
+        name: "pactl".to_string(),
+    });
+    let failed = anyhow!("unexpected output from `pactl get-sink-volume`");
+    let timeout = anyhow::Error::new(crate::process::Timeout {
+        program: "virsh".to_string(),
+        timeout: Duration::from_secs(2),
+    });
+    [
+        (
+            "fallback-missing",
+            Fallback::new("sink-volume", "volume_muted", &missing),
+        ),
+        (
+            "fallback-failed",
+            Fallback::new("sink-volume", "error", &failed),
+        ),
+        (
+            "fallback-timeout",
+            Fallback::new("virsh-active", "vm", &timeout),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, fallback)| (name, Box::new(fallback) as Box<dyn Widget>))
+}
+
+#[test]
+fn every_widget_matches_its_snapshot() {
+    let _env = test_env();
+    let widgets = FETCHED
+        .iter()
+        .map(|(name, args)| (*name, fetched(args)))
+        .chain(BUILT.iter().map(|name| (*name, built(name))));
+    check_all(widgets, &style(false), "");
+}
+
+#[test]
+fn fallbacks_match_their_snapshots() {
+    check_all(fallbacks(), &style(false), "");
+}
+
+#[test]
+fn fallbacks_without_icons_match_their_snapshots() {
+    check_all(fallbacks(), &style(true), "-no-icons");
+}
+
+#[test]
+fn widgets_without_icons_match_their_snapshots() {
+    let _env = test_env();
+    let widgets = FETCHED
+        .iter()
+        .filter(|(name, _)| ["battery", "sink-volume", "virsh-active"].contains(name))
+        .map(|(name, args)| (*name, fetched(args)))
+        .chain(
+            ["memory", "network"]
+                .into_iter()
+                .map(|name| (name, built(name))),
+        );
+    check_all(widgets, &style(true), "-no-icons");
+}
+
+#[test]
+fn templates_match_their_snapshots() {
+    let _env = test_env();
+    let template = template::Template::parse(
+        "{icon} {charge_pct:bold,accent}% {state:fg}",
+        template::fields("battery").unwrap(),
+    )
+    .unwrap();
+    let args = FETCHED
+        .iter()
+        .find(|(name, _)| *name == "battery")
+        .unwrap()
+        .1;
+    for (no_icons, suffix) in [(false, "-template"), (true, "-template-no-icons")] {
+        let mut style = style(no_icons);
+        style
+            .templates
+            .insert("battery".to_string(), template.clone());
+        check_all(std::iter::once(("battery", fetched(args))), &style, suffix);
+    }
+}
+
+#[test]
+fn overridden_colors_match_their_snapshots() {
+    let _env = test_env();
+    let mut style = style(false);
+    style.colors.fg = Some("#ff0000".to_string());
+    style.colors.accent = Some("green".to_string());
+    style.colors.overrides = vec![
+        ("battery.pct".to_string(), "#00f".to_string()),
+        ("memory.unit".to_string(), "magenta".to_string()),
+    ];
+    let widgets = FETCHED
+        .iter()
+        .filter(|(name, _)| *name == "battery")
+        .map(|(name, args)| (*name, fetched(args)))
+        .chain(
+            ["memory", "hostname"]
+                .into_iter()
+                .map(|name| (name, built(name))),
+        );
+    check_all(widgets, &style, "-overrides");
+}
+
+#[test]
+fn every_widget_has_its_short_form() {
+    let _env = test_env();
+    // `None` only where there is no sensible short form, never an empty string
+    let cases: &[(&str, Option<&str>)] = &[
+        ("battery", Some("74<span color=\"#a9b1d6\">%</span>")),
+        ("sink-volume", Some("<span font_size=\"120%\">🔊</span>")),
+        ("app-volume", Some("2")),
+        ("brightness", Some("40<span color=\"#a9b1d6\">%</span>")),
+        ("virsh-active", Some("<span font_size=\"120%\">💻</span> 2")),
+        ("disk", Some("234.1GiB")),
+        ("disk-tree", Some("/run/media/me/usb 97%")),
+        ("sound-cards", Some("2")),
+        ("sessions", Some("2")),
+        ("gpu-fan", Some("42<span color=\"#a9b1d6\">%</span>")),
+        #[cfg(feature = "time")]
+        ("time", Some("18:13")),
+        #[cfg(feature = "time")]
+        ("time-zh", Some("06:13")),
+        #[cfg(feature = "time")]
+        ("countdown", Some("16:40")),
+        ("memory", Some("37<span color=\"#a9b1d6\">%</span>")),
+        ("cpu", Some("37<span color=\"#a9b1d6\">%</span>")),
+        ("tcp-connections", Some("2")),
+        ("network", Some("1.2MiB/s 46.9KiB/s")),
+        ("wireguard", Some("2")),
+        ("sway-mode", None),
+        ("hostname", Some("laptop")),
+        ("lid-state", Some("closed")),
+    ];
+    let style = style(false);
+    for (name, short) in cases {
+        let widget = match FETCHED.iter().find(|(fetched, _)| fetched == name) {
+            Some((_, args)) => fetched(args),
+            None => built(name),
+        };
+        assert_eq!(widget.short(&style).as_deref(), *short, "{}", name);
+    }
+    // without icons, as the outputs tidy it, the icon-only form falls back to the value
+    let style = self::style(true);
+    for (name, short) in [
+        ("sink-volume", "70<span color=\"#a9b1d6\">%</span>"),
+        ("virsh-active", "2"),
+        ("battery", "74<span color=\"#a9b1d6\">%</span>"),
+    ] {
+        let widget = fetched(
+            FETCHED
+                .iter()
+                .find(|(fetched, _)| *fetched == name)
+                .unwrap()
+                .1,
+        );
+        let tidied = widget.short(&style).map(|short| style.tidy(short));
+        assert_eq!(tidied.as_deref(), Some(short), "{}", name);
+    }
+}
+
+#[test]
+fn short_forms_take_the_unit_locale_and_hide_label() {
+    let _env = test_env();
+    let battery = fetched(
+        FETCHED
+            .iter()
+            .find(|(fetched, _)| *fetched == "battery")
+            .unwrap()
+            .1,
+    );
+    let mut style = style(false);
+    style.units = crate::unit_table("zh_CN.UTF-8");
+    assert_eq!(
+        battery.short(&style).as_deref(),
+        Some("74<span color=\"#a9b1d6\">％</span>")
+    );
+    style.hide_label = true;
+    assert_eq!(battery.short(&style).as_deref(), Some("74"));
+}
+
+/// Not a check, the render timings of a representative battery and time-zh line for comparing
+/// serializer changes. Their output is pinned by the snapshots above
+#[test]
+#[ignore = "a benchmark, run with `cargo test --release bench -- --ignored --nocapture`"]
+fn bench_rendering() {
+    let _env = test_env();
+    let style = style(false);
+    let widgets = FETCHED
+        .iter()
+        .filter(|(name, _)| ["battery-charging", "time-zh"].contains(name))
+        .map(|(name, args)| (*name, fetched(args)));
+    for (name, widget) in widgets {
+        const RUNS: u32 = 20_000;
+        let start = std::time::Instant::now();
+        let bytes = (0..RUNS)
+            .map(|_| widget.render(&style).len())
+            .sum::<usize>();
+        let elapsed = start.elapsed();
+        println!(
+            "{:<18}{:>8.2?} per render, {} bytes",
+            name,
+            elapsed / RUNS,
+            bytes / RUNS as usize
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+/// A value kept in a state file, in a small text format of its own
+pub trait Stored: Sized {
+    /// `None` for contents that aren't a valid value, e.g. a truncated or foreign file
+    fn parse(contents: &str) -> Option<Self>;
+    fn serialize(&self) -> String;
+}
+
+pub fn dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(
+            std::env::var_os("HOME")
+                .ok_or_else(|| anyhow!("neither XDG_STATE_HOME nor HOME is set"))?,
+        )
+        .join(".local/state"),
+    };
+    Ok(base.join("i3widgets"))
+}
+
+/// Take an exclusive `flock` on `path`, held until the returned file is dropped
+pub fn lock(path: &Path) -> Result<File> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    // SAFETY: plain syscall on a descriptor we own
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(anyhow!("locking {}", path.display()));
+    }
+    Ok(file)
+}
+
+/// The value in the state file `name`, `None` if it doesn't exist yet or can't be parsed
+pub fn load<T: Stored>(name: &str) -> Result<Option<T>> {
+    let path = dir()?.join(name);
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => Ok(T::parse(&contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        // not UTF-8, so nothing we wrote
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidData => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Replace the state file `name`, through a rename so a concurrent reader never sees half
+/// of it
+pub fn store<T: Stored>(name: &str, value: &T) -> Result<()> {
+    // unique per write, so threads of one process don't share a temporary file either
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let path = dir.join(name);
+    let tmp = dir.join(format!(
+        ".{}.{}.{}",
+        name,
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&tmp, value.serialize())
+        .with_context(|| format!("writing {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Replace the value in the state file `name` with `f` of the current one. Holds a lock on
+/// `<name>.lock` meanwhile, so concurrent runs can't lose each other's updates.
+pub fn update<T: Stored>(name: &str, f: impl FnOnce(Option<T>) -> Result<T>) -> Result<T> {
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let _lock = lock(&dir.join(format!("{}.lock", name)))?;
+    let value = f(load(name)?)?;
+    store(name, &value)?;
+    Ok(value)
+}
+
+/// The last few readings of something, oldest first, stored space-separated
+pub struct Recent<T>(pub Vec<T>);
+
+impl<T: FromStr + Display> Stored for Recent<T> {
+    fn parse(contents: &str) -> Option<Self> {
+        let readings = contents
+            .split_whitespace()
+            .map(|value| value.parse().ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self(readings))
+    }
+
+    fn serialize(&self) -> String {
+        self.0
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Push `value` onto the last `len` readings in the state file `name`, taken from `recent` if
+/// populated and otherwise from the file, so single-shot runs build them up too
+pub fn push_recent<T: FromStr + Display>(
+    name: &str,
+    value: T,
+    len: usize,
+    recent: &mut Vec<T>,
+) -> Result<()> {
+    *recent = update(name, |saved: Option<Recent<T>>| {
+        // a running `--interval` trusts its own readings over the file
+        let mut readings = if recent.is_empty() {
+            saved.map(|saved| saved.0).unwrap_or_default()
+        } else {
+            std::mem::take(recent)
+        };
+        readings.push(value);
+        let excess = readings.len().saturating_sub(len);
+        readings.drain(..excess);
+        Ok(Recent(readings))
+    })?
+    .0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Counter(u64);
+
+    impl Stored for Counter {
+        fn parse(contents: &str) -> Option<Self> {
+            contents.strip_prefix("count ")?.parse().ok().map(Self)
+        }
+
+        fn serialize(&self) -> String {
+            format!("count {}", self.0)
+        }
+    }
+
+    /// A long line of one repeated digit, which a torn write would show as mixed digits or a
+    /// short length
+    struct Filler(u8);
+
+    impl Stored for Filler {
+        fn parse(contents: &str) -> Option<Self> {
+            let digit = *contents.as_bytes().first()?;
+            (contents.len() == 64 * 1024 && contents.bytes().all(|b| b == digit))
+                .then_some(Self(digit))
+        }
+
+        fn serialize(&self) -> String {
+            (self.0 as char).to_string().repeat(64 * 1024)
+        }
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let _env = crate::snapshots::test_env();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        update("test.counter", |count: Option<Counter>| {
+                            Ok(Counter(count.map_or(0, |count| count.0) + 1))
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(load("test.counter").unwrap(), Some(Counter(200)));
+    }
+
+    #[test]
+    fn readers_never_see_half_a_write() {
+        let _env = crate::snapshots::test_env();
+        store("test.filler", &Filler(b'0')).unwrap();
+        std::thread::scope(|scope| {
+            for digit in [b'1', b'2'] {
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        store("test.filler", &Filler(digit)).unwrap();
+                    }
+                });
+            }
+            for _ in 0..200 {
+                assert!(load::<Filler>("test.filler").unwrap().is_some());
+            }
+        });
+    }
+
+    #[test]
+    fn corrupt_and_missing_files_are_none() {
+        let _env = crate::snapshots::test_env();
+        let dir = dir().unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(load::<Counter>("test.missing").unwrap(), None);
+        for (name, contents) in [
+            ("test.truncated", &b"count "[..]),
+            ("test.foreign", b"{\"count\": 3}"),
+            ("test.binary", &[b'c', 0xff, 0xfe, 0x00]),
+        ] {
+            std::fs::write(dir.join(name), contents).unwrap();
+            assert_eq!(load::<Counter>(name).unwrap(), None, "{}", name);
+            // and an update starts over instead of failing
+            let count = update(name, |count: Option<Counter>| {
+                Ok(Counter(count.map_or(0, |count| count.0) + 1))
+            })
+            .unwrap();
+            assert_eq!(count, Counter(1), "{}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_readings() {
+        let _env = crate::snapshots::test_env();
+        for name in ["test-cpu.history", "test-battery.pct"] {
+            let _ = std::fs::remove_file(dir().unwrap().join(name));
+        }
+        let mut recent = Vec::new();
+        for value in [1.0, 2.0, 3.0, 4.0, 5.0] {
+            push_recent("test-cpu.history", value, 3, &mut recent).unwrap();
+        }
+        assert_eq!(recent, [3.0, 4.0, 5.0]);
+        // a one-shot run starts from the state file
+        let mut fresh = Vec::new();
+        push_recent("test-cpu.history", 6.0, 3, &mut fresh).unwrap();
+        assert_eq!(fresh, [4.0, 5.0, 6.0]);
+        // a shorter window drops the oldest
+        push_recent("test-cpu.history", 7.0, 2, &mut fresh).unwrap();
+        assert_eq!(fresh, [6.0, 7.0]);
+        // and whole numbers read back as they were written
+        let mut pcts = Vec::new();
+        push_recent("test-battery.pct", 80, 5, &mut pcts).unwrap();
+        assert_eq!(
+            load::<Recent<i32>>("test-battery.pct").unwrap().unwrap().0,
+            [80]
+        );
+    }
+}
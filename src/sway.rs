@@ -0,0 +1,115 @@
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, pango_escape, Color, Span, Style, Widget};
+
+const MAGIC: &[u8] = b"i3-ipc";
+const SUBSCRIBE: u32 = 2;
+const GET_BINDING_STATE: u32 = 12;
+/// The high bit marks events, the rest is the event type
+const MODE_EVENT: u32 = 0x8000_0002;
+
+pub struct Mode {
+    pub name: String,
+    /// `--default-text`
+    pub default_text: Option<String>,
+}
+
+impl Mode {
+    fn is_default(&self) -> bool {
+        self.name == "default"
+    }
+}
+
+impl Widget for Mode {
+    fn render(&self, style: &Style) -> String {
+        if self.is_default() {
+            return self
+                .default_text
+                .as_deref()
+                .map(pango_escape)
+                .unwrap_or_default();
+        }
+        pango!(
+            self.name,
+            color = style.color("sway_mode.mode", style.theme().red()),
+            weight = "ultrabold",
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new().field("mode", self.name.as_str())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.is_default() && self.default_text.is_none()
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        (!self.is_default()).then(|| style.theme().red())
+    }
+}
+
+/// `SWAYSOCK`, or `I3SOCK` under i3
+pub fn socket_path() -> Result<PathBuf> {
+    ["SWAYSOCK", "I3SOCK"]
+        .into_iter()
+        .find_map(|var| std::env::var_os(var).filter(|path| !path.is_empty()))
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("neither SWAYSOCK nor I3SOCK is set"))
+}
+
+fn connect() -> Result<UnixStream> {
+    let path = socket_path()?;
+    UnixStream::connect(&path).with_context(|| format!("connecting to {}", path.display()))
+}
+
+fn send(stream: &mut UnixStream, kind: u32, payload: &str) -> std::io::Result<()> {
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream.write_all(&message)
+}
+
+/// The type and payload of the next message
+fn receive(stream: &mut UnixStream) -> Result<(u32, String)> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(anyhow!("invalid IPC reply"));
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into()?);
+    let kind = u32::from_ne_bytes(header[10..14].try_into()?);
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((
+        kind,
+        String::from_utf8(payload).context("IPC reply isn't utf-8")?,
+    ))
+}
+
+/// The name of the active binding mode, `default` outside of any
+pub fn binding_mode() -> Result<String> {
+    let mut stream = connect()?;
+    send(&mut stream, GET_BINDING_STATE, "")?;
+    let (_, reply) = receive(&mut stream)?;
+    json::string_field(&reply, "name").ok_or_else(|| anyhow!("invalid binding state reply"))
+}
+
+/// Call `notify` on every mode change until the connection breaks
+pub fn watch_modes(notify: impl Fn()) -> Result<()> {
+    let mut stream = connect()?;
+    send(&mut stream, SUBSCRIBE, r#"["mode"]"#)?;
+    loop {
+        let (kind, _) = receive(&mut stream)?;
+        if kind == MODE_EVENT {
+            notify();
+        }
+    }
+}
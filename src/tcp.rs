@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, Color, Span, Style, Widget, TABULAR_NUMS};
+
+/// TIME_WAIT counts from here on are colored as a sign of connection churn
+const TIME_WAIT_WARN: usize = 100;
+
+/// Connection states in the order of their codes in `/proc/net/tcp`, starting at `01`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum State {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Close,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+}
+
+impl State {
+    const ALL: [State; 11] = [
+        State::Established,
+        State::SynSent,
+        State::SynRecv,
+        State::FinWait1,
+        State::FinWait2,
+        State::TimeWait,
+        State::Close,
+        State::CloseWait,
+        State::LastAck,
+        State::Listen,
+        State::Closing,
+    ];
+
+    /// The kernel's name for the state in lower case, e.g. `time_wait`
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Established => "established",
+            State::SynSent => "syn_sent",
+            State::SynRecv => "syn_recv",
+            State::FinWait1 => "fin_wait1",
+            State::FinWait2 => "fin_wait2",
+            State::TimeWait => "time_wait",
+            State::Close => "close",
+            State::CloseWait => "close_wait",
+            State::LastAck => "last_ack",
+            State::Listen => "listen",
+            State::Closing => "closing",
+        }
+    }
+
+    /// Parse the hex code in the `st` column
+    fn from_code(code: &str) -> Option<State> {
+        let code = u8::from_str_radix(code, 16).ok()?;
+        State::ALL.get(usize::from(code).checked_sub(1)?).copied()
+    }
+}
+
+pub struct Connections {
+    pub counts: HashMap<State, usize>,
+    /// Only show the count of this state
+    pub filter: Option<State>,
+}
+
+impl Connections {
+    pub fn count(&self, state: State) -> usize {
+        self.counts.get(&state).copied().unwrap_or(0)
+    }
+
+    fn time_wait_color(&self, style: &Style) -> Color {
+        if self.count(State::TimeWait) >= TIME_WAIT_WARN {
+            style.theme().yellow()
+        } else {
+            style.palette().primary
+        }
+    }
+}
+
+impl Widget for Connections {
+    fn render(&self, style: &Style) -> String {
+        let number = |count: usize, color: Color| {
+            pango!(
+                count,
+                color = color,
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            )
+        };
+        let icon = style.icon("tcp", "network");
+        match self.filter {
+            Some(state) => {
+                let color = match state {
+                    State::TimeWait => self.time_wait_color(style),
+                    _ => style.color("tcp.count", style.palette().primary),
+                };
+                format!("{icon} {}", number(self.count(state), color))
+            }
+            None => {
+                let mut text = format!(
+                    "{icon} {}",
+                    number(
+                        self.count(State::Established),
+                        style.color("tcp.count", style.palette().primary)
+                    )
+                );
+                let time_wait = self.count(State::TimeWait);
+                if time_wait > 0 {
+                    text += &format!(
+                        " {}{}",
+                        number(time_wait, self.time_wait_color(style)),
+                        pango!(
+                            "tw",
+                            color = style.color("tcp.unit", style.palette().secondary)
+                        ),
+                    );
+                }
+                text
+            }
+        }
+    }
+
+    fn to_json(&self) -> json::Object {
+        State::ALL
+            .iter()
+            .fold(json::Object::new(), |object, state| {
+                object.field(state.name(), self.count(*state))
+            })
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(
+            self.count(self.filter.unwrap_or(State::Established))
+                .to_string(),
+        )
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count(self.filter.unwrap_or(State::Established)) == 0
+    }
+}
+
+pub fn connections(filter: Option<State>) -> Result<Connections> {
+    let mut counts =
+        parse(&std::fs::read_to_string("/proc/net/tcp").context("reading /proc/net/tcp")?)?;
+    // missing when IPv6 is disabled
+    if let Ok(tcp6) = std::fs::read_to_string("/proc/net/tcp6") {
+        for (state, count) in parse(&tcp6)? {
+            *counts.entry(state).or_default() += count;
+        }
+    }
+    Ok(Connections { counts, filter })
+}
+
+/// Count the connections in each state from `/proc/net/tcp` or `/proc/net/tcp6`
+pub fn parse(table: &str) -> Result<HashMap<State, usize>> {
+    let mut counts = HashMap::new();
+    // sl  local_address rem_address   st tx_queue rx_queue ...
+    for line in table.lines().skip(1) {
+        let code = line
+            .split_whitespace()
+            .nth(3)
+            .ok_or_else(|| anyhow!("/proc/net/tcp line is invalid: {line}"))?;
+        let state = State::from_code(code)
+            .ok_or_else(|| anyhow!("unknown TCP state `{code}` in /proc/net/tcp"))?;
+        *counts.entry(state).or_default() += 1;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_state_codes() {
+        // (st column, state)
+        let cases = [
+            ("01", Some(State::Established)),
+            ("02", Some(State::SynSent)),
+            ("03", Some(State::SynRecv)),
+            ("04", Some(State::FinWait1)),
+            ("05", Some(State::FinWait2)),
+            ("06", Some(State::TimeWait)),
+            ("07", Some(State::Close)),
+            ("08", Some(State::CloseWait)),
+            ("09", Some(State::LastAck)),
+            ("0A", Some(State::Listen)),
+            ("0a", Some(State::Listen)),
+            ("0B", Some(State::Closing)),
+            ("00", None),
+            ("0C", None),
+            ("FF", None),
+            ("", None),
+            ("zz", None),
+        ];
+        for (code, state) in cases {
+            assert_eq!(State::from_code(code), state, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn counts_the_connections_by_state() {
+        let counts = parse(include_str!("../tests/fixtures/proc-net-tcp.txt")).unwrap();
+        let expected = HashMap::from([
+            (State::Listen, 1),
+            (State::Established, 2),
+            (State::TimeWait, 1),
+        ]);
+        assert_eq!(counts, expected);
+        // the header alone
+        assert!(parse("  sl  local_address rem_address   st\n")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_broken_lines() {
+        let header = "  sl  local_address rem_address   st tx_queue rx_queue\n";
+        for line in [
+            "   0: 0100007F:0277 00000000:0000\n",
+            "   0: 0100007F:0277 00000000:0000 0C 00000000:00000000\n",
+        ] {
+            assert!(parse(&format!("{header}{line}")).is_err(), "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use anyhow::{anyhow, Result};
+
+use crate::{json, Color, Escaped, Span, Style, Weight};
+
+/// The placeholders of the widgets that can be templated, the same as their JSON fields. Every
+/// one of them also has [`ICON`]
+pub const FIELDS: &[(&str, &[&str])] = &[
+    (
+        "battery",
+        &[
+            "state",
+            "charge_pct",
+            "energy_wh",
+            "energy_full_wh",
+            "energy_full_design_wh",
+            "health_pct",
+            "wear_warning",
+            "time_to_empty_secs",
+            "time_to_full_secs",
+            "profile",
+            "cycle_count",
+            "power_source",
+        ],
+    ),
+    (
+        "memory",
+        &[
+            "total_kib",
+            "used_kib",
+            "buffers_kib",
+            "cached_kib",
+            "swap_total_kib",
+            "swap_used_kib",
+            "thrash_risk",
+            "huge_pages_total",
+            "huge_pages_free",
+        ],
+    ),
+    ("cpu", &["usage_pct"]),
+    (
+        "sink-volume",
+        &["left_pct", "right_pct", "muted", "remembered_pct"],
+    ),
+    ("brightness", &["current", "max", "brightness_pct"]),
+];
+
+/// The placeholder for the icon the widget leads with, which `--no-icons` leaves empty
+pub const ICON: &str = "icon";
+
+/// The placeholders of `widget`, `None` if it can't be templated
+pub fn fields(widget: &str) -> Option<&'static [&'static str]> {
+    FIELDS
+        .iter()
+        .find(|(name, _)| *name == widget)
+        .map(|(_, fields)| *fields)
+}
+
+/// How a placeholder is styled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Modifier {
+    Bold,
+    /// The `--fg` color
+    Fg,
+    /// The `--accent` color
+    Accent,
+    /// One of [`crate::Theme::FIELDS`]
+    Theme(String),
+}
+
+impl Modifier {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "bold" => Some(Modifier::Bold),
+            "fg" => Some(Modifier::Fg),
+            "accent" => Some(Modifier::Accent),
+            name if crate::Theme::FIELDS.contains(&name) => Some(Modifier::Theme(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Literal(String),
+    Field {
+        name: &'static str,
+        modifiers: Vec<Modifier>,
+    },
+    Icon {
+        modifiers: Vec<Modifier>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse `text`, where `{name:modifier,...}` is a placeholder for one of `fields` or
+    /// [`ICON`], and `{{` and `}}` are literal braces. Errors point at the 1-based column of
+    /// the problem
+    pub fn parse(text: &str, fields: &'static [&'static str]) -> Result<Self> {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match (chars[i], chars.get(i + 1)) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    literal.push(chars[i]);
+                    i += 2;
+                }
+                ('}', _) => {
+                    return Err(anyhow!(
+                        "column {}: unmatched `}}`, write `}}}}` for a literal one",
+                        i + 1
+                    ))
+                }
+                ('{', _) => {
+                    let end = chars[i..]
+                        .iter()
+                        .position(|&c| c == '}')
+                        .map(|len| i + len)
+                        .ok_or_else(|| anyhow!("column {}: unclosed `{{`", i + 1))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(placeholder(&chars[i + 1..end], i + 2, fields)?);
+                    i = end + 1;
+                }
+                (c, _) => {
+                    literal.push(c);
+                    i += 1;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// The markup for a widget whose JSON is `object` and whose leading icon is `icon`, the
+    /// widget and icon names for [`Style::icon`]. Missing values are left empty
+    pub fn render(
+        &self,
+        object: &json::Object,
+        icon: Option<(&str, &str)>,
+        style: &Style,
+    ) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => Escaped(text).to_string(),
+                Part::Field { name, modifiers } => {
+                    let value = object
+                        .fields()
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| text(value))
+                        .unwrap_or_default();
+                    styled(Span::new(value), modifiers, style).to_string()
+                }
+                Part::Icon { modifiers } => match icon {
+                    // left out by `--no-icons` as an empty span, which the output tidies away
+                    Some((widget, name)) if style.no_icons => style.icon(widget, name).to_string(),
+                    Some((widget, name)) => {
+                        styled(style.icon(widget, name), modifiers, style).to_string()
+                    }
+                    None => String::new(),
+                },
+            })
+            .collect()
+    }
+}
+
+/// The placeholder between the braces, which starts at `column`
+fn placeholder(inner: &[char], column: usize, fields: &'static [&'static str]) -> Result<Part> {
+    let inner = inner.iter().collect::<String>();
+    let (name, modifiers) = match inner.split_once(':') {
+        Some((name, modifiers)) => (name, Some(modifiers)),
+        None => (inner.as_str(), None),
+    };
+    let field = std::iter::once(&ICON)
+        .chain(fields)
+        .find(|field| **field == name.trim())
+        .ok_or_else(|| {
+            anyhow!(
+                "column {}: unknown placeholder `{}`, expected one of {}, {}",
+                column,
+                name.trim(),
+                ICON,
+                fields.join(", ")
+            )
+        })?;
+    let mut parsed = Vec::new();
+    // the column of each modifier, past the name and its colon
+    let mut offset = column + name.chars().count() + 1;
+    for modifier in modifiers
+        .into_iter()
+        .flat_map(|modifiers| modifiers.split(','))
+    {
+        let trimmed = modifier.trim();
+        let at = offset + (modifier.chars().count() - modifier.trim_start().chars().count());
+        parsed.push(Modifier::parse(trimmed).ok_or_else(|| {
+            anyhow!(
+                "column {}: unknown modifier `{}`, expected bold, fg, accent or a theme color",
+                at,
+                trimmed
+            )
+        })?);
+        offset += modifier.chars().count() + 1;
+    }
+    Ok(match *field {
+        ICON => Part::Icon { modifiers: parsed },
+        name => Part::Field {
+            name,
+            modifiers: parsed,
+        },
+    })
+}
+
+/// A JSON value as plain text: strings unquoted and `null` empty
+fn text(value: &str) -> String {
+    match value {
+        "null" => String::new(),
+        value => json::parse_string(value).unwrap_or_else(|| value.to_string()),
+    }
+}
+
+fn styled(mut span: Span, modifiers: &[Modifier], style: &Style) -> Span {
+    for modifier in modifiers {
+        let color: Color = match modifier {
+            Modifier::Bold => {
+                span = span.weight(Weight::Bold);
+                continue;
+            }
+            Modifier::Fg => style.palette().secondary,
+            Modifier::Accent => style.palette().primary,
+            Modifier::Theme(name) => match style.theme().by_name(name) {
+                Some(color) => color,
+                None => continue,
+            },
+        };
+        span = span.color(color);
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> Part {
+        Part::Literal(text.to_string())
+    }
+
+    fn field(modifiers: Vec<Modifier>) -> Part {
+        Part::Field {
+            name: "usage_pct",
+            modifiers,
+        }
+    }
+
+    #[test]
+    fn parses_placeholders_and_literal_braces() {
+        let cases = [
+            ("{{x}}", vec![literal("{x}")]),
+            (
+                "{{{usage_pct}}}",
+                vec![literal("{"), field(vec![]), literal("}")],
+            ),
+            (
+                "cpu {usage_pct:bold,red}%",
+                vec![
+                    literal("cpu "),
+                    field(vec![Modifier::Bold, Modifier::Theme("red".to_string())]),
+                    literal("%"),
+                ],
+            ),
+            (
+                "{ usage_pct : accent , fg }",
+                vec![field(vec![Modifier::Accent, Modifier::Fg])],
+            ),
+            ("", vec![]),
+            (
+                "{icon:red} {usage_pct}",
+                vec![
+                    Part::Icon {
+                        modifiers: vec![Modifier::Theme("red".to_string())],
+                    },
+                    literal(" "),
+                    field(vec![]),
+                ],
+            ),
+        ];
+        for (text, parts) in cases {
+            let template = Template::parse(text, fields("cpu").unwrap()).unwrap();
+            assert_eq!(template.parts, parts, "{}", text);
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_column() {
+        let cases = [
+            ("a } b", "column 3: unmatched `}`"),
+            ("{{}", "column 3: unmatched `}`"),
+            ("x {usage_pct", "column 3: unclosed `{`"),
+            (
+                "{nope}",
+                "column 2: unknown placeholder `nope`, expected one of icon, usage_pct",
+            ),
+            ("{usage_pct:nope}", "column 12: unknown modifier `nope`"),
+            // past the whitespace, in characters rather than bytes
+            (
+                "{usage_pct: bold,  nope}",
+                "column 20: unknown modifier `nope`",
+            ),
+            ("{ usage_pct : nope }", "column 15: unknown modifier `nope`"),
+            ("é {usage_pct:,bold}", "column 14: unknown modifier ``"),
+        ];
+        for (text, message) in cases {
+            let err = Template::parse(text, fields("cpu").unwrap()).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn renders_the_icon_unless_icons_are_off() {
+        let template = Template::parse("{icon} {usage_pct:bold}%", fields("cpu").unwrap()).unwrap();
+        let object = json::Object::new().field("usage_pct", 37);
+        let style = crate::snapshots::style(false);
+        assert_eq!(
+            template.render(&object, Some(("cpu", "cpu")), &style),
+            format!(
+                "{} <span weight=\"bold\">37</span>%",
+                style.icon("cpu", "cpu")
+            )
+        );
+        // left for the output to tidy away, with the space after it
+        let style = crate::snapshots::style(true);
+        let markup = template.render(&object, Some(("cpu", "cpu")), &style);
+        assert_eq!(style.tidy(markup), "<span weight=\"bold\">37</span>%");
+        // and empty for a widget without one
+        let markup = template.render(&object, None, &crate::snapshots::style(false));
+        assert_eq!(markup, " <span weight=\"bold\">37</span>%");
+    }
+}
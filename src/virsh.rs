@@ -0,0 +1,297 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    json,
+    process::{self, CommandSource},
+    state::{self, Stored},
+    Color, Span, Style, Theme, Widget,
+};
+
+/// Set once the missing `virsh` was reported, so `--interval` doesn't repeat it every tick
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// The state file with the last listing, shown while `virsh` hangs
+const CACHE: &str = "virsh.cache";
+
+/// Like [`CACHE`] for `--running-only`, whose listing has no stopped VMs to show
+const CACHE_RUNNING: &str = "virsh-running.cache";
+
+/// Represents the state returned by the virsh list command
+#[derive(Debug)]
+pub struct State {
+    /// the active vms
+    active: Vec<String>,
+    /// the inactive vms
+    inactive: Vec<String>,
+    /// Whether this is the cached listing because `virsh` timed out
+    stale: bool,
+}
+
+/// One `running <name>` or `shut <name>` line per VM
+impl Stored for State {
+    fn parse(contents: &str) -> Option<Self> {
+        let mut state = State {
+            active: Vec::new(),
+            inactive: Vec::new(),
+            stale: false,
+        };
+        for line in contents.lines() {
+            match line.split_once(' ')? {
+                ("running", name) => state.active.push(name.to_string()),
+                ("shut", name) => state.inactive.push(name.to_string()),
+                _ => return None,
+            }
+        }
+        Some(state)
+    }
+
+    fn serialize(&self) -> String {
+        let active = self.active.iter().map(|name| format!("running {}\n", name));
+        let inactive = self.inactive.iter().map(|name| format!("shut {}\n", name));
+        active.chain(inactive).collect()
+    }
+}
+
+impl State {
+    /// The running VMs in green and the stopped ones dimmed after them, or a dimmed `none`
+    pub fn to_display_string(&self, theme: &Theme) -> String {
+        let names =
+            |names: &[String], color: Color| Span::new(names.join(" ")).color(color).to_string();
+        match (self.active.is_empty(), self.inactive.is_empty()) {
+            (true, true) => Span::new("none").color(theme.white()).to_string(),
+            (false, true) => names(&self.active, theme.green()),
+            (true, false) => names(&self.inactive, theme.white()),
+            (false, false) => format!(
+                "{} {}",
+                names(&self.active, theme.green()),
+                names(&self.inactive, theme.white())
+            ),
+        }
+    }
+}
+
+impl Widget for State {
+    fn render(&self, style: &Style) -> String {
+        format!(
+            "{} {}",
+            style.icon("virsh", "vm"),
+            self.to_display_string(style.theme())
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("active", &self.active)
+            .field("inactive", &self.inactive)
+            .field("stale", self.stale)
+    }
+
+    fn detail(&self) -> Option<String> {
+        let running = self.active.iter().map(|name| format!("{}: running", name));
+        let stopped = self
+            .inactive
+            .iter()
+            .map(|name| format!("{}: shut off", name));
+        let lines = running.chain(stopped).collect::<Vec<_>>();
+        Some(match lines.is_empty() {
+            true => "No VMs".to_string(),
+            false => lines.join("\n"),
+        })
+    }
+
+    /// The number of running VMs
+    fn short(&self, style: &Style) -> Option<String> {
+        Some(format!(
+            "{} {}",
+            style.icon("virsh", "vm"),
+            self.active.len()
+        ))
+    }
+
+    /// Stopped VMs don't count, there is nothing to keep an eye on then
+    fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+}
+
+/// No VMs when `virsh` isn't installed, as on machines without libvirt there are none
+pub fn list(running_only: bool, timeout: Duration, source: &dyn CommandSource) -> Result<State> {
+    let cache = if running_only { CACHE_RUNNING } else { CACHE };
+    let result = if running_only {
+        source
+            .output_within("virsh", &["list", "--name", "--state-running"], timeout)
+            .map(|output| State {
+                active: parse_names(&output),
+                inactive: Vec::new(),
+                stale: false,
+            })
+    } else {
+        source
+            .output_within("virsh", &["list", "--all"], timeout)
+            .and_then(|output| parse(&output))
+    };
+    match result {
+        Ok(state) => {
+            if let Err(err) = state::store(cache, &state) {
+                log!(Warn, "virsh-active", "caching the VMs: {:#}", err);
+            }
+            Ok(state)
+        }
+        Err(err) if err.is::<process::Timeout>() => {
+            // the timeout is what went wrong, a broken cache only explains why there's no listing
+            let mut cached = match state::load::<State>(cache) {
+                Ok(Some(cached)) => cached,
+                Ok(None) => return Err(err),
+                Err(cache_err) => {
+                    return Err(err.context(format!("no cached VMs either ({:#})", cache_err)))
+                }
+            };
+            log!(
+                Warn,
+                "virsh-active",
+                "`virsh` timed out, showing the last VMs it listed"
+            );
+            cached.stale = true;
+            Ok(cached)
+        }
+        Err(err) if process::is_not_found(&err) => {
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                log!(
+                    Warn,
+                    "virsh-active",
+                    "`virsh` is not installed, showing no VMs"
+                );
+            }
+            Ok(State {
+                active: Vec::new(),
+                inactive: Vec::new(),
+                stale: false,
+            })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Parse the output of `virsh list --name`, one name per line and a blank line at the end
+pub fn parse_names(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parse the output of `virsh list --all`
+pub fn parse(output: &str) -> Result<State> {
+    let mut active = Vec::new();
+    let mut inactive = Vec::new();
+    for line in output.trim().lines().skip(2) {
+        let mut fields = line.split_whitespace();
+        // skip the id
+        fields.next();
+        // FIXME: assume that the name has no spaces
+        let name = fields
+            .next()
+            .ok_or_else(|| anyhow!("invalid format"))?
+            .to_string();
+        let state = fields.next().ok_or_else(|| anyhow!("invalid format"))?;
+        match state {
+            "running" => active.push(name),
+            "shut" => inactive.push(name),
+            _ => {}
+        }
+    }
+    Ok(State {
+        active,
+        inactive,
+        stale: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_virsh_list_all() {
+        let state = parse(include_str!("../tests/fixtures/virsh-list-all.txt")).unwrap();
+        assert_eq!(state.active, ["win11", "builder"]);
+        // paused VMs are neither
+        assert_eq!(state.inactive, ["debian"]);
+        assert!(!state.stale);
+    }
+
+    #[test]
+    fn detail_lists_every_vm_with_its_state() {
+        let state = parse(include_str!("../tests/fixtures/virsh-list-all.txt")).unwrap();
+        assert_eq!(
+            state.detail().unwrap(),
+            "win11: running\nbuilder: running\ndebian: shut off"
+        );
+        let none = parse(" Id   Name   State\n--------------------\n").unwrap();
+        assert_eq!(none.detail().unwrap(), "No VMs");
+    }
+
+    struct Hanging;
+
+    impl CommandSource for Hanging {
+        fn output(&self, program: &str, _args: &[&str]) -> Result<String> {
+            Err(process::Timeout {
+                program: program.to_string(),
+                timeout: Duration::from_secs(1),
+            }
+            .into())
+        }
+    }
+
+    #[test]
+    fn shows_the_cached_listing_on_a_timeout() {
+        let _env = crate::snapshots::test_env();
+        let timeout = Duration::from_secs(1);
+        let fixtures = crate::snapshots::FixtureSource;
+        list(true, timeout, &fixtures).unwrap();
+        // the full listing doesn't stand in for the running-only one
+        list(false, timeout, &fixtures).unwrap();
+        let cached = list(true, timeout, &Hanging).unwrap();
+        assert_eq!(cached.active, ["win11", "builder"]);
+        assert!(cached.inactive.is_empty());
+        assert!(cached.stale);
+
+        // a cache that can't be read leaves the timeout as the error
+        let path = state::dir().unwrap().join(CACHE_RUNNING);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        let err = list(true, timeout, &Hanging).unwrap_err();
+        std::fs::remove_dir(&path).unwrap();
+        assert!(err.is::<process::Timeout>());
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("no cached VMs either (reading "),
+            "{}",
+            message
+        );
+        assert!(
+            message.ends_with(": `virsh` timed out after 1.0s"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn parses_virsh_list_names() {
+        let names = parse_names(include_str!("../tests/fixtures/virsh-list-running.txt"));
+        assert_eq!(names, ["win11", "builder"]);
+        assert!(parse_names("\n").is_empty());
+    }
+
+    #[test]
+    fn rejects_rows_without_a_state() {
+        assert!(parse(" Id   Name   State\n-----\n 1    win11\n").is_err());
+    }
+}
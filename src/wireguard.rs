@@ -0,0 +1,165 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{json, process::CommandSource, Color, Span, Style, Widget, TABULAR_NUMS};
+
+/// A peer counts as connected if its last handshake is at most this old. WireGuard
+/// rehandshakes every two minutes while traffic flows, so this allows one to be late.
+pub const ACTIVE_WITHIN: Duration = Duration::from_secs(180);
+
+pub struct Peers {
+    pub interface: String,
+    pub total: usize,
+    /// Peers with a handshake within [`ACTIVE_WITHIN`]
+    pub active: usize,
+}
+
+impl Peers {
+    fn color(&self, style: &Style) -> Color {
+        if self.active == 0 {
+            style.theme().red()
+        } else {
+            style.palette().primary
+        }
+    }
+}
+
+impl Widget for Peers {
+    fn render(&self, style: &Style) -> String {
+        format!(
+            "{icon} {active}{total}",
+            icon = style.icon("wireguard", "vpn"),
+            active = pango!(
+                self.active,
+                color = style.color("wireguard.active", self.color(style)),
+                weight = "ultrabold",
+                font_features = TABULAR_NUMS,
+                font_size = "110%",
+            ),
+            total = pango!(
+                format!("/{}", self.total),
+                color = style.color("wireguard.total", style.palette().secondary)
+            ),
+        )
+    }
+
+    fn to_json(&self) -> json::Object {
+        json::Object::new()
+            .field("interface", self.interface.as_str())
+            .field("peers", self.total)
+            .field("active", self.active)
+    }
+
+    fn short(&self, _style: &Style) -> Option<String> {
+        Some(self.active.to_string())
+    }
+
+    fn urgency_color(&self, style: &Style) -> Option<Color> {
+        (self.active == 0).then(|| style.theme().red())
+    }
+}
+
+/// The peers of `interface` from `wg show`, which needs to run as root or with
+/// `CAP_NET_ADMIN`
+pub fn peers(interface: &str, source: &dyn CommandSource) -> Result<Peers> {
+    let total = parse_peers(&source.output("wg", &["show", interface, "peers"])?);
+    let handshakes =
+        parse_handshakes(&source.output("wg", &["show", interface, "latest-handshakes"])?)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock is before 1970")?;
+    Ok(Peers {
+        interface: interface.to_string(),
+        total,
+        active: count_active(&handshakes, now),
+    })
+}
+
+/// The number of peers in the output of `wg show <interface> peers`, one public key per line
+pub fn parse_peers(output: &str) -> usize {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .count()
+}
+
+/// Parse the output of `wg show <interface> latest-handshakes` into the time of each peer's
+/// last handshake since the epoch, `None` for peers that never completed one
+pub fn parse_handshakes(output: &str) -> Result<Vec<Option<Duration>>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            // `<public key>\t<unix seconds>`, where 0 means no handshake yet
+            let secs = line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("invalid `wg` line `{}`", line))?;
+            Ok((secs > 0).then(|| Duration::from_secs(secs)))
+        })
+        .collect()
+}
+
+/// How many of the handshakes happened within [`ACTIVE_WITHIN`] of `now`
+pub fn count_active(handshakes: &[Option<Duration>], now: Duration) -> usize {
+    handshakes
+        .iter()
+        .flatten()
+        .filter(|at| now.saturating_sub(**at) <= ACTIVE_WITHIN)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_latest_handshakes() {
+        let secs = |secs| Some(Duration::from_secs(secs));
+        // (output, handshakes)
+        let cases = [
+            ("", Some(vec![])),
+            ("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t1700000000\n", Some(vec![secs(1_700_000_000)])),
+            // 0 is a peer that never completed a handshake
+            (
+                "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t1700000000\nTrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=\t0\n\n",
+                Some(vec![secs(1_700_000_000), None]),
+            ),
+            ("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n", None),
+            ("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\tyesterday\n", None),
+            ("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t-5\n", None),
+        ];
+        for (output, handshakes) in cases {
+            assert_eq!(parse_handshakes(output).ok(), handshakes, "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn counts_handshakes_within_three_minutes() {
+        let now = Duration::from_secs(1_700_000_000);
+        let ago = |secs| Some(now - Duration::from_secs(secs));
+        // (handshakes, active)
+        let cases = [
+            (vec![], 0),
+            (vec![None], 0),
+            (vec![ago(0)], 1),
+            (vec![ago(180)], 1),
+            (vec![ago(181)], 0),
+            (vec![ago(10), None, ago(179), ago(3600)], 2),
+            // from a clock that is ahead of ours
+            (vec![Some(now + Duration::from_secs(5))], 1),
+        ];
+        for (handshakes, active) in cases {
+            assert_eq!(count_active(&handshakes, now), active, "{:?}", handshakes);
+        }
+    }
+
+    #[test]
+    fn counts_the_peers() {
+        let output = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\nTrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=\n";
+        assert_eq!(parse_peers(output), 2);
+        assert_eq!(parse_peers(""), 0);
+    }
+}
@@ -0,0 +1,192 @@
+//! What scripts get from the binary: the exit codes `main` documents (2 for usage and
+//! configuration errors, 0 with a ⚠ block for fetch errors, and 1 for fetch errors under
+//! `--strict`), the pango a piped stdout gets, the `list` of widgets and what `doctor` and the
+//! widgets show when none of the tools are installed
+
+use std::process::{Command, Output};
+
+fn command(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_i3widgets"));
+    command
+        .args(args)
+        // keep the user's config out of it
+        .env("XDG_CONFIG_HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env("XDG_STATE_HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env_remove("I3WIDGETS_LOG");
+    command
+}
+
+fn i3widgets(args: &[&str]) -> Output {
+    command(args).output().unwrap()
+}
+
+/// Run with `PATH` pointing at an empty directory, so none of the external tools are installed
+fn without_tools(args: &[&str]) -> Output {
+    let empty = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("empty-path");
+    std::fs::create_dir_all(&empty).unwrap();
+    command(args)
+        .env("PATH", empty)
+        // the dark theme's dim color
+        .env_remove("GTK_THEME")
+        .output()
+        .unwrap()
+}
+
+/// The tools in `process::KNOWN_TOOLS`
+const KNOWN_TOOLS: &[&str] = &[
+    "upower",
+    "powerprofilesctl",
+    "free",
+    "df",
+    "pactl",
+    "brightnessctl",
+    "virsh",
+    "nvidia-smi",
+    "aplay",
+    "loginctl",
+    "wg",
+    "notify-send",
+];
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let cases: &[(&[&str], &str)] = &[
+        (&["memory", "--no-such-flag"], "--no-such-flag"),
+        (&["--output", "html", "memory"], "html"),
+        (&["battery"], "--device-path is required"),
+        (&["--fg", "not-a-color", "memory"], "not-a-color"),
+        (&["--cmd-timeout=-1", "memory"], "expected seconds from 0"),
+        (&["--cmd-timeout=nan", "memory"], "expected seconds from 0"),
+        (&["--interval=0", "memory"], "expected seconds from 0.1"),
+        (&["--interval=-1", "memory"], "expected seconds from 0.1"),
+        #[cfg(feature = "time")]
+        (
+            &["countdown", "--until", "garbage"],
+            "neither RFC3339 nor HH:MM",
+        ),
+        #[cfg(not(feature = "time"))]
+        (&["time"], "built without feature `time`"),
+        #[cfg(not(feature = "time"))]
+        (
+            &["countdown", "--until", "18:30"],
+            "built without feature `time`",
+        ),
+    ];
+    for (args, message) in cases {
+        let output = i3widgets(args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(2), "{:?}: {}", args, stderr);
+        assert!(stderr.contains(message), "{:?}: {}", args, stderr);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+    }
+}
+
+#[test]
+fn a_missing_binary_renders_a_block() {
+    let args = [
+        "--tool-path",
+        "pactl=/nonexistent/pactl",
+        "--output",
+        "i3blocks",
+        "sink-volume",
+    ];
+    let output = i3widgets(&args);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!String::from_utf8_lossy(&output.stdout).trim().is_empty());
+
+    let output = i3widgets(&[&["--strict"][..], &args].concat());
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("/nonexistent/pactl"));
+}
+
+#[test]
+fn doctor_marks_every_tool_missing_without_a_path() {
+    let output = without_tools(&["doctor"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows = stdout.lines().skip(1).collect::<Vec<_>>();
+    assert_eq!(rows.len(), KNOWN_TOOLS.len(), "{}", stdout);
+    for (row, tool) in rows.iter().zip(KNOWN_TOOLS) {
+        let columns = row.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(columns[..2], [*tool, "missing"], "{}", stdout);
+    }
+}
+
+#[test]
+fn a_tool_missing_from_the_path_renders_n_a() {
+    let output = without_tools(&["--output", "pango", "sink-volume"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.ends_with(" <span color=\"#a9b1d6\">n/a</span>\n"),
+        "{:?}",
+        stdout
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`pactl` is not installed"));
+
+    let output = without_tools(&["--quiet", "--output", "pango", "sink-volume"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "\n");
+}
+
+#[test]
+fn piped_output_defaults_to_pango() {
+    let output = i3widgets(&["hostname"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains('\x1b'), "{:?}", stdout);
+    assert!(stdout.contains("<span"), "{:?}", stdout);
+}
+
+#[test]
+fn lists_the_widgets_as_json() {
+    let output = i3widgets(&["--output", "json", "list"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("[{\"name\":\"battery\",\"description\":\""),
+        "{}",
+        stdout
+    );
+    assert!(stdout.trim_end().ends_with("}]"), "{}", stdout);
+    let plain = i3widgets(&["list"]);
+    let count = String::from_utf8_lossy(&plain.stdout).lines().count();
+    assert_eq!(stdout.matches("{\"name\":").count(), count);
+}
+
+#[test]
+fn a_failing_tool_renders_a_block() {
+    let wg = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("failing-wg");
+    std::fs::write(
+        &wg,
+        "#!/bin/sh\necho 'Unable to access interface: Operation not permitted' >&2\nexit 1\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&wg, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let tool_path = format!("wg={}", wg.display());
+    let args = [
+        "--tool-path",
+        &tool_path,
+        "--output",
+        "json",
+        "wireguard",
+        "--interface",
+        "wg0",
+    ];
+    let output = i3widgets(&args);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // the error, not zero peers
+    assert!(
+        stdout.starts_with("{\"error\":\"`wg` exited with 1: Unable to access interface"),
+        "{}",
+        stdout
+    );
+
+    let output = i3widgets(&[&["--strict"][..], &args].concat());
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("`wg` exited with 1: Unable to access interface: Operation not permitted"));
+}
@@ -0,0 +1,47 @@
+//! `--log-level` output goes to stderr only, so a bar reading stdout never sees a log line
+
+use std::{os::unix::fs::PermissionsExt, path::Path, process::Command};
+
+#[test]
+fn logs_go_to_stderr_and_stdout_has_only_the_block() {
+    // an `aplay` printing the fixture, so there is tool output to trace
+    let aplay = Path::new(env!("CARGO_TARGET_TMPDIR")).join("logging-aplay");
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/aplay-l.txt");
+    std::fs::write(&aplay, format!("#!/bin/sh\ncat '{}'\n", fixture.display())).unwrap();
+    std::fs::set_permissions(&aplay, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = |level: &str| {
+        Command::new(env!("CARGO_BIN_EXE_i3widgets"))
+            .args(["--log-level", level, "--output", "pango", "--tool-path"])
+            .arg(format!("aplay={}", aplay.display()))
+            .arg("sound-cards")
+            // keep the user's config out of it
+            .env("XDG_CONFIG_HOME", env!("CARGO_TARGET_TMPDIR"))
+            .env("XDG_STATE_HOME", env!("CARGO_TARGET_TMPDIR"))
+            .env_remove("I3WIDGETS_LOG")
+            .output()
+            .unwrap()
+    };
+
+    let quiet = run("error");
+    assert_eq!(quiet.status.code(), Some(0));
+    assert!(quiet.stderr.is_empty());
+    let block = String::from_utf8(quiet.stdout).unwrap();
+    assert_eq!(block.lines().count(), 1, "{}", block);
+
+    let traced = run("trace");
+    assert_eq!(traced.status.code(), Some(0));
+    assert_eq!(String::from_utf8(traced.stdout).unwrap(), block);
+    let stderr = String::from_utf8(traced.stderr).unwrap();
+    for line in [
+        "trace process: `aplay` printed:",
+        "debug sound-cards: fetched in ",
+    ] {
+        assert!(stderr.contains(line), "{:?} not in\n{}", line, stderr);
+    }
+    // what the tool printed is traced as is
+    assert!(
+        stderr.contains(&std::fs::read_to_string(&fixture).unwrap()),
+        "{}",
+        stderr
+    );
+}
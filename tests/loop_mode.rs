@@ -0,0 +1,166 @@
+//! `--interval` as i3blocks runs it: a line per tick as soon as it's rendered, a refresh on
+//! SIGUSR1, hiding and showing on SIGUSR2, a clean exit on SIGTERM and SIGINT, a quiet one
+//! when the bar closes the pipe, and a reload when the config is saved
+
+use std::{
+    io::{BufRead, BufReader},
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+fn i3widgets(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_i3widgets"));
+    command
+        .args(args)
+        // keep the user's config out of it
+        .env("XDG_CONFIG_HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env("XDG_STATE_HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env_remove("I3WIDGETS_LOG");
+    command
+}
+
+fn spawn(args: &[&str]) -> Child {
+    i3widgets(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+/// Wait for `child` to exit, killing it if it's still running after a few seconds
+fn exit_status(child: &mut Child) -> ExitStatus {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    child.kill().unwrap();
+    panic!("still running after 5s");
+}
+
+/// Send `signal` to the still running `child`
+fn kill(child: &Child, signal: libc::c_int) {
+    // SAFETY: the child hasn't been waited on, so its pid is still ours
+    assert_eq!(unsafe { libc::kill(child.id() as libc::pid_t, signal) }, 0);
+}
+
+#[test]
+fn prints_a_line_per_tick_until_signaled() {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let mut child = spawn(&["--interval", "0.1", "--output", "pango", "hostname"]);
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        // each line is read while the process is still running, so each tick is flushed
+        let first = lines.next().unwrap().unwrap();
+        assert!(first.contains("<span"), "{:?}", first);
+        for _ in 0..2 {
+            assert_eq!(lines.next().unwrap().unwrap(), first);
+        }
+        assert!(child.try_wait().unwrap().is_none());
+        kill(&child, signal);
+        let status = exit_status(&mut child);
+        assert_eq!(status.code(), Some(0), "signal {}", signal);
+    }
+}
+
+#[test]
+fn exits_quietly_when_the_pipe_closes() {
+    let mut child = spawn(&["--interval", "0.1", "--output", "pango", "hostname"]);
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    drop(stdout);
+    let status = exit_status(&mut child);
+    let mut stderr = String::new();
+    std::io::Read::read_to_string(child.stderr.as_mut().unwrap(), &mut stderr).unwrap();
+    assert_eq!(status.code(), Some(0), "{}", stderr);
+    assert!(stderr.is_empty(), "{}", stderr);
+}
+
+#[test]
+fn a_failed_fetch_shows_the_same_block_as_a_single_run() {
+    // runs, but prints nothing `pactl` would
+    let args = ["--tool-path", "pactl=/bin/false", "--output", "i3blocks"];
+    let once = i3widgets(&args).arg("sink-volume").output().unwrap();
+    let once = String::from_utf8(once.stdout).unwrap();
+    assert!(once.contains('⚠'), "{}", once);
+
+    let mut child = spawn(&[&args[..], &["--interval", "0.1", "sink-volume"]].concat());
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let tick = (0..once.lines().count())
+        .map(|_| lines.next().unwrap().unwrap() + "\n")
+        .collect::<String>();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(tick, once);
+}
+
+#[test]
+fn sigusr1_prints_a_line_right_away() {
+    let mut child = spawn(&["--interval", "60", "--output", "pango", "hostname"]);
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let first = lines.next().unwrap().unwrap();
+    let start = Instant::now();
+    kill(&child, libc::SIGUSR1);
+    assert_eq!(lines.next().unwrap().unwrap(), first);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn sigusr2_hides_and_shows_the_widget() {
+    let mut child = spawn(&["--interval", "60", "--output", "pango", "hostname"]);
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let first = lines.next().unwrap().unwrap();
+    assert!(!first.is_empty());
+    let start = Instant::now();
+    kill(&child, libc::SIGUSR2);
+    assert_eq!(lines.next().unwrap().unwrap(), "");
+    kill(&child, libc::SIGUSR2);
+    assert_eq!(lines.next().unwrap().unwrap(), first);
+    // both came right away instead of after the interval
+    assert!(start.elapsed() < Duration::from_secs(5));
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn a_saved_config_applies_on_the_next_tick() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("reload");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    // editors write a new file and rename it over the old one
+    let save = |text: &str| {
+        std::fs::write(dir.join("config.toml.tmp"), text).unwrap();
+        std::fs::rename(dir.join("config.toml.tmp"), &config).unwrap();
+    };
+    save("[format]\nmemory = \"before\"\n");
+    let mut child = spawn(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--interval",
+        "60",
+        "--output",
+        "pango",
+        "memory",
+    ]);
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert!(lines.next().unwrap().unwrap().contains("before"));
+    let start = Instant::now();
+    save("[format]\nmemory = \"after\"\n");
+    assert!(lines.next().unwrap().unwrap().contains("after"));
+    // a broken config is shown once and the last good one carries on
+    save("[format]\nmemory = \"{nope}\"\n");
+    assert!(lines.next().unwrap().unwrap().contains("config error"));
+    kill(&child, libc::SIGUSR1);
+    assert!(lines.next().unwrap().unwrap().contains("after"));
+    // each came right away instead of after the interval
+    assert!(start.elapsed() < Duration::from_secs(5));
+    kill(&child, libc::SIGTERM);
+    assert_eq!(exit_status(&mut child).code(), Some(0));
+    let mut stderr = String::new();
+    std::io::Read::read_to_string(child.stderr.as_mut().unwrap(), &mut stderr).unwrap();
+    assert!(stderr.contains("keeping the old config"), "{}", stderr);
+}